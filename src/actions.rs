@@ -0,0 +1,319 @@
+// src/actions.rs
+use crate::core::{AppCore, CommandStep, CoreError, StepStatus};
+use anyhow::Context;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+// --- Handler trait ---
+// One implementation per plan `action_type`. Handlers get the step as planned and the running
+// AppCore, so they can substitute placeholders and read/store discovered values.
+#[async_trait]
+pub trait ActionHandler: Send + Sync {
+    async fn handle(&self, step: &CommandStep, ctx: &mut AppCore) -> Result<ActionOutput, CoreError>;
+}
+
+// What a step did (recorded in the history sent back to the model) and what it produced
+#[derive(Debug, Clone)]
+pub struct ActionOutput {
+    pub performed: String,
+    pub output: String,
+    pub status: StepStatus,
+}
+
+impl ActionOutput {
+    pub fn succeeded(performed: impl Into<String>, output: impl Into<String>) -> Self {
+        ActionOutput { performed: performed.into(), output: output.into(), status: StepStatus::Succeeded }
+    }
+}
+
+// --- ActionRegistry ---
+// action_type -> handler; the built-in types are registered the same way as custom ones
+#[derive(Clone)]
+pub struct ActionRegistry {
+    handlers: HashMap<String, Arc<dyn ActionHandler>>,
+}
+
+impl ActionRegistry {
+    pub fn empty() -> Self {
+        ActionRegistry { handlers: HashMap::new() }
+    }
+
+    // Registering an existing type replaces its handler (built-ins included)
+    pub fn register(&mut self, action_type: &str, handler: Arc<dyn ActionHandler>) {
+        self.handlers.insert(action_type.to_lowercase(), handler);
+    }
+
+    pub fn get(&self, action_type: &str) -> Option<Arc<dyn ActionHandler>> {
+        self.handlers.get(&action_type.to_lowercase()).cloned()
+    }
+
+    pub fn action_types(&self) -> Vec<&str> {
+        let mut types: Vec<&str> = self.handlers.keys().map(String::as_str).collect();
+        types.sort_unstable();
+        types
+    }
+}
+
+impl Default for ActionRegistry {
+    fn default() -> Self {
+        let mut registry = ActionRegistry::empty();
+        registry.register("command", Arc::new(CommandAction));
+        registry.register("note", Arc::new(NoteAction));
+        registry.register("pause", Arc::new(PauseAction));
+        registry.register("write_file", Arc::new(WriteFileAction));
+        registry.register("capture", Arc::new(CaptureAction));
+        registry.register("function", Arc::new(FunctionAction));
+        registry
+    }
+}
+
+// --- Built-in: command ---
+// Substitution, allowlist/scope gates, execution and output parsing
+struct CommandAction;
+
+#[async_trait]
+impl ActionHandler for CommandAction {
+    async fn handle(&self, step: &CommandStep, ctx: &mut AppCore) -> Result<ActionOutput, CoreError> {
+        ctx.run_command_step(step).await
+    }
+}
+
+// --- Built-in: note ---
+// Records the model's observation (command text, else purpose) without running anything
+struct NoteAction;
+
+#[async_trait]
+impl ActionHandler for NoteAction {
+    async fn handle(&self, step: &CommandStep, ctx: &mut AppCore) -> Result<ActionOutput, CoreError> {
+        let text = step.command.as_deref().or(step.purpose.as_deref()).unwrap_or_default();
+        let note = ctx.substitute_placeholders(step.step, text).await?;
+        println!("NOTE: {}", note);
+        Ok(ActionOutput::succeeded("note", note))
+    }
+}
+
+// --- Built-in: pause ---
+// Waits for Enter before the next step (e.g. to start a listener by hand); no-op without a terminal
+struct PauseAction;
+
+#[async_trait]
+impl ActionHandler for PauseAction {
+    async fn handle(&self, step: &CommandStep, _ctx: &mut AppCore) -> Result<ActionOutput, CoreError> {
+        let reason = step.purpose.as_deref().unwrap_or("Paused by plan");
+        if !std::io::stdin().is_terminal() {
+            println!("INFO: Not waiting at pause step {} (stdin is not a terminal).", step.step);
+            return Ok(ActionOutput { performed: "pause".to_string(), output: "Pause skipped (no terminal)".to_string(), status: StepStatus::Skipped });
+        }
+        println!("PAUSED: {} -- press Enter to continue.", reason);
+        // Nobody at the terminal: stop rather than carry on with whatever the pause was guarding
+        if crate::prompt::read_answer().await?.is_none() {
+            return Err(CoreError::Other(anyhow::anyhow!("No one resumed pause step {} before --input-timeout; stopping the run", step.step)));
+        }
+        Ok(ActionOutput::succeeded("pause", "Resumed by user"))
+    }
+}
+
+// --- Built-in: write_file ---
+// Writes options.content (else the command text) to options.path, after substitution. The path is
+// the model's, so it is confined to the artifact directory (see confined_write_path) and an
+// existing file is only replaced when the step sets options.overwrite = "true".
+struct WriteFileAction;
+
+#[async_trait]
+impl ActionHandler for WriteFileAction {
+    async fn handle(&self, step: &CommandStep, ctx: &mut AppCore) -> Result<ActionOutput, CoreError> {
+        let path_template = step
+            .options
+            .get("path")
+            .ok_or_else(|| CoreError::InvalidPlan(format!("write_file step {} has no options.path", step.step)))?;
+        let content_template = step.options.get("content").or(step.command.as_ref()).map(String::as_str).unwrap_or_default();
+        let requested = ctx.substitute_placeholders(step.step, path_template).await?;
+        let content = ctx.substitute_placeholders(step.step, content_template).await?;
+        let base = ctx.artifact_dir();
+        let path = match confined_write_path(&base, &requested) {
+            Ok(path) => path,
+            Err(reason) => {
+                println!("BLOCKED: Step {} write_file to '{}': {}", step.step, requested, reason);
+                let output = CoreError::Blocked { step: step.step, reason }.to_string();
+                return Ok(ActionOutput { performed: format!("write_file {}", requested), output, status: StepStatus::Blocked });
+            }
+        };
+        let overwrite = step.options.get("overwrite").is_some_and(|v| v.trim().eq_ignore_ascii_case("true"));
+        let mut open = std::fs::OpenOptions::new();
+        open.write(true);
+        if overwrite {
+            open.create(true).truncate(true);
+        } else {
+            open.create_new(true);
+        }
+        let mut file = open.open(&path).context(format!(
+            "Failed to write file: {}{}",
+            path.display(),
+            if overwrite { "" } else { " (set options.overwrite = \"true\" to replace an existing file)" }
+        ))?;
+        file.write_all(content.as_bytes()).context(format!("Failed to write file: {}", path.display()))?;
+        println!("Wrote {} bytes to {}", content.len(), path.display());
+        Ok(ActionOutput::succeeded(format!("write_file {}", path.display()), format!("Wrote {} bytes to {}", content.len(), path.display())))
+    }
+}
+
+// The file write_file may write for `requested`: inside `base` (the engagement directory, else the
+// working directory), with no `..`, and not through a symlink that leads out of it. Creates the
+// missing parent directories inside `base`.
+fn confined_write_path(base: &Path, requested: &str) -> Result<PathBuf, String> {
+    let requested = Path::new(requested.trim());
+    if requested.components().any(|c| matches!(c, Component::ParentDir)) {
+        return Err("'..' is not allowed in write_file paths".to_string());
+    }
+    let canonical_base = base.canonicalize().map_err(|e| format!("cannot resolve {}: {}", base.display(), e))?;
+    let candidate = if requested.is_absolute() { requested.to_path_buf() } else { base.join(requested) };
+    if !candidate.starts_with(base) && !candidate.starts_with(&canonical_base) {
+        return Err(format!("outside {}", canonical_base.display()));
+    }
+    let (Some(parent), Some(name)) = (candidate.parent(), candidate.file_name()) else {
+        return Err("not a file path".to_string());
+    };
+    std::fs::create_dir_all(parent).map_err(|e| format!("cannot create {}: {}", parent.display(), e))?;
+    let canonical_parent = parent.canonicalize().map_err(|e| format!("cannot resolve {}: {}", parent.display(), e))?;
+    if !canonical_parent.starts_with(&canonical_base) {
+        return Err(format!("outside {}", canonical_base.display()));
+    }
+    let path = canonical_parent.join(name);
+    if path.symlink_metadata().is_ok_and(|meta| meta.file_type().is_symlink()) {
+        return Err("the path is a symlink".to_string());
+    }
+    Ok(path)
+}
+
+// --- Built-in: capture ---
+// Starts a background packet capture (options.interface, options.filter) that runs until
+// options.until_step has finished, or the plan ends. The pcap goes to options.path, else the
+// engagement (or working) directory; its path is stored as {pcap_path} and {capture_<step>_pcap}.
+struct CaptureAction;
+
+#[async_trait]
+impl ActionHandler for CaptureAction {
+    async fn handle(&self, step: &CommandStep, ctx: &mut AppCore) -> Result<ActionOutput, CoreError> {
+        let until_step = match step.options.get("until_step") {
+            Some(value) => Some(value.trim().parse::<u32>().map_err(|_| {
+                CoreError::InvalidPlan(format!("capture step {} has a non-numeric options.until_step '{}'", step.step, value))
+            })?),
+            None => None,
+        };
+        let interface = match step.options.get("interface") {
+            Some(value) => Some(ctx.substitute_placeholders(step.step, value).await?),
+            None => None,
+        };
+        let filter = match step.options.get("filter").or(step.command.as_ref()).filter(|f| !f.trim().is_empty()) {
+            Some(value) => Some(ctx.substitute_placeholders(step.step, value).await?),
+            None => None,
+        };
+        let path = match step.options.get("path") {
+            Some(value) => PathBuf::from(ctx.substitute_placeholders(step.step, value).await?),
+            None => ctx.artifact_path(&format!("hacker-rs-capture-step{}-{}.pcap", step.step, std::process::id())),
+        };
+        let path = std::path::absolute(&path).unwrap_or(path);
+
+        let capture = match ctx.start_capture(step.step, interface.as_deref(), filter.as_deref(), &path, until_step).await {
+            Ok(capture) => capture,
+            // Gates stop the step, not the plan
+            Err(CoreError::Blocked { step: number, reason }) => {
+                println!("BLOCKED: Step {} capture: {}", number, reason);
+                let output = CoreError::Blocked { step: number, reason }.to_string();
+                return Ok(ActionOutput { performed: format!("capture {}", filter.unwrap_or_default()).trim_end().to_string(), output, status: StepStatus::Blocked });
+            }
+            Err(e) => return Err(e),
+        };
+        let until = until_step.map_or_else(|| "the end of the plan".to_string(), |s| format!("step {}", s));
+        let output = format!("Capturing {} to {} until {}", capture, path.display(), until);
+        println!("INFO: {}", output);
+        Ok(ActionOutput::succeeded(format!("capture {}", capture), output))
+    }
+}
+
+// --- Built-in: function ---
+// Structured lookups the model can call by name (the step's command) instead of planning a shell
+// command and having its output scraped. Arguments come from options (placeholders allowed); the
+// result is stored as discovered values and becomes the step output the model sees next time.
+struct FunctionAction;
+
+// Names accepted in a function step's "command"
+pub const PLAN_FUNCTIONS: &[&str] = &["get_open_ports", "get_gateway", "resolve_host"];
+
+#[async_trait]
+impl ActionHandler for FunctionAction {
+    async fn handle(&self, step: &CommandStep, ctx: &mut AppCore) -> Result<ActionOutput, CoreError> {
+        let name = step.command.as_deref().map(str::trim).unwrap_or_default();
+        let mut args = std::collections::BTreeMap::new();
+        for (key, value) in &step.options {
+            args.insert(key.to_lowercase(), ctx.substitute_placeholders(step.step, value).await?);
+        }
+        let performed = format!("function {}({})", name, args.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(", "));
+
+        let output = match name {
+            // Ports the parsers already recorded for that host (default {target_ip}), as "22,80"; another
+            // host's open_ports must never stand in for it, so an unscanned host is "unknown"
+            "get_open_ports" => {
+                let host = match args.get("host") {
+                    Some(host) => host.clone(),
+                    None => ctx.substitute_placeholders(step.step, "{target_ip}").await?,
+                };
+                let key = format!("host_{}_ports", host.replace('.', "_"));
+                match ctx.discovered_values().get(&key) {
+                    Some(ports) => format!("Open ports on {}: {}", host, ports),
+                    None => format!("Open ports on {}: unknown (not scanned yet)", host),
+                }
+            }
+            "get_gateway" => {
+                let gateway = tokio::task::spawn_blocking(crate::network::get_default_gateway)
+                    .await
+                    .context("Gateway lookup task failed")??;
+                match gateway {
+                    Some(gateway) => {
+                        ctx.store_value("default_gateway", gateway.clone());
+                        format!("Default gateway: {}", gateway)
+                    }
+                    None => "No default gateway found".to_string(),
+                }
+            }
+            // Addresses for options.host; the first one is stored as {resolved_ip}
+            "resolve_host" => {
+                let host = args
+                    .get("host")
+                    .cloned()
+                    .ok_or_else(|| CoreError::InvalidPlan(format!("function step {} (resolve_host) has no options.host", step.step)))?;
+                let lookup = host.clone();
+                let addresses: Vec<String> = tokio::task::spawn_blocking(move || {
+                    use std::net::ToSocketAddrs;
+                    (lookup.as_str(), 0).to_socket_addrs().map(|addrs| addrs.map(|a| a.ip().to_string()).collect())
+                })
+                .await
+                .context("Host lookup task failed")?
+                .context(format!("Could not resolve {}", host))?;
+                let mut unique: Vec<String> = Vec::new();
+                for address in addresses {
+                    if !unique.contains(&address) {
+                        unique.push(address);
+                    }
+                }
+                if let Some(first) = unique.first() {
+                    ctx.store_value("resolved_ip", first.clone());
+                }
+                format!("{} resolves to {}", host, if unique.is_empty() { "nothing".to_string() } else { unique.join(", ") })
+            }
+            _ => {
+                return Err(CoreError::InvalidPlan(format!(
+                    "function step {} calls unknown function '{}' (available: {})",
+                    step.step,
+                    name,
+                    PLAN_FUNCTIONS.join(", ")
+                )))
+            }
+        };
+        println!("{}", output);
+        Ok(ActionOutput::succeeded(performed, output))
+    }
+}
@@ -0,0 +1,40 @@
+// src/audit.rs
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const AUDIT_LOG_FILENAME: &str = "audit.log";
+
+// --- AuditLog ---
+// Append-only log of security-relevant events (acknowledgments, etc.)
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    // Log lives next to the config file unless a directory is given explicitly
+    pub fn in_dir(dir: &Path) -> Self {
+        AuditLog { path: dir.join(AUDIT_LOG_FILENAME) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Appends "<unix-seconds> <user> <event>" as one line
+    pub fn record(&self, event: &str) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_string());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("Failed to open audit log: {}", self.path.display()))?;
+        writeln!(file, "{} {} {}", timestamp, user, event.replace('\n', " "))
+            .context("Failed to write audit log entry")?;
+        Ok(())
+    }
+}
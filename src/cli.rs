@@ -1,15 +1,178 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "hacker-rs")]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    // None for a bare `hacker-rs "<query>"`; see take_command
     #[command(subcommand)]
-    pub command: Commands,
+    pub command: Option<Commands>,
 
+    /// Query to run when no subcommand is given (`hacker-rs "scan 10.0.0.1"` is `hacker-rs run "scan 10.0.0.1"`)
+    #[arg(value_name = "QUERY")]
+    pub query: Option<String>,
+
+    /// Config file layered over the user and ./hacker-rs.toml configs; repeatable, later wins
     #[arg(short, long)]
-    pub config: Option<PathBuf>,
+    pub config: Vec<PathBuf>,
+
+    /// Directory for config.toml, system_prompt.txt, profiles, sessions and the audit log
+    #[arg(long, value_name = "DIR")]
+    pub config_dir: Option<PathBuf>,
+
+    /// Named profile from <config dir>/profiles/<name>/ (config overlay, system prompt, safety and scope)
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Don't pre-parse IPs/CIDRs from the query; only use values the model discovers
+    #[arg(long)]
+    pub no_preparse: bool,
+
+    /// Hosts/CIDRs that must never be targeted (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Ports that must never be targeted (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    pub exclude_ports: Vec<String>,
+
+    /// Target for queries that don't name an IP/CIDR (overrides default_target in the config)
+    #[arg(long)]
+    pub target: Option<String>,
+
+    /// File of targets (IP, CIDR or hostname per line) for the {targets} placeholder and per-target steps
+    #[arg(long, value_name = "PATH")]
+    pub targets_file: Option<PathBuf>,
+
+    /// On Windows, relaunch privileged steps through a UAC prompt when not already elevated
+    #[arg(long)]
+    pub elevate: bool,
+
+    /// On Linux, run commands that need root with `sudo -n` when not already root
+    #[arg(long)]
+    pub auto_sudo: bool,
+
+    /// Run plan steps on a remote host over ssh (user@host or an ssh_config alias)
+    #[arg(long)]
+    pub remote: Option<String>,
+
+    /// Identity file for --remote
+    #[arg(long, requires = "remote")]
+    pub ssh_key: Option<PathBuf>,
+
+    /// SSH port for --remote
+    #[arg(long, requires = "remote")]
+    pub ssh_port: Option<u16>,
+
+    /// Acknowledge the legal warning non-interactively
+    #[arg(long)]
+    pub i_am_authorized: bool,
+
+    /// Connect local commands to this terminal's stdin so tools that prompt can be answered
+    #[arg(long)]
+    pub attach_stdin: bool,
+
+    /// Print command output live as it arrives (same as advanced.stream_output = true)
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Keep stderr of successful commands in their output (same as advanced.include_stderr = true)
+    #[arg(long)]
+    pub include_stderr: bool,
+
+    /// Show at most this many lines of each step's output (first and last halves); 0 = no limit
+    #[arg(long, value_name = "N", default_value_t = hacker_rs::core::DEFAULT_MAX_DISPLAY_LINES)]
+    pub max_display_lines: usize,
+
+    /// Resume (and keep updating) a named session: model conversation, history and discovered values
+    #[arg(long, value_name = "NAME")]
+    pub session: Option<String>,
+
+    /// Start with the discovered values of this earlier session (not its model conversation or history)
+    #[arg(long, value_name = "SESSION")]
+    pub since: Option<String>,
+
+    /// Keep this job's audit log, reports, plans, pcaps and session in ~/hacker-rs/<NAME>-<date>/ (reused by later runs)
+    #[arg(long, value_name = "NAME")]
+    pub engagement: Option<String>,
+
+    /// Print the exact system and user prompts sent to the model (to stderr)
+    #[arg(long)]
+    pub debug_prompt: bool,
+
+    /// Known value for a placeholder, e.g. --set default_gateway=10.0.0.1 (repeatable; discovery may overwrite)
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub set_values: Vec<(String, String)>,
+
+    /// Like --set, but discovery never overwrites the value (repeatable)
+    #[arg(long = "pin", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub pin_values: Vec<(String, String)>,
+
+    /// Run only plan steps with one of these tags, e.g. recon (comma-separated; untagged steps are skipped)
+    #[arg(long, value_delimiter = ',', value_name = "TAGS")]
+    pub only_tags: Vec<String>,
+
+    /// Skip plan steps with any of these tags, e.g. exploit (comma-separated)
+    #[arg(long, value_delimiter = ',', value_name = "TAGS")]
+    pub skip_tags: Vec<String>,
+
+    /// Skip steps whose placeholders can't be resolved instead of aborting the plan
+    #[arg(long)]
+    pub skip_unresolved: bool,
+
+    /// Reject plans with duplicate, out-of-order or missing step numbers instead of warning
+    #[arg(long)]
+    pub strict_steps: bool,
+
+    /// Text added before the system prompt for this run (e.g. "Prefer passive techniques.")
+    #[arg(long, value_name = "TEXT")]
+    pub prompt_prepend: Option<String>,
+
+    /// Text added after the system prompt for this run (e.g. "Focus on stealth.")
+    #[arg(long, value_name = "TEXT")]
+    pub prompt_append: Option<String>,
+
+    /// Actually run the plan's commands; without it (or safety.auto_execute) plans are only previewed
+    #[arg(short = 'x', long)]
+    pub execute: bool,
+
+    /// Generate the plan and show how each step's placeholders would be filled, without executing
+    #[arg(long)]
+    pub show_substitutions: bool,
+
+    /// Ask the model to explain steps whose purpose is missing or too terse before they run
+    #[arg(long)]
+    pub explain_plan: bool,
+
+    /// Ask the model to critique each plan for risky or wrong steps before it runs (one extra generation)
+    #[arg(long)]
+    pub review: bool,
+
+    /// Plan each query from scratch: no earlier steps or model conversation (same as advanced.keep_history = false)
+    #[arg(long)]
+    pub no_history: bool,
+
+    /// Re-ask the model to break steps with no command into concrete commands (same as advanced.expand_vague_steps = true)
+    #[arg(long)]
+    pub expand_vague: bool,
+
+    /// Show every target, tool and action type a plan involves and ask once before running it
+    #[arg(long)]
+    pub confirm_plan: bool,
+
+    /// Open each generated plan as JSON in $EDITOR to change or remove steps before it runs
+    #[arg(long)]
+    pub edit_plan: bool,
+
+    /// Give up on any prompt (authorization, confirmations, pause, model picker) after this long, taking the safe choice (no/abort); e.g. 60, 90s, 5m
+    #[arg(long, value_name = "SECS", value_parser = parse_interval)]
+    pub input_timeout: Option<Duration>,
+
+    /// Skip the Ollama install check and model validation (faster startup when Ollama is known-good)
+    #[arg(long)]
+    pub skip_setup: bool,
 }
 
 #[derive(Subcommand)]
@@ -20,7 +183,160 @@ pub enum Commands {
         
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// Notes file (prior scans, asset inventory) given to the model as background
+        #[arg(long)]
+        context_file: Option<PathBuf>,
+
+        /// Result format for stdout and --output
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Save the model's parsed plan as JSON (re-run it later with `replay`)
+        #[arg(long, value_name = "PATH")]
+        save_plan: Option<PathBuf>,
+
+        /// Replace target IPs/CIDRs/hostnames with stable pseudonyms (HOST_1, NET_1) for sharing
+        #[arg(long)]
+        anonymize: bool,
+
+        /// With --anonymize, leave out the pseudonym -> real legend
+        #[arg(long, requires = "anonymize")]
+        omit_legend: bool,
+
+        /// Re-run the query on this interval (e.g. 300, 90s, 5m, 1h) and report what changed; Ctrl-C stops
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+        watch: Option<Duration>,
+
+        /// Print only the JSON run report on stdout (all progress and diagnostics go to stderr), for piping
+        #[arg(long, conflicts_with = "watch")]
+        quiet_json: bool,
+    },
+    /// Execute a plan saved with --save-plan without asking the model again
+    Replay {
+        plan: PathBuf,
+
+        /// Text to pre-parse for targets like a run query (e.g. "scan 10.0.0.7"); --set works too
+        #[arg(long)]
+        query: Option<String>,
+
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Compare two saved JSON run reports or session files (values, ports, step outputs)
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+
+        /// Text (default) or the differences as a JSON object
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
     /// Start interactive session
     Interactive,
-}
\ No newline at end of file
+    /// Unload the configured model from Ollama's memory now
+    Unload,
+    /// Print the JSON schema of the plan the model is expected to return
+    Schema,
+    /// List the available --profile names
+    Profiles,
+    /// Check Ollama, the model, config files, network and platform, with fixes for failures
+    Doctor,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print every setting after all layers, the profile and CLI flags, with where each came from
+    Effective,
+}
+
+// Parses KEY=VALUE where KEY is usable as a {placeholder} name
+fn parse_key_value(raw: &str) -> Result<(String, String), String> {
+    let (key, value) = raw.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got '{}'", raw))?;
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("'{}' is not a valid placeholder name (letters, digits, _)", key));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+// "300" (seconds) or a number with an s/m/h suffix
+fn parse_interval(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    let (number, unit) = match raw.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => raw.split_at(i),
+        None => (raw, "s"),
+    };
+    let n: u64 = number.parse().map_err(|_| format!("expected an interval like 300, 90s, 5m or 1h, got '{}'", raw))?;
+    let secs = match unit {
+        "s" => n,
+        "m" => n * 60,
+        "h" => n * 3600,
+        _ => return Err(format!("unknown interval unit '{}' (use s, m or h)", unit)),
+    };
+    if secs == 0 {
+        return Err("interval must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Plan execution summary
+    Text,
+    /// RunReport object (summary, discovered values, step outputs)
+    Json,
+}
+
+impl Cli {
+    // The subcommand to run. A bare query is a `run` with its defaults; with neither, this exits
+    // with clap's usage error like a missing subcommand always did.
+    pub fn take_command(&mut self) -> Commands {
+        match (self.command.take(), self.query.take()) {
+            (Some(command), None) => command,
+            (None, Some(query)) => Commands::Run {
+                query,
+                output: None,
+                context_file: None,
+                format: OutputFormat::Text,
+                save_plan: None,
+                anonymize: false,
+                omit_legend: false,
+                watch: None,
+                quiet_json: false,
+            },
+            (Some(_), Some(query)) => Cli::command()
+                .error(clap::error::ErrorKind::ArgumentConflict, format!("unexpected argument '{}' before the subcommand", query))
+                .exit(),
+            (None, None) => Cli::command()
+                .error(clap::error::ErrorKind::MissingSubcommand, "give a query (hacker-rs \"scan 10.0.0.1\") or a subcommand")
+                .exit(),
+        }
+    }
+}
+
+impl Commands {
+    // Whether this subcommand sends prompts to the model (and so needs Ollama set up and validated)
+    pub fn needs_model(&self) -> bool {
+        match self {
+            Commands::Run { .. } | Commands::Interactive => true,
+            // Only tells Ollama to drop the model; validating it first would load it again
+            Commands::Unload => false,
+            Commands::Schema | Commands::Profiles | Commands::Diff { .. } | Commands::Config { .. } => false,
+            // Runs a saved plan; the model is never asked
+            Commands::Replay { .. } => false,
+            // Reports on Ollama/model problems instead of failing on them
+            Commands::Doctor => false,
+        }
+    }
+
+    // Whether this subcommand can run plan steps (and so needs the authorization gate)
+    pub fn executes_commands(&self) -> bool {
+        matches!(self, Commands::Run { .. } | Commands::Interactive | Commands::Replay { .. })
+    }
+}
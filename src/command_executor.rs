@@ -1,11 +1,33 @@
 // src/command_executor.rs
 
-use crate::setup::SystemSetup;
-use std::path::Path;
+use crate::setup::{Platform, SystemSetup};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::{Command as StdCommand, Stdio, Output as StdOutput}; // Use std::process
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 use tokio::process::Command as TokioCommand;
 use tokio::task; // Use spawn_blocking
+use which::which;
+
+// How long a command may run before we point out it might be waiting for input
+const INPUT_HINT_AFTER: std::time::Duration = std::time::Duration::from_secs(30);
+// A capture that exits within this long after starting has failed (bad interface, no permission)
+const CAPTURE_STARTUP_CHECK: std::time::Duration = std::time::Duration::from_millis(500);
+// How often a UAC-elevated command is checked for a timeout or Ctrl-C while it runs
+#[cfg(windows)]
+const ELEVATED_WAIT_SLICE_MS: u32 = 200;
+// How long a stopped capture gets to flush its pcap before it is killed
+const CAPTURE_STOP_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+// A post_step_hook still running after this is killed so it can't stall the plan
+const POST_STEP_HOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+// On shutdown, leftover processes get SIGTERM and this long to exit before SIGKILL
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_millis(500);
+
+// Pipes and command separators between the stages of a compound command
+static SEPARATOR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\|\|?|&&|;").expect("Invalid separator regex"));
 
 #[derive(Error, Debug)]
 pub enum ExecutionError {
@@ -23,11 +45,15 @@ pub enum ExecutionError {
     BlockingTaskError(String),
     #[error("Command parsing failed: {0}")] // Added
     CommandParsingError(String),
+    #[error("Aborted by user")]
+    Aborted,
+    #[error("Timed out after {0}s")]
+    TimedOut(u64),
 }
 
 
 // --- get_tool_from_command function (remains the same) ---
-fn get_tool_from_command(command: &str) -> Option<String> {
+pub fn get_tool_from_command(command: &str) -> Option<String> {
      command.split_whitespace().next().and_then(|first_part| {
         let path = Path::new(first_part);
         path.file_name().and_then(|os| os.to_str()).map(|s| s.to_string())
@@ -35,30 +61,59 @@ fn get_tool_from_command(command: &str) -> Option<String> {
     })
 }
 
+// --- Helper to list the tool of every stage in a compound command ---
+// Splits on pipes and command separators so `nmap x | nc y` yields both tools
+pub fn get_tools_from_command(command: &str) -> Vec<String> {
+    SEPARATOR_RE
+        .split(command)
+        .filter_map(get_tool_from_command)
+        .collect()
+}
+
 // --- NEW: Helper function for basic shell-like argument parsing ---
 // Parses a command line, handling simple quoted arguments. Returns (command, args).
 fn parse_command_line(line: &str) -> Result<(String, Vec<String>), ExecutionError> {
+    let (command, args) = parse_command_words(line)?;
+    Ok((command, args.into_iter().map(|(arg, _)| arg).collect()))
+}
+
+// The same split, with each argument flagged when any part of it was inside quotes
+fn parse_command_words(line: &str) -> Result<(String, Vec<(String, bool)>), ExecutionError> {
     let mut args = Vec::new();
     let mut current_arg = String::new();
+    let mut current_quoted = false;
     let mut in_quotes = false;
     let mut chars = line.trim().chars().peekable();
     let mut command = None;
 
     while let Some(c) = chars.next() {
         match c {
+            // "" inside quotes is a literal quote, as cmd and quote_arg write it
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                current_arg.push('"');
+            }
             '"' => {
                 in_quotes = !in_quotes;
+                current_quoted = true;
                 // Decide whether to include quotes in the arg - usually not
             }
+            // cmd's escape character, outside quotes
+            '^' if !in_quotes => {
+                if let Some(escaped) = chars.next() {
+                    current_arg.push(escaped);
+                }
+            }
             ' ' | '\t' if !in_quotes => {
                 if !current_arg.is_empty() {
                     if command.is_none() {
                         command = Some(current_arg.clone());
                     } else {
-                        args.push(current_arg.clone());
+                        args.push((current_arg.clone(), current_quoted));
                     }
                     current_arg.clear();
                 }
+                current_quoted = false;
             }
             _ => {
                 current_arg.push(c);
@@ -70,7 +125,7 @@ fn parse_command_line(line: &str) -> Result<(String, Vec<String>), ExecutionErro
          if command.is_none() {
             command = Some(current_arg.clone());
          } else {
-            args.push(current_arg);
+            args.push((current_arg, current_quoted));
          }
     }
 
@@ -78,37 +133,374 @@ fn parse_command_line(line: &str) -> Result<(String, Vec<String>), ExecutionErro
            .map(|cmd| (cmd, args))
 }
 
+// --- Shell state (cd/export) ---
+// Each step runs in a fresh shell, so a `cd` or `export` is gone by the next step. Leading ones
+// are split off here and carried over by the caller instead (ExecOptions::workdir/env).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateChange {
+    // None: a bare `cd` (home directory)
+    Cd(Option<String>),
+    Export(Vec<(String, String)>),
+}
+
+// Splits leading `cd DIR` / `export K=V` segments joined by && or ; from the rest of the command.
+// Stops at anything only the shell can evaluate ($VAR, `cd -`, pipes, ...), which stays in the rest.
+pub fn split_state_changes(command: &str) -> (Vec<StateChange>, &str) {
+    let mut changes = Vec::new();
+    let mut rest = command.trim();
+    while !rest.is_empty() {
+        let (segment, remainder) = match SEPARATOR_RE.find(rest) {
+            Some(m) if m.as_str() == "&&" || m.as_str() == ";" => (&rest[..m.start()], rest[m.end()..].trim_start()),
+            Some(_) => break,
+            None => (rest, ""),
+        };
+        match parse_state_change(segment) {
+            Some(change) => changes.push(change),
+            None => break,
+        }
+        rest = remainder;
+    }
+    (changes, rest)
+}
+
+fn parse_state_change(segment: &str) -> Option<StateChange> {
+    if segment.contains(['$', '`', '>', '<', '(', '&', '*']) {
+        return None;
+    }
+    let (name, args) = parse_command_line(segment).ok()?;
+    let args: Vec<String> = args.iter().map(|a| a.trim_matches('\'').to_string()).collect();
+    match (name.as_str(), args.as_slice()) {
+        ("cd", []) => Some(StateChange::Cd(None)),
+        ("cd", [dir]) if dir != "-" => Some(StateChange::Cd(Some(dir.clone()))),
+        ("export", vars) if !vars.is_empty() => vars
+            .iter()
+            .map(|var| {
+                let (key, value) = var.split_once('=')?;
+                let valid = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                valid.then(|| (key.to_string(), value.to_string()))
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(StateChange::Export),
+        _ => None,
+    }
+}
+
+
+// --- Argument quoting ---
+// Quotes one argument for the shell it will be parsed by (sh/bash, or cmd and our own pipeline
+// parser on Windows) when it has whitespace or shell metacharacters; plain values pass unchanged.
+// For cmd a quote inside the value is doubled (`\"` would end cmd's quoting and expose `&`/`|`),
+// and `%` can't be escaped inside quotes, so the quotes close around a `^%`.
+pub fn quote_arg(value: &str, windows: bool) -> String {
+    const SH_SPECIAL: &[char] = &[
+        ' ', '\t', '\n', '\'', '"', '\\', '$', '`', '|', '&', ';', '<', '>', '(', ')', '*', '?', '[', ']', '{', '}', '~', '#', '!',
+    ];
+    const CMD_SPECIAL: &[char] = &[' ', '\t', '"', '&', '|', '<', '>', '^', '(', ')', ';', ',', '%', '!'];
+    if windows {
+        if !value.is_empty() && !value.contains(CMD_SPECIAL) {
+            return value.to_string();
+        }
+        // Backslashes right before a quote we write are doubled so programs don't read \" as a quote
+        let mut quoted = String::from("\"");
+        let mut backslashes = 0;
+        for c in value.chars() {
+            match c {
+                '\\' => {
+                    backslashes += 1;
+                    quoted.push(c);
+                    continue;
+                }
+                '"' => quoted.push_str(&format!("{}\"\"", "\\".repeat(backslashes))),
+                '%' => quoted.push_str(&format!("{}\"^%\"", "\\".repeat(backslashes))),
+                _ => quoted.push(c),
+            }
+            backslashes = 0;
+        }
+        quoted.push_str(&"\\".repeat(backslashes));
+        quoted.push('"');
+        quoted
+    } else {
+        if !value.is_empty() && !value.contains(SH_SPECIAL) {
+            return value.to_string();
+        }
+        // Nothing is special inside single quotes; a literal ' closes, escapes and reopens
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+// --- Glob expansion for the pipeline path ---
+// No shell is involved there, so expand `*`, `?` and `[...]` ourselves like sh would:
+// only when the pattern is valid and matches something, otherwise the literal is kept.
+// Quoted arguments (see parse_command_words) are never expanded, e.g. a grep pattern "a.*b".
+fn expand_globs(args: Vec<(String, bool)>) -> Vec<String> {
+    let mut expanded = Vec::with_capacity(args.len());
+    for (arg, quoted) in args {
+        if quoted || arg.contains('\'') || !arg.contains(['*', '?', '[']) {
+            expanded.push(arg);
+            continue;
+        }
+        let matches: Vec<String> = match glob::glob(&arg) {
+            Ok(paths) => paths.flatten().map(|p| p.display().to_string()).collect(),
+            Err(_) => Vec::new(),
+        };
+        if matches.is_empty() {
+            expanded.push(arg);
+        } else {
+            expanded.extend(matches);
+        }
+    }
+    expanded
+}
+
+// --- Shell selection for the non-pipeline path ---
+// `preferred` is the exec_shell setting; "auto"/unset means bash on Unix and cmd on Windows.
+// Falls back to sh/cmd when the preferred shell isn't on the PATH. Returns (program, leading args).
+pub fn resolve_shell(preferred: Option<&str>) -> (String, Vec<&'static str>) {
+    let fallback = if cfg!(windows) { "cmd" } else { "sh" };
+    let wanted = match preferred.map(str::trim) {
+        Some(shell) if !shell.is_empty() && shell != "auto" => shell,
+        _ if cfg!(windows) => "cmd",
+        _ => "bash",
+    };
+    let shell = if wanted == fallback || which(wanted).is_ok() {
+        wanted
+    } else {
+        if preferred.is_some_and(|p| p.trim() == wanted) {
+            println!("WARN: exec_shell '{}' not found on PATH, falling back to {}.", wanted, fallback);
+        }
+        fallback
+    };
+    let name = Path::new(shell)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(shell)
+        .to_lowercase();
+    let args = match name.as_str() {
+        "cmd" => vec!["/C"],
+        "pwsh" | "powershell" => vec!["-NoProfile", "-NonInteractive", "-Command"],
+        _ => vec!["-c"],
+    };
+    (shell.to_string(), args)
+}
+
+// --- Linux-only tools that are skipped when running locally on Windows ---
+pub fn unsupported_on_platform(tool: &str, setup: &SystemSetup) -> bool {
+    cfg!(windows) && setup.remote().is_none() && ["setoolkit", "msfconsole"].contains(&tool)
+}
+
+// --- ExecOptions ---
+// How a local/remote command is run (everything besides the command itself)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecOptions<'a> {
+    // Preferred shell ("auto", "bash", "pwsh", ...); see resolve_shell
+    pub shell: Option<&'a str>,
+    // Some(display cap in lines, 0 = no cap) prints output live as it arrives
+    pub stream: Option<usize>,
+    // Give local commands the terminal's stdin (for tools that must prompt); Ctrl-C then reaches them too
+    pub attach_stdin: bool,
+    // Linux, not root: prefix commands that need root with `sudo -n` (see needs_root)
+    pub auto_sudo: bool,
+    // Append stderr to successful output (under STDERR_MARKER) instead of dropping it
+    pub include_stderr: bool,
+    // Kill the command once it has run this long (timeouts.exec_timeout_secs)
+    pub timeout: Option<std::time::Duration>,
+    // Working directory and extra environment from earlier steps' cd/export (local commands only)
+    pub workdir: Option<&'a Path>,
+    pub env: &'a [(String, String)],
+}
 
 // --- execute_command function (Using spawn_blocking with better parsing) ---
-pub async fn execute_command(command: &str, setup: &SystemSetup) -> Result<String, ExecutionError> {
+// --- Spawned process tracking ---
+// Every process a plan starts is registered here (pid -> whether it leads its own process group)
+// while it runs, so shutdown can take down whatever is still running: on Unix the whole group (the
+// shell plus the scanner it started, sudo plus tcpdump), on Windows the process tree. An entry goes
+// once its child is waited for, except a Unix group that still holds something the child left
+// running in the background (`nmap ... &`): that stays until the group empties or we shut down.
+static SPAWNED: Lazy<Mutex<HashMap<u32, bool>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Registration of one spawned child. Dropped before finish() (step aborted, timed out, failed to
+// wait), it kills the child's group right away instead of leaving its descendants running.
+#[derive(Debug)]
+struct SpawnGuard {
+    pid: u32,
+    own_group: bool,
+    finished: bool,
+}
+
+impl SpawnGuard {
+    fn track(pid: Option<u32>, own_group: bool) -> Option<Self> {
+        let pid = pid?;
+        let mut spawned = spawned();
+        // Forget groups whose leftovers have all exited, before their id can be reused
+        #[cfg(unix)]
+        spawned.retain(|&pid, own_group| !*own_group || group_alive(pid));
+        spawned.insert(pid, own_group);
+        Some(SpawnGuard { pid, own_group, finished: false })
+    }
+
+    // The child exited normally and was waited for: deregister it so nothing signals its id later,
+    // unless its group lives on in what it backgrounded (signalling -pgid works without the leader)
+    fn finish(mut self) {
+        self.finished = true;
+        #[cfg(unix)]
+        if self.own_group && group_alive(self.pid) {
+            return;
+        }
+        spawned().remove(&self.pid);
+    }
+}
+
+impl Drop for SpawnGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            spawned().remove(&self.pid);
+            signal_process_tree(self.pid, self.own_group, true);
+        }
+    }
+}
+
+fn spawned() -> std::sync::MutexGuard<'static, HashMap<u32, bool>> {
+    SPAWNED.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Whether anything is left in the process group `pgid` (EPERM: there is, but it isn't ours to signal)
+#[cfg(unix)]
+fn group_alive(pgid: u32) -> bool {
+    unsafe { libc::kill(-(pgid as i32), 0) == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+// SIGTERM (or SIGKILL with `force`) to the child's group, or just the child when it shares ours.
+// Windows has no graceful variant: the tree is always killed. False if nothing was there.
+fn signal_process_tree(pid: u32, own_group: bool, force: bool) -> bool {
+    #[cfg(unix)]
+    {
+        let target = if own_group { -(pid as i32) } else { pid as i32 };
+        unsafe {
+            if !force {
+                return libc::kill(target, libc::SIGTERM) == 0;
+            }
+            // sudo only relays catchable signals to its (root) command, so it gets a SIGTERM first
+            libc::kill(target, libc::SIGTERM);
+            libc::kill(target, libc::SIGKILL) == 0
+        }
+    }
+    #[cfg(windows)]
+    {
+        let _ = (own_group, force);
+        StdCommand::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+// --- Shutdown ---
+// Called on the way out (normal exit, error, SIGTERM/SIGHUP) so no capture or scanner outlives us
+pub fn kill_all_spawned() {
+    let leftovers: Vec<(u32, bool)> = spawned().drain().collect();
+    let stopping: Vec<(u32, bool)> = leftovers.into_iter().filter(|(pid, own_group)| signal_process_tree(*pid, *own_group, false)).collect();
+    if stopping.is_empty() {
+        return;
+    }
+    eprintln!("INFO: Stopping {} process group(s) still running from this run.", stopping.len());
+    if cfg!(unix) {
+        std::thread::sleep(SHUTDOWN_GRACE);
+        for (pid, own_group) in stopping {
+            signal_process_tree(pid, own_group, true);
+        }
+    }
+}
+
+// Resolves when we are asked to terminate other than by Ctrl-C (kill, closed terminal or console)
+pub async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match (signal(SignalKind::terminate()), signal(SignalKind::hangup())) {
+            (Ok(mut term), Ok(mut hangup)) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = hangup.recv() => {}
+                }
+            }
+            _ => std::future::pending::<()>().await,
+        }
+    }
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        match (ctrl_close(), ctrl_shutdown()) {
+            (Ok(mut close), Ok(mut shutdown)) => {
+                tokio::select! {
+                    _ = close.recv() => {}
+                    _ = shutdown.recv() => {}
+                }
+            }
+            _ => std::future::pending::<()>().await,
+        }
+    }
+}
+
+pub async fn execute_command(command: &str, setup: &SystemSetup, exec: &ExecOptions<'_>) -> Result<String, ExecutionError> {
+    let stream = exec.stream;
     // Tool check remains the same
     let tool_for_check = get_tool_from_command(command).ok_or_else(|| ExecutionError::CommandParsingError("Cannot determine tool from empty command".to_string()))?;
-    if cfg!(windows) && ["setoolkit", "msfconsole"].contains(&tool_for_check.as_str()) { return Err(ExecutionError::UnsupportedPlatform(format!("{} requires Linux", tool_for_check))); }
+    let remote = setup.remote();
+    if unsupported_on_platform(&tool_for_check, setup) { return Err(ExecutionError::UnsupportedPlatform(format!("{} requires Linux", tool_for_check))); }
     if let Err(e) = setup.check_and_install_tool(&tool_for_check).await { return Err(ExecutionError::DependencyFailure(e.to_string())); }
 
+    // --- Auto sudo --- `-n` fails instead of waiting for a password nobody can type
+    let use_sudo = exec.auto_sudo
+        && remote.is_none()
+        && matches!(setup.platform, Platform::KaliLinux | Platform::OtherLinux)
+        && !setup.is_admin()
+        && needs_root(command);
+    let sudo_command = format!("sudo -n {}", command);
+    let command = if use_sudo {
+        println!("INFO: '{}' needs root, running it with sudo.", tool_for_check);
+        sudo_command.as_str()
+    } else {
+        command
+    };
+
     // --- Execute command ---
-    let output_result: std::result::Result<StdOutput, ExecutionError> = if cfg!(windows) && command.contains('|') {
+    let output_result: std::result::Result<StdOutput, ExecutionError> = if let Some(remote) = remote {
+        // --- Remote Execution over SSH --- the remote shell handles pipes/redirection
+        // The command itself was already shown by the caller, with secrets redacted
+        println!("Executing on {} via ssh.", remote.destination);
+        let mut cmd = remote.command(command);
+        cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        output_or_abort(cmd, stream, false, exec.timeout).await
+    } else if cfg!(windows) && command.contains('|') {
         // --- Windows Pipeline Handling via spawn_blocking ---
-        println!("Executing Windows pipeline (blocking thread): {}", command);
+        println!("Executing Windows pipeline (blocking thread).");
         let command_clone = command.to_string();
+        let workdir = exec.workdir.map(Path::to_path_buf);
+        let env = exec.env.to_vec();
+        // Pids of the stages started so far, shared so a timeout can kill them; None once it has
+        let stage_pids: Arc<Mutex<Option<Vec<u32>>>> = Arc::new(Mutex::new(Some(Vec::new())));
+        let thread_stage_pids = Arc::clone(&stage_pids);
 
-        task::spawn_blocking(move || -> std::io::Result<StdOutput> {
+        let pipeline = task::spawn_blocking(move || -> std::io::Result<StdOutput> {
             let parts: Vec<&str> = command_clone.split('|').map(|s| s.trim()).collect();
             let mut children: Vec<std::process::Child> = Vec::new();
+            let mut guards: Vec<SpawnGuard> = Vec::new();
             let mut previous_stdout: Option<std::process::ChildStdout> = None;
 
             for (i, part) in parts.iter().enumerate() {
                  if part.is_empty() { return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Empty command part in pipeline")); }
 
                  // Use the new parser for each part
-                 let (cmd_name, cmd_args) = parse_command_line(part)
+                 let (cmd_name, cmd_args) = parse_command_words(part)
                      .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?; // Map error to io::Error
-
-                 // *** ADDED LOGGING HERE ***
-                 println!("DEBUG: Pipeline Part {}: cmd='{}', args='{:?}'", i + 1, cmd_name, cmd_args);
+                 let cmd_args = expand_globs(cmd_args);
 
                  let mut cmd = StdCommand::new(cmd_name);
                  cmd.args(&cmd_args); // Pass parsed args
+                 if let Some(dir) = &workdir { cmd.current_dir(dir); }
+                 cmd.envs(env.iter().cloned());
 
                  if let Some(stdout) = previous_stdout.take() { cmd.stdin(Stdio::from(stdout)); }
                  else { cmd.stdin(Stdio::inherit()); }
@@ -123,13 +515,31 @@ pub async fn execute_command(command: &str, setup: &SystemSetup) -> Result<Strin
                      cmd.stderr(Stdio::piped());
                  }
 
-                 let mut child = cmd.spawn()?;
+                 // Spawned under the lock, so a timeout either sees this stage or stops it starting
+                 let mut child = {
+                     let mut pids = thread_stage_pids.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                     let Some(pids) = pids.as_mut() else {
+                         return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Pipeline timed out"));
+                     };
+                     let child = cmd.spawn()?;
+                     pids.push(child.id());
+                     child
+                 };
+                 guards.extend(SpawnGuard::track(Some(child.id()), false));
                  previous_stdout = child.stdout.take(); // Take stdout for the next potential command
 
                  if i == parts.len() - 1 {
                      // This is the last command, wait for it and capture its output
                      // *** MODIFIED TO CAPTURE stderr AND status MORE EXPLICITLY ***
-                     match child.wait_with_output() {
+                     let result = match stream {
+                         // The loop above already took this stage's stdout into previous_stdout
+                         Some(max_lines) => match previous_stdout.take() {
+                             Some(stdout) => stream_blocking(child, stdout, max_lines),
+                             None => child.wait_with_output(),
+                         },
+                         None => child.wait_with_output(),
+                     };
+                     match result {
                          Ok(output) => {
                              // Log status and stderr before returning
                              println!("DEBUG: Final command status: {}", output.status);
@@ -138,6 +548,7 @@ pub async fn execute_command(command: &str, setup: &SystemSetup) -> Result<Strin
                                  println!("DEBUG: Final command stderr:\n{}", stderr_text);
                              }
                              // Return the captured output
+                             guards.into_iter().for_each(SpawnGuard::finish);
                              return Ok(output);
                          }
                          Err(e) => {
@@ -155,22 +566,457 @@ pub async fn execute_command(command: &str, setup: &SystemSetup) -> Result<Strin
             for mut child in children { let _ = child.wait(); }
             // This part should ideally not be reached if the loop structure is correct
             Err(std::io::Error::new(std::io::ErrorKind::Other, "Pipeline structure error - loop finished unexpectedly"))
-        }).await
-        .map_err(|e| ExecutionError::BlockingTaskError(format!("Blocking task failed: {}", e)))
+        });
+        // The blocking thread can't be cancelled; on timeout its stages are killed, which ends its waits
+        let joined = match exec.timeout {
+            Some(limit) => tokio::time::timeout(limit, pipeline).await.map_err(|_| {
+                let started = stage_pids.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take().unwrap_or_default();
+                for pid in started {
+                    signal_process_tree(pid, false, true);
+                }
+                ExecutionError::TimedOut(limit.as_secs())
+            }),
+            None => Ok(pipeline.await),
+        };
+        joined.and_then(|joined| joined.map_err(|e| ExecutionError::BlockingTaskError(format!("Blocking task failed: {}", e))))
         .and_then(|result| result.map_err(ExecutionError::IoError)) // Maps io::Result<StdOutput> to Result<StdOutput, IoError>
 
     } else {
         // --- Non-Pipeline / Linux Handling (using TokioCommand) ---
-        println!("Executing command via shell: {}", command);
-        let shell = if cfg!(windows) { "cmd" } else { "sh" };
-        let arg = if cfg!(windows) { "/C" } else { "-c" };
-        TokioCommand::new(shell)
-            .arg(arg).arg(command)
-            .stdout(Stdio::piped()).stderr(Stdio::piped())
-            .output().await.map_err(ExecutionError::IoError) // Maps io::Error to IoError
+        let (shell, shell_args) = resolve_shell(exec.shell);
+        println!("Executing command via {}.", shell);
+        let mut cmd = TokioCommand::new(&shell);
+        cmd.args(&shell_args).arg(command).stdout(Stdio::piped()).stderr(Stdio::piped());
+        if let Some(dir) = exec.workdir {
+            cmd.current_dir(dir);
+        }
+        cmd.envs(exec.env.iter().cloned());
+        output_or_abort(cmd, stream, exec.attach_stdin, exec.timeout).await
     };
 
     // --- Process output (This part remains the same) ---
+    match process_output(output_result, exec.include_stderr) {
+        Err(ExecutionError::CommandFailure(msg)) if use_sudo && msg.contains("sudo:") && msg.contains("password is required") => {
+            Err(ExecutionError::CommandFailure(format!(
+                "auto-sudo could not elevate without a password for '{}'. Run `sudo -v` first to cache your credentials, or allow the tool in sudoers (NOPASSWD).",
+                tool_for_check
+            )))
+        }
+        result => result,
+    }
+}
+
+// --- Post-step hook ---
+// Runs `hook` (program plus optional fixed args) with step, command and status appended and as
+// HACKER_RS_* env vars. Never fails the step: problems are printed as warnings.
+pub async fn run_post_step_hook(hook: &str, step: u32, command: &str, status: &str) {
+    let mut words = hook.split_whitespace();
+    let Some(program) = words.next() else { return };
+    let program = shellexpand::tilde(program).to_string();
+    let mut cmd = TokioCommand::new(&program);
+    cmd.args(words)
+        .arg(step.to_string())
+        .arg(command)
+        .arg(status)
+        .env("HACKER_RS_STEP", step.to_string())
+        .env("HACKER_RS_COMMAND", command)
+        .env("HACKER_RS_STATUS", status)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            println!("WARN: Could not run post_step_hook '{}': {}", program, e);
+            return;
+        }
+    };
+    let guard = SpawnGuard::track(child.id(), false);
+    let result = tokio::time::timeout(POST_STEP_HOOK_TIMEOUT, child.wait_with_output()).await;
+    if let (Ok(_), Some(guard)) = (&result, guard) {
+        guard.finish();
+    }
+    match result {
+        Ok(Ok(output)) if output.status.success() => {}
+        Ok(Ok(output)) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let detail = if stderr.trim().is_empty() { String::new() } else { format!(": {}", stderr.trim()) };
+            println!("WARN: post_step_hook '{}' failed for step {} ({}){}", program, step, output.status, detail);
+        }
+        Ok(Err(e)) => println!("WARN: Could not run post_step_hook '{}': {}", program, e),
+        Err(_) => println!("WARN: post_step_hook '{}' took longer than {}s for step {}, killed it.", program, POST_STEP_HOOK_TIMEOUT.as_secs(), step),
+    }
+}
+
+// --- Background capture ---
+// A packet capture (tcpdump, else dumpcap) that keeps running while later plan steps execute
+#[derive(Debug)]
+pub struct BackgroundCapture {
+    // Step that started it and the step after which it stops (None = end of plan)
+    pub step: u32,
+    pub until_step: Option<u32>,
+    pub path: PathBuf,
+    child: tokio::process::Child,
+    guard: Option<SpawnGuard>,
+}
+
+// Capture tools in order of preference, for the ones on the PATH
+pub fn installed_capture_tools() -> Vec<&'static str> {
+    ["tcpdump", "dumpcap"].into_iter().filter(|tool| which(tool).is_ok()).collect()
+}
+
+// Starts the capture writing to `path`; with `sudo`, tcpdump runs under `sudo -n` (see needs_root)
+pub async fn start_capture(tool: &str, interface: Option<&str>, filter: Option<&str>, path: &Path, sudo: bool) -> Result<tokio::process::Child, ExecutionError> {
+    let mut cmd = if sudo && tool == "tcpdump" {
+        let mut cmd = TokioCommand::new("sudo");
+        cmd.args(["-n", tool]);
+        cmd
+    } else {
+        TokioCommand::new(tool)
+    };
+    match tool {
+        // -U writes each packet straight away, so the file is usable even if we have to kill it
+        "tcpdump" => {
+            cmd.args(["-n", "-U", "-i", interface.unwrap_or("any"), "-w"]).arg(path);
+            if let Some(filter) = filter {
+                cmd.arg(filter);
+            }
+        }
+        _ => {
+            if let Some(interface) = interface {
+                cmd.args(["-i", interface]);
+            }
+            cmd.arg("-q").arg("-w").arg(path);
+            if let Some(filter) = filter {
+                cmd.args(["-f", filter]);
+            }
+        }
+    }
+    cmd.kill_on_drop(true).stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::piped());
+    // Keep it out of the terminal's process group so Ctrl-C on a step doesn't end the capture too
+    #[cfg(unix)]
+    cmd.process_group(0);
+    #[cfg(windows)]
+    {
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let mut child = cmd.spawn()?;
+    tokio::time::sleep(CAPTURE_STARTUP_CHECK).await;
+    if let Some(status) = child.try_wait()? {
+        let output = child.wait_with_output().await?;
+        return Err(ExecutionError::CommandFailure(format!(
+            "{} exited right away ({}):\n{}",
+            tool,
+            status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(child)
+}
+
+impl BackgroundCapture {
+    pub fn new(step: u32, until_step: Option<u32>, path: PathBuf, child: tokio::process::Child) -> Self {
+        // Its own process group (see start_capture), reaped on shutdown if never stopped
+        let guard = SpawnGuard::track(child.id(), true);
+        BackgroundCapture { step, until_step, path, child, guard }
+    }
+
+    // Interrupts the capture so it flushes and closes the pcap (killing it if it won't exit)
+    pub async fn stop(mut self) -> PathBuf {
+        #[cfg(unix)]
+        if let Some(pid) = self.child.id() {
+            // Its own group (see start_capture): SIGINT reaches sudo and tcpdump alike
+            unsafe {
+                libc::kill(-(pid as i32), libc::SIGINT);
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = self.child.start_kill();
+        if tokio::time::timeout(CAPTURE_STOP_GRACE, self.child.wait()).await.is_err() {
+            println!("WARN: Capture from step {} did not stop within {}s, killing it.", self.step, CAPTURE_STOP_GRACE.as_secs());
+            let _ = self.child.kill().await;
+        }
+        // Dropped unfinished, so a sudo'd tcpdump that outlived the signal goes with its group
+        self.guard.take();
+        self.path
+    }
+}
+
+// --- Per-step abort ---
+// Runs the child to completion unless Ctrl-C arrives first, in which case the child is killed
+// (dropping the output future kills it) and the step reports Aborted. The child gets its own
+// process group so the terminal's Ctrl-C reaches only us, not the scan we may want to keep.
+// (The Windows pipeline path runs on a blocking thread and can't be aborted this way.)
+// With `attach_stdin` the child shares our terminal (and its Ctrl-C) so it can prompt.
+async fn output_or_abort(mut cmd: TokioCommand, stream: Option<usize>, attach_stdin: bool, timeout: Option<std::time::Duration>) -> Result<StdOutput, ExecutionError> {
+    cmd.kill_on_drop(true);
+    if attach_stdin {
+        cmd.stdin(Stdio::inherit());
+    } else {
+        #[cfg(unix)]
+        {
+            // Outside the foreground group a tty read would stop the child, so it gets no stdin
+            cmd.process_group(0).stdin(Stdio::null());
+        }
+        #[cfg(windows)]
+        {
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+            cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+        }
+    }
+    let child = cmd.spawn()?;
+    // Dropping it unfinished (Ctrl-C, timeout) also kills what the shell started, not just the shell
+    let guard = SpawnGuard::track(child.id(), !attach_stdin);
+    let run = async {
+        match stream {
+            Some(max_lines) => stream_output(child, max_lines).await,
+            None => child.wait_with_output().await,
+        }
+    };
+    tokio::pin!(run);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    // A silent command that's still running may be stuck on a prompt; say so once
+    let input_hint = tokio::time::sleep(INPUT_HINT_AFTER);
+    tokio::pin!(input_hint);
+    let mut hinted = attach_stdin;
+    // Dropping `run` kills the child (kill_on_drop)
+    let deadline = tokio::time::sleep(timeout.unwrap_or(std::time::Duration::MAX));
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            output = &mut run => {
+                if let (Ok(_), Some(guard)) = (&output, guard) {
+                    guard.finish();
+                }
+                return output.map_err(ExecutionError::IoError);
+            }
+            _ = &mut ctrl_c => return Err(ExecutionError::Aborted),
+            _ = &mut deadline, if timeout.is_some() => return Err(ExecutionError::TimedOut(timeout.map_or(0, |t| t.as_secs()))),
+            _ = &mut input_hint, if !hinted => {
+                hinted = true;
+                eprintln!(
+                    "INFO: Still running after {}s. If this command is waiting for input, press Ctrl-C to abort the step (or re-run with --attach-stdin).",
+                    INPUT_HINT_AFTER.as_secs()
+                );
+            }
+        }
+    }
+}
+
+// --- Interactive command detection ---
+// Tools that read from the terminal when invoked without their batch flags. With no stdin they
+// usually just exit, but they never do the intended work.
+pub enum InteractiveCheck {
+    // Not known to prompt
+    Ok,
+    // A known non-interactive form of the same command
+    Rewritten { command: String, change: &'static str },
+    // Known to prompt, no safe automatic fix
+    MayPrompt(&'static str),
+}
+
+pub fn check_interactive(command: &str) -> InteractiveCheck {
+    let Some(tool) = get_tool_from_command(command) else { return InteractiveCheck::Ok };
+    let tool = tool.trim_end_matches(".exe").to_lowercase();
+    let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+    let has = |flags: &[&str]| args.iter().any(|a| flags.iter().any(|f| a == f || a.starts_with(&format!("{}=", f))));
+    match tool.as_str() {
+        "apt" | "apt-get" if args.iter().any(|a| matches!(*a, "install" | "remove" | "upgrade" | "dist-upgrade")) && !has(&["-y", "--yes", "--assume-yes"]) => {
+            InteractiveCheck::Rewritten { command: insert_after_tool(command, "-y"), change: "added -y" }
+        }
+        "ssh" if !command.contains("BatchMode") => {
+            InteractiveCheck::Rewritten { command: insert_after_tool(command, "-o BatchMode=yes"), change: "added -o BatchMode=yes (no password prompts)" }
+        }
+        "msfconsole" if !has(&["-x", "-r", "--execute-command", "--resource"]) => {
+            InteractiveCheck::MayPrompt("msfconsole opens a console; pass commands with -x \"...; exit\" or -r <resource file>")
+        }
+        "ftp" | "telnet" => InteractiveCheck::MayPrompt("opens an interactive session"),
+        "mysql" if !has(&["-e", "--execute"]) => InteractiveCheck::MayPrompt("opens a SQL shell without -e"),
+        "psql" if !has(&["-c", "--command", "-f", "--file"]) => InteractiveCheck::MayPrompt("opens a SQL shell without -c/-f"),
+        "smbclient" if !has(&["-c", "--command", "-L", "--list"]) => InteractiveCheck::MayPrompt("opens an smb: prompt without -c or -L"),
+        "python" | "python3" | "irb" | "node" if args.is_empty() => InteractiveCheck::MayPrompt("starts a REPL"),
+        "nc" | "ncat" | "netcat" if !has(&["-z"]) => InteractiveCheck::MayPrompt("keeps the connection open reading stdin unless -z is given"),
+        _ => InteractiveCheck::Ok,
+    }
+}
+
+// --- Org-wide tool defaults (tool_defaults) ---
+// Inserts the configured args (e.g. nmap = "--max-rate 100") right after the tool in every stage
+// of the command that runs it. A default flag the stage already sets is left out together with its
+// value. None when nothing was added.
+pub fn apply_tool_defaults(command: &str, defaults: &HashMap<String, String>) -> Option<String> {
+    if defaults.is_empty() {
+        return None;
+    }
+    let mut result = String::with_capacity(command.len());
+    let mut last = 0;
+    for separator in SEPARATOR_RE.find_iter(command) {
+        result.push_str(&stage_with_defaults(&command[last..separator.start()], defaults));
+        result.push_str(separator.as_str());
+        last = separator.end();
+    }
+    result.push_str(&stage_with_defaults(&command[last..], defaults));
+    (result != command).then_some(result)
+}
+
+fn stage_with_defaults(stage: &str, defaults: &HashMap<String, String>) -> String {
+    let Some(tool) = get_tool_from_command(stage) else { return stage.to_string() };
+    let tool = tool.to_lowercase();
+    let tool = tool.strip_suffix(".exe").unwrap_or(&tool);
+    let Some(args) = defaults.iter().find(|(name, _)| name.eq_ignore_ascii_case(tool)).map(|(_, args)| args) else {
+        return stage.to_string();
+    };
+    let present: Vec<&str> = stage.split_whitespace().skip(1).filter(|word| word.starts_with('-')).map(flag_name).collect();
+    let mut kept = Vec::new();
+    let mut skipping = false;
+    for word in args.split_whitespace() {
+        if word.starts_with('-') {
+            skipping = present.contains(&flag_name(word));
+        }
+        if !skipping {
+            kept.push(word);
+        }
+    }
+    if kept.is_empty() {
+        return stage.to_string();
+    }
+    let leading = &stage[..stage.len() - stage.trim_start().len()];
+    format!("{}{}", leading, insert_after_tool(stage, &kept.join(" ")))
+}
+
+// The flag a word sets: "--max-rate=100" -> "--max-rate", and a short flag with a numeric value
+// attached ("-T4", "-p22") -> "-T"/"-p", so -T2 and -T4 count as the same setting
+fn flag_name(word: &str) -> &str {
+    let flag = word.split('=').next().unwrap_or(word);
+    let short_with_number = !flag.starts_with("--") && flag.len() > 2 && flag[2..].chars().all(|c| c.is_ascii_digit());
+    if short_with_number && flag.is_char_boundary(2) {
+        &flag[..2]
+    } else {
+        flag
+    }
+}
+
+// "tool rest" -> "tool <flags> rest"
+pub fn insert_after_tool(command: &str, flags: &str) -> String {
+    let trimmed = command.trim_start();
+    match trimmed.split_once(char::is_whitespace) {
+        Some((tool, rest)) => format!("{} {} {}", tool, flags, rest.trim_start()),
+        None => format!("{} {}", trimmed, flags),
+    }
+}
+
+// --- Output streaming ---
+// Prints stdout lines as they arrive (stderr straight to stderr) while keeping everything for
+// parsing. Past the display cap the middle is held back and only the tail is shown at the end,
+// matching the non-streamed excerpt.
+struct LiveDisplay {
+    head: usize,
+    tail: usize,
+    shown: usize,
+    held: std::collections::VecDeque<String>,
+    hidden: usize,
+}
+
+impl LiveDisplay {
+    fn new(max_lines: usize) -> Self {
+        let head = if max_lines == 0 { usize::MAX } else { max_lines.div_ceil(2) };
+        LiveDisplay { head, tail: max_lines - max_lines.div_ceil(2), shown: 0, held: Default::default(), hidden: 0 }
+    }
+
+    fn line(&mut self, line: &[u8]) {
+        let text = String::from_utf8_lossy(line).trim_end_matches('\r').to_string();
+        if self.shown < self.head {
+            println!("{}", text);
+            self.shown += 1;
+            return;
+        }
+        self.held.push_back(text);
+        if self.held.len() > self.tail {
+            self.held.pop_front();
+            self.hidden += 1;
+        }
+    }
+
+    fn finish(self) {
+        if self.hidden > 0 {
+            println!("... {} lines hidden (full output is kept in the summary and saved files) ...", self.hidden);
+        }
+        for line in self.held {
+            println!("{}", line);
+        }
+    }
+}
+
+async fn stream_output(mut child: tokio::process::Child, max_lines: usize) -> std::io::Result<StdOutput> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    let mut stdout_lines = BufReader::new(child.stdout.take().expect("stdout is piped")).split(b'\n');
+    let mut stderr_lines = BufReader::new(child.stderr.take().expect("stderr is piped")).split(b'\n');
+    let mut display = LiveDisplay::new(max_lines);
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    let (mut stdout_done, mut stderr_done) = (false, false);
+    while !(stdout_done && stderr_done) {
+        tokio::select! {
+            line = stdout_lines.next_segment(), if !stdout_done => match line? {
+                Some(line) => {
+                    display.line(&line);
+                    stdout.extend_from_slice(&line);
+                    stdout.push(b'\n');
+                }
+                None => stdout_done = true,
+            },
+            line = stderr_lines.next_segment(), if !stderr_done => match line? {
+                Some(line) => {
+                    eprintln!("{}", String::from_utf8_lossy(&line).trim_end_matches('\r'));
+                    stderr.extend_from_slice(&line);
+                    stderr.push(b'\n');
+                }
+                None => stderr_done = true,
+            },
+        }
+    }
+    let status = child.wait().await?;
+    display.finish();
+    Ok(StdOutput { status, stdout, stderr })
+}
+
+// Blocking variant for the last stage of a Windows pipeline; stderr is drained on a helper
+// thread so a chatty stderr can't fill its pipe and stall the child
+fn stream_blocking(mut child: std::process::Child, stdout_pipe: std::process::ChildStdout, max_lines: usize) -> std::io::Result<StdOutput> {
+    use std::io::{BufRead, BufReader, Read};
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let stderr_reader = std::thread::spawn(move || {
+        let mut stderr = Vec::new();
+        stderr_pipe.read_to_end(&mut stderr).map(|_| stderr)
+    });
+    let mut display = LiveDisplay::new(max_lines);
+    let mut stdout = Vec::new();
+    for line in BufReader::new(stdout_pipe).split(b'\n') {
+        let line = line?;
+        display.line(&line);
+        stdout.extend_from_slice(&line);
+        stdout.push(b'\n');
+    }
+    let status = child.wait()?;
+    display.finish();
+    let stderr = stderr_reader.join().map_err(|_| std::io::Error::other("stderr reader panicked"))??;
+    Ok(StdOutput { status, stdout, stderr })
+}
+
+// True if Ctrl-C is pressed again within `window` (used to escalate a step abort to the whole run)
+pub async fn ctrl_c_within(window: std::time::Duration) -> bool {
+    tokio::time::timeout(window, tokio::signal::ctrl_c()).await.is_ok()
+}
+
+// Separates stdout from stderr in successful output when include_stderr is on
+pub const STDERR_MARKER: &str = "--- stderr ---";
+
+// --- Shared output handling: non-zero exit becomes CommandFailure, success returns stdout ---
+// (plus non-empty stderr under STDERR_MARKER when `include_stderr` is set)
+fn process_output(output_result: Result<StdOutput, ExecutionError>, include_stderr: bool) -> Result<String, ExecutionError> {
     match output_result {
         Ok(output) => { // output here is std::process::Output
             if !output.status.success() {
@@ -179,8 +1025,239 @@ pub async fn execute_command(command: &str, setup: &SystemSetup) -> Result<Strin
                 let error_message = if stderr_output.trim().is_empty() { format!("Command failed with status {}. Output:\n{}", output.status, stdout_output) }
                                   else { format!("Command failed with status {}. Error:\n{}", output.status, stderr_output) };
                 Err(ExecutionError::CommandFailure(error_message))
-            } else { Ok(String::from_utf8_lossy(&output.stdout).into_owned()) }
+            } else {
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                let stderr_output = String::from_utf8_lossy(&output.stderr);
+                if include_stderr && !stderr_output.trim().is_empty() {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    text.push_str(&format!("{}\n{}", STDERR_MARKER, stderr_output));
+                }
+                Ok(text)
+            }
         }
         Err(e) => Err(e), // Pass through any IoError or BlockingTaskError from above
     }
-}
\ No newline at end of file
+}
+
+// Tools that need Administrator on Windows for their usual invocations
+const WINDOWS_PRIVILEGED_TOOLS: &[&str] = &["netsh", "sc", "bcdedit", "diskpart", "pktmon", "wevtutil"];
+
+// --- Heuristic for commands that need Administrator on Windows ---
+// Known privileged tools, nmap raw-socket/OS scans, or a failure that says elevation was required
+pub fn needs_elevation(command: &str, failure: Option<&str>) -> bool {
+    if let Some(message) = failure {
+        let lower = message.to_lowercase();
+        return lower.contains("requires elevation")
+            || lower.contains("access is denied")
+            || lower.contains("run as administrator")
+            || lower.contains("administrator privileges");
+    }
+    let tool = get_tool_from_command(command).unwrap_or_default().to_lowercase();
+    let tool = tool.strip_suffix(".exe").unwrap_or(&tool);
+    WINDOWS_PRIVILEGED_TOOLS.contains(&tool)
+        || (tool == "nmap" && command.split_whitespace().any(|a| a == "-sS" || a == "-O" || a == "-sU"))
+}
+
+// Tools that need root on Linux for their usual invocations
+const LINUX_PRIVILEGED_TOOLS: &[&str] = &[
+    "tcpdump", "masscan", "arp-scan", "netdiscover", "hping3", "iptables", "nft", "airmon-ng", "airodump-ng",
+    "aireplay-ng", "responder", "ettercap", "bettercap", "macchanger",
+];
+
+// --- Heuristic for commands that need root on Linux ---
+// Known privileged tools and nmap raw-socket/OS scans; already-sudo'd commands are left alone
+pub fn needs_root(command: &str) -> bool {
+    let tool = get_tool_from_command(command).unwrap_or_default().to_lowercase();
+    if tool == "sudo" {
+        return false;
+    }
+    LINUX_PRIVILEGED_TOOLS.contains(&tool.as_str())
+        || (tool == "nmap" && command.split_whitespace().any(|a| a == "-sS" || a == "-O" || a == "-sU"))
+}
+
+// --- execute_command_elevated function ---
+// Re-launches the command through a UAC prompt (ShellExecuteExW "runas").
+// The elevated process can't share our pipes, so stdout/stderr are redirected to temp files and read back.
+// Ctrl-C or `timeout` terminates it, as for any other step.
+pub async fn execute_command_elevated(command: &str, include_stderr: bool, timeout: Option<std::time::Duration>) -> Result<String, ExecutionError> {
+    #[cfg(windows)]
+    {
+        use std::sync::atomic::AtomicBool;
+        println!("Requesting elevation (UAC) for this step.");
+        let command_clone = command.to_string();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let thread_cancel = Arc::clone(&cancel);
+        let elevated = task::spawn_blocking(move || run_elevated_windows(&command_clone, &thread_cancel));
+        tokio::pin!(elevated);
+        let deadline = tokio::time::sleep(timeout.unwrap_or(std::time::Duration::MAX));
+        tokio::pin!(deadline);
+        let stopped = tokio::select! {
+            joined = &mut elevated => {
+                let output_result = joined
+                    .map_err(|e| ExecutionError::BlockingTaskError(format!("Blocking task failed: {}", e)))
+                    .and_then(|result| result.map_err(ExecutionError::IoError));
+                return process_output(output_result, include_stderr);
+            }
+            _ = tokio::signal::ctrl_c() => ExecutionError::Aborted,
+            _ = &mut deadline, if timeout.is_some() => ExecutionError::TimedOut(timeout.map_or(0, |t| t.as_secs())),
+        };
+        // The thread notices within one wait slice (once any UAC prompt is answered) and kills the process
+        cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        let _ = elevated.await;
+        Err(stopped)
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (include_stderr, timeout);
+        Err(ExecutionError::UnsupportedPlatform(format!("UAC elevation is Windows-only (command: {})", command)))
+    }
+}
+
+#[cfg(windows)]
+fn run_elevated_windows(command: &str, cancel: &std::sync::atomic::AtomicBool) -> std::io::Result<StdOutput> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetExitCodeProcess, GetProcessId, TerminateProcess};
+    use winapi::um::shellapi::{ShellExecuteExW, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW};
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::winbase::{WAIT_FAILED, WAIT_OBJECT_0};
+    use winapi::um::winuser::SW_HIDE;
+
+    let wide = |s: &str| OsStr::new(s).encode_wide().chain(Some(0)).collect::<Vec<u16>>();
+    // Unpredictable names, created by us; deleted when these are dropped
+    let temp_file = |suffix: &str| tempfile::Builder::new().prefix("hacker-rs-elevated-").suffix(suffix).tempfile().map(|file| file.into_temp_path());
+    let out_path = temp_file(".out")?;
+    let err_path = temp_file(".err")?;
+
+    // /S strips exactly the outer quotes, so the inner quoted redirect targets survive
+    let params = format!("/S /C \"{} 1> \"{}\" 2> \"{}\"\"", command, out_path.display(), err_path.display());
+    let verb = wide("runas");
+    let file = wide("cmd.exe");
+    let params_w = wide(&params);
+
+    let mut info: SHELLEXECUTEINFOW = unsafe { std::mem::zeroed() };
+    info.cbSize = std::mem::size_of::<SHELLEXECUTEINFOW>() as u32;
+    info.fMask = SEE_MASK_NOCLOSEPROCESS;
+    info.lpVerb = verb.as_ptr();
+    info.lpFile = file.as_ptr();
+    info.lpParameters = params_w.as_ptr();
+    info.nShow = SW_HIDE;
+
+    // Fails with ERROR_CANCELLED if the user declines the UAC prompt
+    if unsafe { ShellExecuteExW(&mut info) } == 0 || info.hProcess.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // Waited for in slices so a timeout or Ctrl-C (`cancel`) can stop it
+    let mut exit_code: u32 = 1;
+    let cancelled = loop {
+        match unsafe { WaitForSingleObject(info.hProcess, ELEVATED_WAIT_SLICE_MS) } {
+            WAIT_OBJECT_0 | WAIT_FAILED => break false,
+            _ if cancel.load(std::sync::atomic::Ordering::Relaxed) => {
+                // Its tree first (taskkill may lack the rights for an elevated one), then the shell itself
+                signal_process_tree(unsafe { GetProcessId(info.hProcess) }, false, true);
+                unsafe { TerminateProcess(info.hProcess, 1) };
+                break true;
+            }
+            _ => {}
+        }
+    };
+    unsafe {
+        GetExitCodeProcess(info.hProcess, &mut exit_code);
+        CloseHandle(info.hProcess);
+    }
+    if cancelled {
+        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Elevated command was stopped"));
+    }
+
+    let stdout = std::fs::read(&out_path).unwrap_or_default();
+    let stderr = std::fs::read(&err_path).unwrap_or_default();
+
+    Ok(StdOutput { status: ExitStatus::from_raw(exit_code), stdout, stderr })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn finished_group_stays_registered_while_its_background_job_runs() {
+        use std::os::unix::process::CommandExt;
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "sleep 30 >/dev/null 2>&1 & exit 0"])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let guard = SpawnGuard::track(Some(pid), true).unwrap();
+        child.wait().unwrap();
+        guard.finish();
+        assert!(spawned().contains_key(&pid));
+        // The leader is gone, but the group (and the sleep in it) can still be signalled
+        assert!(signal_process_tree(pid, true, true));
+        spawned().remove(&pid);
+    }
+
+    #[test]
+    fn parse_command_words_flags_quoted_arguments() {
+        let (command, args) = parse_command_words(r#"findstr "a*b" *.txt x"y"z"#).unwrap();
+        assert_eq!(command, "findstr");
+        assert_eq!(args, vec![("a*b".to_string(), true), ("*.txt".to_string(), false), ("xyz".to_string(), true)]);
+    }
+
+    #[test]
+    fn quote_arg_for_sh() {
+        assert_eq!(quote_arg("10.0.0.5", false), "10.0.0.5");
+        assert_eq!(quote_arg("", false), "''");
+        assert_eq!(quote_arg("my payload", false), "'my payload'");
+        assert_eq!(quote_arg("it's; $(id)", false), r"'it'\''s; $(id)'");
+    }
+
+    #[test]
+    fn quote_arg_for_cmd() {
+        assert_eq!(quote_arg("C:\\tools\\nc.exe", true), "C:\\tools\\nc.exe");
+        assert_eq!(quote_arg("my payload", true), "\"my payload\"");
+        assert_eq!(quote_arg("a\"b&c", true), "\"a\"\"b&c\"");
+        assert_eq!(quote_arg("100%", true), "\"100\"^%\"\"");
+        assert_eq!(quote_arg("C:\\my dir\\", true), "\"C:\\my dir\\\\\"");
+    }
+
+    #[test]
+    fn quote_arg_for_cmd_round_trips_through_the_pipeline_parser() {
+        for value in ["my payload", "a\"b&c", "100% sure", "x | y", "%PATH%", "^caret"] {
+            let (_, args) = parse_command_words(&format!("tool {}", quote_arg(value, true))).unwrap();
+            assert_eq!(args.into_iter().map(|(arg, _)| arg).collect::<Vec<_>>(), vec![value.to_string()], "{}", value);
+        }
+    }
+
+    #[test]
+    fn expand_globs_expands_only_unquoted_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "c.log"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let pattern = dir.path().join("*.txt").display().to_string();
+        let expanded = expand_globs(vec![(pattern.clone(), false)]);
+        let names: Vec<String> = expanded.iter().map(|p| Path::new(p).file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+
+        assert_eq!(expand_globs(vec![(pattern.clone(), true)]), vec![pattern]);
+        let quoted_single = format!("'{}'", dir.path().join("*.txt").display());
+        assert_eq!(expand_globs(vec![(quoted_single.clone(), false)]), vec![quoted_single]);
+    }
+
+    #[test]
+    fn expand_globs_keeps_literals_without_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let unmatched = dir.path().join("*.none").display().to_string();
+        let invalid = dir.path().join("[").display().to_string();
+        let args = vec![("-n".to_string(), false), (unmatched.clone(), false), (invalid.clone(), false)];
+        assert_eq!(expand_globs(args), vec!["-n".to_string(), unmatched, invalid]);
+    }
+}
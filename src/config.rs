@@ -3,8 +3,10 @@ use anyhow::{Context, Result};
 use directories_next::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use shellexpand;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 // --- ModelConfig struct ---
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -18,25 +20,175 @@ pub struct ModelConfig {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AdvancedConfig {
     pub qwen_formatting: Option<bool>,
+    // Pull IPs/CIDRs out of the query text into discovered values (default: true)
+    pub query_preparse: Option<bool>,
+    // Only treat a pre-parsed IP/CIDR as a target when a word like "scan" or "host" is nearby
+    pub preparse_require_context: Option<bool>,
+    // Add `-oX` to nmap commands so results are parsed from XML (default: true)
+    pub nmap_xml: Option<bool>,
+    // Add `-i` to curl commands so the response status and headers are parsed too (default: true)
+    pub http_headers: Option<bool>,
+    // Shell for local commands: "auto" (bash, else sh; cmd on Windows), or e.g. "bash", "pwsh", "powershell"
+    pub exec_shell: Option<String>,
+    // Send the system prompt and user prompt as one raw prompt, skipping the model's template (default: false)
+    pub raw_prompt: Option<bool>,
+    // Print command output line by line while it runs (default: false); --stream turns it on
+    pub stream_output: Option<bool>,
+    // Append stderr of successful commands to their output under "--- stderr ---" (default: false)
+    pub include_stderr: Option<bool>,
+    // Ask the model to break command-less, high-level steps into concrete commands (default: false)
+    pub expand_vague_steps: Option<bool>,
+    // Carry earlier steps and the model conversation into each new query (default: true); --no-history turns it off
+    pub keep_history: Option<bool>,
 }
 
+// --- SafetyConfig struct ---
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SafetyConfig {
+    // When non-empty, only these tools may be executed
+    pub allowed_tools: Option<Vec<String>>,
+    // When non-empty, only steps with these action types run (e.g. ["command", "note"])
+    pub allowed_actions: Option<Vec<String>>,
+    // Show the legal warning and require acknowledgment before running (default: true)
+    pub require_authorization: Option<bool>,
+    // Run plans without --execute (default: false, plans are only previewed)
+    pub auto_execute: Option<bool>,
+    // Replaces the built-in legal warning, e.g. with an org's rules-of-engagement notice
+    pub legal_warning: Option<String>,
+}
+
+// --- ScopeConfig struct ---
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScopeConfig {
+    // Hosts/CIDRs that must never be targeted; --exclude adds to these
+    pub exclude: Option<Vec<String>>,
+    // Ports that must never be targeted; --exclude-ports adds to these
+    pub exclude_ports: Option<Vec<String>>,
+    // Refuse to expand or scan a CIDR with more usable hosts than this (default: DEFAULT_MAX_CIDR_HOSTS, 0 = no limit)
+    pub max_cidr_hosts: Option<u64>,
+}
+
+// --- SetupConfig struct ---
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SetupConfig {
+    // Expected SHA-256 (hex) of https://ollama.com/install.sh; download is refused on mismatch
+    pub ollama_script_sha256: Option<String>,
+    // Expected SHA-256 (hex) of OllamaSetup.exe; download is refused on mismatch
+    pub ollama_installer_sha256: Option<String>,
+}
+
+// --- TimeoutsConfig struct ---
+// All in seconds; 0 turns that timeout off
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TimeoutsConfig {
+    // How long one model request may take (default: DEFAULT_GEN_TIMEOUT_SECS)
+    pub gen_timeout_secs: Option<u64>,
+    // How long one command may run before it is killed (default: DEFAULT_EXEC_TIMEOUT_SECS)
+    pub exec_timeout_secs: Option<u64>,
+    // Deadline for a whole query/replay; remaining steps are skipped once it passes (default: 0, none)
+    pub run_timeout_secs: Option<u64>,
+}
+
+pub const DEFAULT_GEN_TIMEOUT_SECS: u64 = 300;
+pub const DEFAULT_EXEC_TIMEOUT_SECS: u64 = 1800;
+pub const DEFAULT_MAX_CIDR_HOSTS: u64 = 1024;
+
+// Where Kali and SecLists put the wordlists the system prompt names; a name missing from
+// [wordlists] uses the first of these that exists
+pub const DEFAULT_WORDLISTS: &[(&str, &[&str])] = &[
+    ("common", &["/usr/share/wordlists/dirb/common.txt", "/usr/share/dirb/wordlists/common.txt", "/usr/share/seclists/Discovery/Web-Content/common.txt"]),
+    ("passwords", &["/usr/share/wordlists/rockyou.txt", "/usr/share/seclists/Passwords/Leaked-Databases/rockyou.txt", "/usr/share/seclists/Passwords/Common-Credentials/10k-most-common.txt"]),
+    ("users", &["/usr/share/seclists/Usernames/top-usernames-shortlist.txt", "/usr/share/wordlists/metasploit/unix_users.txt", "/usr/share/metasploit-framework/data/wordlists/unix_users.txt"]),
+];
+
 // --- AppConfig struct ---
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AppConfig {
     pub model: ModelConfig,
     pub ollama_host: Option<String>,
+    // Inline system prompt template ({OS} still substituted); wins over every prompt file
+    pub system_prompt: Option<String>,
+    // System prompt file to use instead of a profile's or <config_dir>/system_prompt.txt
+    pub system_prompt_path: Option<String>,
+    // Host every query targets unless it names an IP/CIDR itself; --target overrides
+    pub default_target: Option<String>,
+    // tool -> args added to every command that runs it, unless the command sets the same flag
+    pub tool_defaults: Option<HashMap<String, String>>,
+    // Logical wordlist name -> file, used by {wordlist:<name>} placeholders (e.g. common, passwords)
+    pub wordlists: Option<HashMap<String, String>>,
+    // Program run after every plan step with its number, command and status (args and HACKER_RS_* env)
+    pub post_step_hook: Option<String>,
     pub advanced: Option<AdvancedConfig>,
+    pub safety: Option<SafetyConfig>,
+    pub scope: Option<ScopeConfig>,
+    pub setup: Option<SetupConfig>,
+    pub timeouts: Option<TimeoutsConfig>,
 }
 
+// Project-local override picked up from the working directory
+pub const LOCAL_CONFIG_FILENAME: &str = "hacker-rs.toml";
+
 impl AppConfig {
     pub fn from_file(path: &str) -> Result<Self> {
-        let expanded_path = shellexpand::tilde(path);
+        Self::from_layers(&[PathBuf::from(path)])
+    }
+
+    // Loads and deep-merges config files; later layers override individual fields of earlier ones.
+    // Only the merged result has to be a complete config.
+    pub fn from_layers(paths: &[PathBuf]) -> Result<Self> {
+        if paths.is_empty() {
+            anyhow::bail!("No config files to load");
+        }
+        let mut merged = toml::Value::Table(toml::map::Map::new());
+        for path in paths {
+            merge_toml(&mut merged, Self::read_layer(path)?);
+        }
+        merged
+            .try_into()
+            .context("Merged configuration is invalid (is [model] name set in some layer?)")
+    }
+
+    // Precedence, lowest to highest:
+    //   1. user config (default_path(), e.g. ~/.config/hacker-rs/config.toml)
+    //   2. ./hacker-rs.toml in the working directory
+    //   3. each --config file, in the order given
+    // Implicit layers are skipped when missing; explicit ones must exist.
+    pub fn layer_paths(explicit: &[PathBuf]) -> Vec<PathBuf> {
+        Self::layer_paths_with_user(&Self::default_path(), explicit)
+    }
+
+    // Same, with the user config somewhere other than default_path (--config-dir, fallback dir)
+    pub fn layer_paths_with_user(user_config: &Path, explicit: &[PathBuf]) -> Vec<PathBuf> {
+        let mut layers: Vec<PathBuf> = [user_config.to_path_buf(), PathBuf::from(LOCAL_CONFIG_FILENAME)]
+            .into_iter()
+            .filter(|p| p.exists())
+            .collect();
+        layers.extend(explicit.iter().cloned());
+        layers
+    }
+
+    // Format follows the extension: .yaml/.yml and .json, anything else is TOML.
+    // Every layer becomes a toml::Value so layers of different formats merge the same way.
+    fn read_layer(path: &Path) -> Result<toml::Value> {
+        let display = path.display().to_string();
+        let expanded_path = shellexpand::tilde(&display);
         // Now .context() should work because the Context trait is in scope
         let config_str = fs::read_to_string(expanded_path.as_ref())
-            .context(format!("Failed to read config file: {}", path))?;
-        let config: AppConfig = toml::from_str(&config_str)
-            .context(format!("Failed to parse TOML from config file: {}", path))?;
-        Ok(config)
+            .context(format!("Failed to read config file: {}", display))?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        let value: serde_json::Value = match extension.as_str() {
+            "yaml" | "yml" => serde_yaml::from_str(&config_str)
+                .context(format!("Failed to parse YAML from config file: {}", display))?,
+            "json" => serde_json::from_str(&config_str)
+                .context(format!("Failed to parse JSON from config file: {}", display))?,
+            _ => {
+                return toml::from_str(&config_str)
+                    .context(format!("Failed to parse TOML from config file: {}", display))
+            }
+        };
+        // TOML has no null; an explicit null just means "not set in this layer"
+        toml::Value::try_from(strip_nulls(value))
+            .context(format!("Config file {} has values TOML can't represent", display))
     }
 
     pub fn default_path() -> PathBuf {
@@ -46,28 +198,221 @@ impl AppConfig {
             .join("config.toml")
     }
 
+    // Writes model.name and/or ollama_host into a TOML config file, keeping its other settings
+    // (comments are not preserved)
+    pub fn save_model_settings(path: &Path, model: Option<&str>, host: Option<&str>) -> Result<()> {
+        let display = path.display();
+        let text = fs::read_to_string(path).context(format!("Failed to read {}", display))?;
+        let mut value: toml::Value = toml::from_str(&text).context(format!("Failed to parse {}", display))?;
+        let table = value.as_table_mut().context(format!("{} is not a TOML table", display))?;
+        if let Some(model) = model {
+            let model_table = table
+                .entry("model")
+                .or_insert_with(|| toml::Value::Table(toml::map::Map::new()))
+                .as_table_mut()
+                .context(format!("[model] in {} is not a table", display))?;
+            model_table.insert("name".to_string(), toml::Value::String(model.to_string()));
+        }
+        if let Some(host) = host {
+            table.insert("ollama_host".to_string(), toml::Value::String(host.to_string()));
+        }
+        fs::write(path, toml::to_string_pretty(&value)?).context(format!("Failed to write {}", display))?;
+        Ok(())
+    }
+
+    // --- Timeouts --- None when set to 0
+    pub fn gen_timeout(&self) -> Option<Duration> {
+        Self::timeout_secs(self.timeouts.as_ref().and_then(|t| t.gen_timeout_secs), DEFAULT_GEN_TIMEOUT_SECS)
+    }
+
+    pub fn exec_timeout(&self) -> Option<Duration> {
+        Self::timeout_secs(self.timeouts.as_ref().and_then(|t| t.exec_timeout_secs), DEFAULT_EXEC_TIMEOUT_SECS)
+    }
+
+    pub fn run_timeout(&self) -> Option<Duration> {
+        Self::timeout_secs(self.timeouts.as_ref().and_then(|t| t.run_timeout_secs), 0)
+    }
+
+    fn timeout_secs(configured: Option<u64>, default: u64) -> Option<Duration> {
+        Some(configured.unwrap_or(default)).filter(|secs| *secs > 0).map(Duration::from_secs)
+    }
+
+    // --- Wordlists --- configured files (~ expanded), plus detected defaults for the names left out
+    pub fn wordlists(&self) -> HashMap<String, PathBuf> {
+        let mut wordlists: HashMap<String, PathBuf> = self
+            .wordlists
+            .iter()
+            .flatten()
+            .map(|(name, path)| (name.clone(), PathBuf::from(shellexpand::tilde(path).as_ref())))
+            .collect();
+        for (name, candidates) in DEFAULT_WORDLISTS {
+            if wordlists.contains_key(*name) {
+                continue;
+            }
+            if let Some(found) = candidates.iter().map(PathBuf::from).find(|path| path.is_file()) {
+                wordlists.insert(name.to_string(), found);
+            }
+        }
+        wordlists
+    }
+
+    // --- CIDR size limit --- None when set to 0
+    pub fn max_cidr_hosts(&self) -> Option<u64> {
+        Some(self.scope.as_ref().and_then(|s| s.max_cidr_hosts).unwrap_or(DEFAULT_MAX_CIDR_HOSTS)).filter(|max| *max > 0)
+    }
+
     pub fn generate_default_config() -> Result<()> {
-        let default_path = Self::default_path();
+        Self::generate_default_config_at(&Self::default_path())
+    }
+
+    pub fn generate_default_config_at(default_path: &Path) -> Result<()> {
         let default_dir = default_path
             .parent()
             .ok_or_else(|| anyhow::anyhow!("Invalid default config path parent"))?;
 
         std::fs::create_dir_all(default_dir)?;
 
-        let default_config = AppConfig {
+        let toml = toml::to_string_pretty(&Self::defaults())?;
+        std::fs::write(default_path, toml)?;
+        Ok(())
+    }
+
+    // What a freshly generated config.toml contains
+    pub fn defaults() -> Self {
+        AppConfig {
             model: ModelConfig {
                 name: "phi4-mini:latest".to_string(),
                 temperature: Some(0.7),
                 max_tokens: Some(1000),
             },
             ollama_host: Some("http://localhost:11434".to_string()),
+            system_prompt: None,
+            system_prompt_path: None,
+            default_target: None,
+            tool_defaults: None,
+            post_step_hook: None,
+            wordlists: None,
             advanced: Some(AdvancedConfig {
                 qwen_formatting: Some(true),
+                query_preparse: Some(true),
+                preparse_require_context: Some(false),
+                nmap_xml: Some(true),
+                http_headers: Some(true),
+                exec_shell: Some("auto".to_string()),
+                raw_prompt: Some(false),
+                stream_output: Some(false),
+                include_stderr: Some(false),
+                expand_vague_steps: Some(false),
+                keep_history: Some(true),
             }),
-        };
+            safety: Some(SafetyConfig {
+                allowed_tools: Some(Vec::new()),
+                allowed_actions: Some(Vec::new()),
+                require_authorization: Some(true),
+                auto_execute: Some(false),
+                legal_warning: None,
+            }),
+            scope: Some(ScopeConfig { exclude: None, exclude_ports: None, max_cidr_hosts: Some(DEFAULT_MAX_CIDR_HOSTS) }),
+            setup: None,
+            timeouts: Some(TimeoutsConfig {
+                gen_timeout_secs: Some(DEFAULT_GEN_TIMEOUT_SECS),
+                exec_timeout_secs: Some(DEFAULT_EXEC_TIMEOUT_SECS),
+                run_timeout_secs: Some(0),
+            }),
+        }
+    }
+}
 
-        let toml = toml::to_string_pretty(&default_config)?;
-        std::fs::write(&default_path, toml)?;
-        Ok(())
+// --- EffectiveConfig ---
+// Every resolved setting (dotted key) with the layer that decided it: "default", a config file
+// path, or a flag the caller records with `set`. Arrays count as single values, like in merging.
+#[derive(Debug, Clone, Default)]
+pub struct EffectiveConfig {
+    pub entries: BTreeMap<String, (toml::Value, String)>,
+}
+
+impl EffectiveConfig {
+    // Same layers and precedence as AppConfig::from_layers, on top of the generated defaults
+    pub fn resolve(paths: &[PathBuf]) -> Result<Self> {
+        // Fails the same way a real run would if the merged config is invalid
+        AppConfig::from_layers(paths)?;
+        let mut effective = EffectiveConfig::default();
+        let defaults = toml::Value::try_from(AppConfig::defaults()).context("Failed to serialize default config")?;
+        flatten_toml("", &defaults, &mut |key, value| {
+            // The f32 fields (temperature) would otherwise show as 0.699999988079071
+            let value = match value {
+                toml::Value::Float(f) => toml::Value::Float((*f as f32).to_string().parse().unwrap_or(*f)),
+                other => other.clone(),
+            };
+            effective.set(key, value, "default");
+        });
+        for path in paths {
+            let layer = AppConfig::read_layer(path)?;
+            let source = path.display().to_string();
+            flatten_toml("", &layer, &mut |key, value| {
+                effective.set(key, value.clone(), source.clone());
+            });
+        }
+        Ok(effective)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&toml::Value> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    pub fn set(&mut self, key: &str, value: toml::Value, source: impl Into<String>) {
+        self.entries.insert(key.to_string(), (value, source.into()));
     }
-}
\ No newline at end of file
+
+    // `key = value  # source`, one per line, keys aligned
+    pub fn to_text(&self) -> String {
+        let rendered: Vec<(String, &String)> =
+            self.entries.iter().map(|(key, (value, source))| (format!("{} = {}", key, value), source)).collect();
+        let width = rendered.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+        rendered.iter().map(|(line, source)| format!("{:<width$}  # {}", line, source, width = width)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+// Calls `visit` with the dotted key of every non-table value
+fn flatten_toml(prefix: &str, value: &toml::Value, visit: &mut dyn FnMut(&str, &toml::Value)) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, child) in table {
+                let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                flatten_toml(&path, child, visit);
+            }
+        }
+        leaf => visit(prefix, leaf),
+    }
+}
+
+// Removes null object members (recursively) before converting YAML/JSON to TOML
+fn strip_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, strip_nulls(v)))
+                .collect(),
+        ),
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.into_iter().map(strip_nulls).collect()),
+        other => other,
+    }
+}
+
+// --- Deep merge helper: tables merge key-by-key, anything else is replaced ---
+fn merge_toml(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base_table), toml::Value::Table(overlay_table)) => {
+            for (key, value) in overlay_table {
+                match base_table.get_mut(&key) {
+                    Some(existing) => merge_toml(existing, value),
+                    None => {
+                        base_table.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
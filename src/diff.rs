@@ -0,0 +1,179 @@
+// src/diff.rs
+use crate::report::RunReport;
+use crate::session::SessionState;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+// --- RunSnapshot ---
+// The comparable part of a saved run report (`run --format json`) or session file
+#[derive(Debug, Clone, Default)]
+pub struct RunSnapshot {
+    pub discovered_values: BTreeMap<String, String>,
+    // (step number, command, output) in execution order
+    pub steps: Vec<(u32, String, String)>,
+}
+
+impl RunSnapshot {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+        if let Ok(report) = serde_json::from_str::<RunReport>(&text) {
+            let steps = report.steps.into_iter().map(|s| (s.step, s.command, s.output)).collect();
+            return Ok(RunSnapshot { discovered_values: report.discovered_values, steps });
+        }
+        match serde_json::from_str::<SessionState>(&text) {
+            Ok(state) => {
+                let steps = state.command_history.into_iter().map(|e| (e.step, e.command, e.output)).collect();
+                Ok(RunSnapshot { discovered_values: state.discovered_values, steps })
+            }
+            Err(e) => Err(anyhow!("{} is neither a JSON run report nor a session file: {}", path.display(), e)),
+        }
+    }
+}
+
+// --- ReportDiff ---
+#[derive(Debug, Clone, Serialize)]
+pub struct ValueChange {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortChange {
+    pub host: String,
+    pub opened: Vec<String>,
+    pub closed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepChange {
+    pub step: u32,
+    pub command: String,
+    // Set when the step at this position ran a different command before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_command: Option<String>,
+    pub removed_lines: Vec<String>,
+    pub added_lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReportDiff {
+    pub values: Vec<ValueChange>,
+    pub ports: Vec<PortChange>,
+    pub steps: Vec<StepChange>,
+}
+
+impl ReportDiff {
+    // Port facts (host_<ip>_ports, open_ports) are compared as port sets, everything else by value.
+    // Steps are paired by position; their outputs are compared line by line (as sets, so reordered
+    // lines don't count).
+    pub fn between(old: &RunSnapshot, new: &RunSnapshot) -> Self {
+        let keys: BTreeSet<&String> = old.discovered_values.keys().chain(new.discovered_values.keys()).collect();
+        let mut diff = ReportDiff::default();
+        for key in keys {
+            let (before, after) = (old.discovered_values.get(key), new.discovered_values.get(key));
+            if before == after {
+                continue;
+            }
+            if let Some(host) = port_fact_host(key) {
+                let ports = |value: Option<&String>| -> BTreeSet<String> {
+                    value.map(|v| v.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect()).unwrap_or_default()
+                };
+                let (before, after) = (ports(before), ports(after));
+                let opened: Vec<String> = after.difference(&before).cloned().collect();
+                let closed: Vec<String> = before.difference(&after).cloned().collect();
+                if !opened.is_empty() || !closed.is_empty() {
+                    diff.ports.push(PortChange { host, opened, closed });
+                }
+                continue;
+            }
+            diff.values.push(ValueChange { key: key.clone(), old: before.cloned(), new: after.cloned() });
+        }
+
+        for index in 0..old.steps.len().max(new.steps.len()) {
+            let (step, command, removed, added, old_command) = match (old.steps.get(index), new.steps.get(index)) {
+                (Some((_, old_cmd, old_out)), Some((step, cmd, out))) => {
+                    let (removed, added) = line_changes(old_out, out);
+                    (*step, cmd.clone(), removed, added, Some(old_cmd.clone()).filter(|c| c != cmd))
+                }
+                (None, Some((step, cmd, out))) => (*step, cmd.clone(), Vec::new(), non_empty_lines(out), None),
+                (Some((step, cmd, out)), None) => (*step, cmd.clone(), non_empty_lines(out), Vec::new(), None),
+                (None, None) => continue,
+            };
+            if !removed.is_empty() || !added.is_empty() || old_command.is_some() {
+                diff.steps.push(StepChange { step, command, old_command, removed_lines: removed, added_lines: added });
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.ports.is_empty() && self.steps.is_empty()
+    }
+
+    pub fn to_text(&self) -> String {
+        if self.is_empty() {
+            return "No differences.".to_string();
+        }
+        let mut lines = Vec::new();
+        if !self.values.is_empty() {
+            lines.push("Discovered values:".to_string());
+            for change in &self.values {
+                lines.push(match (&change.old, &change.new) {
+                    (None, Some(new)) => format!("  NEW     {}: {}", change.key, new),
+                    (Some(old), None) => format!("  GONE    {}: {}", change.key, old),
+                    (old, new) => format!("  CHANGED {}: {} -> {}", change.key, old.as_deref().unwrap_or(""), new.as_deref().unwrap_or("")),
+                });
+            }
+        }
+        if !self.ports.is_empty() {
+            lines.push("Ports:".to_string());
+            for change in &self.ports {
+                let mut parts = Vec::new();
+                if !change.opened.is_empty() {
+                    parts.push(format!("newly open {}", change.opened.join(",")));
+                }
+                if !change.closed.is_empty() {
+                    parts.push(format!("no longer open {}", change.closed.join(",")));
+                }
+                lines.push(format!("  {}: {}", change.host, parts.join("; ")));
+            }
+        }
+        if !self.steps.is_empty() {
+            lines.push("Step outputs:".to_string());
+            for change in &self.steps {
+                lines.push(format!("  Step {}: {}", change.step, change.command));
+                if let Some(old_command) = &change.old_command {
+                    lines.push(format!("    (was: {})", old_command));
+                }
+                lines.extend(change.removed_lines.iter().map(|l| format!("    - {}", l)));
+                lines.extend(change.added_lines.iter().map(|l| format!("    + {}", l)));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+// "host_10_0_0_5_ports" -> "10.0.0.5"; "open_ports" (single-host scans) keeps its name
+fn port_fact_host(key: &str) -> Option<String> {
+    if key == "open_ports" {
+        return Some(key.to_string());
+    }
+    key.strip_prefix("host_").and_then(|rest| rest.strip_suffix("_ports")).map(|ip| ip.replace('_', "."))
+}
+
+fn non_empty_lines(text: &str) -> Vec<String> {
+    text.lines().map(str::trim_end).filter(|l| !l.trim().is_empty()).map(str::to_string).collect()
+}
+
+// (lines only in old, lines only in new), each in its original order
+fn line_changes(old: &str, new: &str) -> (Vec<String>, Vec<String>) {
+    let (old_lines, new_lines) = (non_empty_lines(old), non_empty_lines(new));
+    let old_set: BTreeSet<&String> = old_lines.iter().collect();
+    let new_set: BTreeSet<&String> = new_lines.iter().collect();
+    let removed = old_lines.iter().filter(|l| !new_set.contains(l)).cloned().collect();
+    let added = new_lines.iter().filter(|l| !old_set.contains(l)).cloned().collect();
+    (removed, added)
+}
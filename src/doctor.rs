@@ -0,0 +1,136 @@
+// src/doctor.rs
+use hacker_rs::network::{get_default_gateway, get_default_gateway_v6};
+use hacker_rs::{OllamaClient, SystemSetup};
+use std::path::PathBuf;
+use which::which;
+
+// --- Check results ---
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Check { name, status: Status::Ok, detail: detail.into(), hint: None }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check { name, status: Status::Warn, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check { name, status: Status::Fail, detail: detail.into(), hint: Some(hint.into()) }
+    }
+}
+
+// Context length vs. the system prompt size, with the model's parameters as detail
+async fn context_check(client: &OllamaClient) -> Check {
+    let window = match client.context_window().await {
+        Ok(window) => window,
+        Err(e) => return Check::warn("Context length", format!("{:#}", e), "Check the model with `ollama show <model>`"),
+    };
+    let parameters = if window.parameters.is_empty() {
+        "no model parameters".to_string()
+    } else {
+        window.parameters.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(", ")
+    };
+    let detail = format!("{}; {}", window.describe(), parameters);
+    if window.is_sufficient() {
+        Check::ok("Context length", detail)
+    } else {
+        Check::warn(
+            "Context length",
+            detail,
+            format!("Long prompts get truncated; create a variant with `PARAMETER num_ctx {}` (ollama create) or set OLLAMA_CONTEXT_LENGTH", window.needed.next_power_of_two()),
+        )
+    }
+}
+
+// --- run_doctor function ---
+// Prints one OK/WARN/FAIL line per check; returns false if anything failed
+pub async fn run_doctor(client: &OllamaClient, setup: &SystemSetup, config_layers: &[PathBuf]) -> bool {
+    let mut checks = Vec::new();
+
+    // Config layers were already parsed to get this far
+    let layers = config_layers.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ");
+    checks.push(Check::ok("Config", format!("loaded {}", layers)));
+
+    let prompt_origin = client.system_prompt_origin();
+    checks.push(match client.system_prompt_template() {
+        Ok(prompt) if prompt.trim().is_empty() => Check::fail(
+            "System prompt",
+            format!("{} is empty", prompt_origin),
+            "Copy system_prompt.txt from the repository into the config directory",
+        ),
+        Ok(prompt) => Check::ok("System prompt", format!("{} ({} bytes)", prompt_origin, prompt.len())),
+        Err(e) => Check::fail(
+            "System prompt",
+            format!("{:#}", e),
+            "Copy system_prompt.txt from the repository into the config directory, or set system_prompt in the config",
+        ),
+    });
+
+    match client.list_models().await {
+        Ok(models) => {
+            checks.push(Check::ok("Ollama", format!("reachable at {} ({} model(s) installed)", client.host(), models.len())));
+            let wanted = client.model();
+            let wanted_tagged = if wanted.contains(':') { wanted.to_string() } else { format!("{}:latest", wanted) };
+            if models.contains(&wanted_tagged) {
+                checks.push(Check::ok("Model", format!("{} is installed", wanted)));
+                checks.push(context_check(client).await);
+            } else {
+                checks.push(Check::fail("Model", format!("{} is not installed", wanted), format!("Run `ollama pull {}` or set [model] name to one of: {}", wanted, models.join(", "))));
+            }
+        }
+        Err(e) => {
+            checks.push(Check::fail("Ollama", format!("{:#}", e), "Start it with `ollama serve` and check ollama_host / firewall (port 11434)"));
+            checks.push(Check::fail("Model", "not checked (Ollama unreachable)", "Fix the Ollama check first"));
+        }
+    }
+
+    checks.push(match get_default_gateway() {
+        Ok(Some(gateway)) => Check::ok("Default gateway", gateway),
+        Ok(None) => Check::warn("Default gateway", "not found", "Plans that target the local network may need --set default_gateway=<ip>"),
+        Err(e) => Check::warn("Default gateway", format!("{:#}", e), "Plans that target the local network may need --set default_gateway=<ip>"),
+    });
+    // Plenty of networks are IPv4-only, so a missing v6 route is not a problem
+    checks.push(match get_default_gateway_v6() {
+        Ok(Some(gateway)) => Check::ok("IPv6 gateway", gateway),
+        Ok(None) => Check::ok("IPv6 gateway", "none (IPv4-only network)"),
+        Err(e) => Check::warn("IPv6 gateway", format!("{:#}", e), "IPv6 plans may need --set default_gateway6=<ip>"),
+    });
+
+    let platform = setup.platform.to_string();
+    checks.push(match setup.package_manager() {
+        Some(manager) if which(manager).is_ok() => Check::ok("Platform", format!("{} (tools auto-install via {})", platform, manager)),
+        Some(manager) => Check::warn("Platform", format!("{} ({} not found)", platform, manager), "Missing tools must be installed manually"),
+        None => Check::warn("Platform", format!("{} (no automatic tool installs)", platform), "Install the tools your plans need manually"),
+    });
+
+    println!("hacker-rs doctor");
+    let mut healthy = true;
+    for check in &checks {
+        let label = match check.status {
+            Status::Ok => "  OK",
+            Status::Warn => "WARN",
+            Status::Fail => {
+                healthy = false;
+                "FAIL"
+            }
+        };
+        println!("[{}] {:<16} {}", label, check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            println!("       {:<16} -> {}", "", hint);
+        }
+    }
+    healthy
+}
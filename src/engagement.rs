@@ -0,0 +1,98 @@
+// src/engagement.rs
+use anyhow::{anyhow, Context, Result};
+use directories_next::UserDirs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Engagement directories live under ~/<ENGAGEMENTS_DIRNAME>/
+const ENGAGEMENTS_DIRNAME: &str = "hacker-rs";
+
+// --- Engagement ---
+// One directory per job (--engagement) holding the audit log, reports, saved plans, pcaps and the
+// session file. Named <name>-<YYYY-MM-DD> after the day it was started; later runs with the same
+// name keep using the newest such directory instead of starting a new one each day.
+#[derive(Debug, Clone)]
+pub struct Engagement {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl Engagement {
+    pub fn open(name: &str) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(anyhow!("Invalid engagement name '{}'", name));
+        }
+        let root = UserDirs::new()
+            .context("Failed to find the home directory for engagement files")?
+            .home_dir()
+            .join(ENGAGEMENTS_DIRNAME);
+        let dir = match Self::existing_dir(&root, name) {
+            Some(dir) => dir,
+            None => root.join(format!("{}-{}", name, date_stamp(unix_now()))),
+        };
+        std::fs::create_dir_all(&dir).context(format!("Failed to create engagement directory: {}", dir.display()))?;
+        Ok(Engagement { name: name.to_string(), dir })
+    }
+
+    // Newest <name>-<YYYY-MM-DD> directory (ISO dates sort by name)
+    fn existing_dir(root: &Path, name: &str) -> Option<PathBuf> {
+        let prefix = format!("{}-", name);
+        std::fs::read_dir(root)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter(|entry| {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                file_name.strip_prefix(&prefix).is_some_and(is_date_stamp)
+            })
+            .map(|entry| entry.path())
+            .max()
+    }
+
+    // Relative paths (e.g. --output report.json) land in the engagement directory
+    pub fn path_for(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.dir.join(path)
+        }
+    }
+
+    // Fresh name for a report nobody asked to save, e.g. run-2025-06-01-142530.json
+    pub fn report_path(&self, kind: &str, extension: &str) -> PathBuf {
+        let now = unix_now();
+        let time_of_day = now % 86_400;
+        self.dir.join(format!(
+            "{}-{}-{:02}{:02}{:02}.{}",
+            kind,
+            date_stamp(now),
+            time_of_day / 3600,
+            time_of_day % 3600 / 60,
+            time_of_day % 60,
+            extension
+        ))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// "YYYY-MM-DD" (UTC) for a Unix timestamp
+fn date_stamp(unix_secs: u64) -> String {
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil, inverted)
+    let days = (unix_secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn is_date_stamp(text: &str) -> bool {
+    text.len() == 10 && text.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() })
+}
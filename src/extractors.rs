@@ -0,0 +1,672 @@
+// src/extractors.rs
+use quick_xml::events::{BytesStart, Event};
+use once_cell::sync::Lazy;
+use quick_xml::Reader;
+use regex::Regex;
+use reqwest::Url;
+
+// --- ExtractInput ---
+// Everything an extractor may look at for one executed step
+pub struct ExtractInput<'a> {
+    pub command: &'a str,
+    pub output: &'a str,
+    pub purpose: &'a str,
+    // Machine-readable side output captured for the step (e.g. nmap's -oX report), if any
+    pub artifact: Option<&'a str>,
+}
+
+// gobuster: "/admin    (Status: 200) [Size: 1234]"
+static GOBUSTER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(/\S*)\s+\(Status:\s*\d{3}\)").expect("Invalid gobuster regex"));
+// ffuf: "admin    [Status: 200, Size: 1234, Words: 10, Lines: 5, Duration: 20ms]"
+static FFUF_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\S+)\s+\[Status:\s*\d{3},").expect("Invalid ffuf regex"));
+// dirb: "+ http://host/admin (CODE:200|SIZE:1234)" and "==> DIRECTORY: http://host/images/"
+static DIRB_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(?:\+ |==> DIRECTORY: )(https?://\S+)").expect("Invalid dirb regex"));
+// nmap text output: "Nmap scan report for ..." headers followed by "22/tcp open ssh OpenSSH 8.9" rows
+static NMAP_REPORT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^Nmap scan report for (?:\S+ \(([^)]+)\)|(\S+))").expect("Invalid nmap report regex"));
+static NMAP_PORT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(\d+)/(tcp|udp)\s+(\S+)\s+(\S+)(?:\s+(.+))?$").expect("Invalid nmap port regex"));
+// Service banners: "SSH-2.0-OpenSSH_8.9p1 Ubuntu-3", "Server: nginx/1.18.0", "220 mail.example.com ESMTP Postfix"
+static SSH_BANNER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*SSH-\d\.\d+-(\S.*?)\s*$").expect("Invalid SSH banner regex"));
+static HTTP_HEADER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?mi)^\s*[<>]?\s*(server|x-powered-by):\s*(\S.*?)\s*$").expect("Invalid HTTP header regex"));
+// HTTP responses (curl -i/-v, wget -S): status lines, a few headers, the page title and login forms
+static HTTP_STATUS_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*<?\s*HTTP/[\d.]+\s+(\d{3})\b").expect("Invalid HTTP status regex"));
+static HTTP_RESPONSE_HEADER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?mi)^\s*<?\s*(location|content-type|set-cookie):\s*(\S.*?)\s*$").expect("Invalid HTTP response header regex"));
+static HTML_TITLE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<title[^>]*>\s*(.*?)\s*</title>").expect("Invalid HTML title regex"));
+static HTML_FORM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<form\b([^>]*)>(.*?)(?:</form>|$)").expect("Invalid HTML form regex"));
+static HTML_ATTR_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?i)\b(action|method|name)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#).expect("Invalid HTML attribute regex"));
+static HTML_FIELD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<(?:input|select|textarea)\b([^>]*)>").expect("Invalid HTML field regex"));
+static GREETING_220_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^\s*220[ -](\S.*?)\s*$").expect("Invalid 220 greeting regex"));
+// Credentials: hydra "login: admin   password: secret", medusa "User: admin Password: secret", and the like
+static LOGIN_PASSWORD_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?im)\b(?:login|user(?:name)?):\s*(\S+)\s+password:\s*(\S+)").expect("Invalid login/password regex"));
+// netexec/crackmapexec: "[+] CORP\admin:Passw0rd! (Pwn3d!)"
+static NXC_LOGIN_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)\[\+\]\s+(?:\S+\\)?([^\s:\\]+):(\S+)").expect("Invalid netexec regex"));
+// john while cracking: "password1        (admin)"
+static JOHN_CRACKED_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)^(\S+)\s+\(([^)\s]+)\)\s*$").expect("Invalid john regex"));
+// API keys: AWS access key ids, GitHub tokens, and key/token assignments
+static API_KEY_RES: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"\b(AKIA[0-9A-Z]{16})\b",
+        r"\b(gh[pousr]_[A-Za-z0-9]{36,})\b",
+        r#"(?i)\b(?:api[_-]?key|access[_-]?token|secret[_-]?key)["']?\s*[:=]\s*["']?([A-Za-z0-9_\-.]{16,})"#,
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).expect("Invalid API key regex"))
+    .collect()
+});
+
+static EMAIL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}\b").expect("Invalid email regex"));
+// Usernames as enumeration tools report them: smtp-user-enum "10.0.0.5: root exists", kerbrute
+// "VALID USERNAME: jdoe@corp.local", enum4linux/rpcclient "user:[jdoe] rid:[0x44f]", ldapsearch
+// "sAMAccountName: jdoe"
+static USERNAME_RES: Lazy<Vec<Regex>> = Lazy::new(|| {
+    [
+        r"(?m)^\S+:\s+(\S+)\s+exists\s*$",
+        r"VALID USERNAME:\s+([^\s@]+)",
+        r"user:\[([^\]\s]+)\]\s+rid:",
+        r"(?m)^sAMAccountName:\s+(\S+)",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).expect("Invalid username regex"))
+    .collect()
+});
+
+// An extractor turns a step's output into (key, value) facts for discovered_values
+pub type Extractor = fn(&ExtractInput) -> Vec<(String, String)>;
+
+// --- Built-in extractor registry ---
+pub const EXTRACTORS: &[(&str, Extractor)] = &[
+    ("nmap", extract_nmap),
+    ("web_paths", extract_web_paths),
+    ("banners", extract_banners),
+    ("scan_blocking", extract_scan_blocking),
+    ("credentials", extract_credentials),
+    ("users", extract_users),
+    ("http_response", extract_http_response),
+];
+
+// Facts that hold secrets: usable in placeholders, but shown as REDACTED in progress output
+pub const SECRET_KEYS: &[&str] = &["found_credentials", "found_password", "found_passwords", "found_api_keys"];
+pub const REDACTED: &str = "<redacted>";
+
+pub fn is_secret_key(key: &str) -> bool {
+    SECRET_KEYS.contains(&key)
+}
+
+// Facts whose text comes from what the target sent back (login banners, page bodies, ...). They
+// are shell-quoted as one argument when substituted into a command.
+pub const TARGET_DERIVED_KEYS: &[&str] =
+    &["found_username", "found_password", "found_passwords", "found_credentials", "found_api_keys", "found_users", "found_emails", "http_location", "http_cookies", "http_title", "http_form_action", "http_form_fields"];
+
+pub fn is_target_derived(key: &str) -> bool {
+    TARGET_DERIVED_KEYS.contains(&key)
+}
+
+// Runs every registered extractor and collects their facts in registry order
+pub fn run_extractors(input: &ExtractInput) -> Vec<(String, String)> {
+    let mut facts = Vec::new();
+    for (_, extractor) in EXTRACTORS {
+        facts.extend(extractor(input));
+    }
+    facts
+}
+
+// First tool of the command without path or .exe, lowercased
+fn tool_name(command: &str) -> String {
+    command
+        .split_whitespace()
+        .next()
+        .and_then(|first| first.rsplit(['/', '\\']).next())
+        .map(|tool| tool.trim_end_matches(".exe").to_lowercase())
+        .unwrap_or_default()
+}
+
+// --- Web content discovery (gobuster, ffuf, dirb) ---
+// Facts: found_paths = "/admin /login" and, when the target URL is known, found_urls
+fn extract_web_paths(input: &ExtractInput) -> Vec<(String, String)> {
+    let tool = tool_name(input.command);
+    if !["gobuster", "ffuf", "dirb"].contains(&tool.as_str()) {
+        return Vec::new();
+    }
+    let mut urls = Vec::new();
+    let mut paths: Vec<String> = Vec::new();
+    for line in input.output.lines().map(str::trim) {
+        let path = if let Some(cap) = DIRB_RE.captures(line) {
+            urls.push(cap[1].to_string());
+            url_path(&cap[1])
+        } else if let Some(cap) = GOBUSTER_RE.captures(line) {
+            cap[1].to_string()
+        } else if let Some(cap) = FFUF_RE.captures(line) {
+            format!("/{}", cap[1].trim_start_matches('/'))
+        } else {
+            continue;
+        };
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        return Vec::new();
+    }
+
+    // gobuster/ffuf print bare paths; rebuild URLs from the -u target
+    if urls.is_empty() {
+        if let Some(base) = target_url(input.command) {
+            urls = paths.iter().map(|p| format!("{}{}", base, p)).collect();
+        }
+    }
+    urls.dedup();
+
+    let mut facts = vec![("found_paths".to_string(), paths.join(" "))];
+    if !urls.is_empty() {
+        facts.push(("found_urls".to_string(), urls.join(" ")));
+    }
+    facts
+}
+
+// "-u http://host/base/FUZZ" (or --url) -> "http://host/base" without the trailing slash
+fn target_url(command: &str) -> Option<String> {
+    let mut args = command.split_whitespace();
+    while let Some(arg) = args.next() {
+        if arg == "-u" || arg == "--url" {
+            let url = args.next()?.trim_matches(['"', '\'']);
+            return Some(url.trim_end_matches("FUZZ").trim_end_matches('/').to_string());
+        }
+    }
+    None
+}
+
+// "http://host:8080/admin/" -> "/admin/"
+fn url_path(url: &str) -> String {
+    let after_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    match after_scheme.find('/') {
+        Some(index) => after_scheme[index..].to_string(),
+        None => "/".to_string(),
+    }
+}
+
+// --- Service banners (nc, curl -I, telnet, nmap -sV, ...) ---
+// Facts: ssh_version, http_server, http_powered_by, smtp_banner, ftp_banner (first seen of each)
+fn extract_banners(input: &ExtractInput) -> Vec<(String, String)> {
+    let mut facts: Vec<(String, String)> = Vec::new();
+    let mut add = |key: &str, value: &str| {
+        let value = value.trim();
+        if !value.is_empty() && value.len() <= 200 && !facts.iter().any(|(k, _)| k == key) {
+            facts.push((key.to_string(), value.to_string()));
+        }
+    };
+
+    if let Some(cap) = SSH_BANNER_RE.captures(input.output) {
+        add("ssh_version", &cap[1]);
+    }
+    for cap in HTTP_HEADER_RE.captures_iter(input.output) {
+        let key = if cap[1].eq_ignore_ascii_case("server") { "http_server" } else { "http_powered_by" };
+        add(key, &cap[2]);
+    }
+    // 220 greets both SMTP and FTP; tell them apart by the text, else by the port in the command
+    let command = input.command.to_lowercase();
+    let port_in_command = |ports: &[&str]| ports.iter().any(|p| command.split(|c: char| !c.is_ascii_digit()).any(|n| n == *p));
+    for cap in GREETING_220_RE.captures_iter(input.output) {
+        let text = cap[1].to_lowercase();
+        if text.contains("smtp") || (!text.contains("ftp") && port_in_command(&["25", "465", "587"])) {
+            add("smtp_banner", &cap[1]);
+        } else if text.contains("ftp") || port_in_command(&["21"]) {
+            add("ftp_banner", &cap[1]);
+        }
+    }
+    // nmap -sV rows: "22/tcp open ssh OpenSSH 8.9p1 Ubuntu 3ubuntu0.1 (Ubuntu Linux; protocol 2.0)"
+    for line in input.output.lines() {
+        let Some(cap) = NMAP_PORT_RE.captures(line.trim()) else { continue };
+        let (Some(version), "open") = (cap.get(5), &cap[3]) else { continue };
+        match cap[4].trim_start_matches("ssl/") {
+            "ssh" => add("ssh_version", version.as_str()),
+            "http" | "https" | "http-proxy" => add("http_server", version.as_str()),
+            "smtp" | "smtps" | "submission" => add("smtp_banner", version.as_str()),
+            "ftp" => add("ftp_banner", version.as_str()),
+            _ => {}
+        }
+    }
+    facts
+}
+
+// --- Rate limiting / blocking ---
+// Signs that a target or IDS is dropping or throttling our traffic. Facts: scan_blocked = "true"
+// and scan_blocked_reason (the signs seen, "; "-separated). Timeouts only count when repeated.
+const BLOCKING_SIGNS: &[(&str, &str)] = &[
+    ("host seems down", "nmap reports the host as down (probes may be blocked)"),
+    ("dropped probes", "nmap is slowing down because probes are being dropped"),
+    ("retransmission cap hit", "nmap gave up on ports after hitting the retransmission cap"),
+    ("too many requests", "the server answered 429 Too Many Requests"),
+    ("status: 429", "the server answered 429 Too Many Requests"),
+    ("rate limit", "the server reports rate limiting"),
+];
+const REPEATED_SIGNS: &[(&str, &str)] = &[
+    ("timed out", "repeated timeouts"),
+    ("connection reset by peer", "repeated connection resets"),
+];
+const REPEATED_SIGN_THRESHOLD: usize = 3;
+
+// Advice printed with the warning when scan_blocked is found
+pub const SCAN_BLOCKED_ADVICE: &str =
+    "the target may be rate-limiting or blocking us; consider slowing down (nmap -T2 or --max-rate, fewer threads) or -Pn";
+
+fn extract_scan_blocking(input: &ExtractInput) -> Vec<(String, String)> {
+    let output = input.output.to_lowercase();
+    let mut reasons: Vec<&str> = Vec::new();
+    for (needle, reason) in BLOCKING_SIGNS {
+        if output.contains(needle) && !reasons.contains(reason) {
+            reasons.push(reason);
+        }
+    }
+    // "All 1000 scanned ports on x are filtered" / "... are in ignored states" with only filtered ports
+    if output.lines().any(|l| l.contains("scanned ports on") && l.contains("filtered")) {
+        reasons.push("every scanned port is filtered");
+    }
+    for (needle, reason) in REPEATED_SIGNS {
+        if output.matches(needle).count() >= REPEATED_SIGN_THRESHOLD {
+            reasons.push(reason);
+        }
+    }
+    if reasons.is_empty() {
+        return Vec::new();
+    }
+    vec![("scan_blocked".to_string(), "true".to_string()), ("scan_blocked_reason".to_string(), reasons.join("; "))]
+}
+
+// --- Credentials (hydra, medusa, netexec, hashcat, john, API keys) ---
+// Facts: found_credentials = "admin:secret root:toor", found_username/found_password (the first
+// pair), found_passwords (every recovered password, hashcat plaintexts included), found_api_keys
+fn extract_credentials(input: &ExtractInput) -> Vec<(String, String)> {
+    let tool = tool_name(input.command);
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    let mut passwords: Vec<String> = Vec::new();
+    let add_pair = |user: &str, password: &str, pairs: &mut Vec<(String, String)>| {
+        let pair = (user.to_string(), password.to_string());
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    };
+
+    for cap in LOGIN_PASSWORD_RE.captures_iter(input.output) {
+        add_pair(&cap[1], &cap[2], &mut pairs);
+    }
+    match tool.as_str() {
+        "nxc" | "netexec" | "crackmapexec" | "cme" => {
+            for cap in NXC_LOGIN_RE.captures_iter(input.output) {
+                add_pair(&cap[1], &cap[2], &mut pairs);
+            }
+        }
+        "john" => {
+            for cap in JOHN_CRACKED_RE.captures_iter(input.output) {
+                add_pair(&cap[2], &cap[1], &mut pairs);
+            }
+        }
+        // Cracked lines are "<hash>:<plain>" ("<user>:<hash>:<plain>" with --username); salts may add colons
+        "hashcat" => {
+            let with_user = input.command.contains("--username");
+            for line in input.output.lines().map(str::trim) {
+                let Some((left, plain)) = line.rsplit_once(':') else { continue };
+                let hash = if with_user { left.split_once(':').map_or(left, |(_, hash)| hash) } else { left };
+                if plain.is_empty() || hash.len() < 16 || !hash.chars().all(|c| c.is_ascii_hexdigit() || "$:*./".contains(c)) {
+                    continue;
+                }
+                match left.split_once(':').filter(|_| with_user) {
+                    Some((user, _)) => add_pair(user, plain, &mut pairs),
+                    None => passwords.push(plain.to_string()),
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut facts = Vec::new();
+    if let Some((user, password)) = pairs.first() {
+        facts.push(("found_username".to_string(), user.clone()));
+        facts.push(("found_password".to_string(), password.clone()));
+        let joined: Vec<String> = pairs.iter().map(|(user, password)| format!("{}:{}", user, password)).collect();
+        facts.push(("found_credentials".to_string(), joined.join(" ")));
+    }
+    for (_, password) in &pairs {
+        passwords.push(password.clone());
+    }
+    let mut unique_passwords: Vec<String> = Vec::new();
+    for password in passwords {
+        if !unique_passwords.contains(&password) {
+            unique_passwords.push(password);
+        }
+    }
+    if !unique_passwords.is_empty() {
+        facts.push(("found_passwords".to_string(), unique_passwords.join(" ")));
+    }
+
+    let mut keys: Vec<&str> = Vec::new();
+    for re in API_KEY_RES.iter() {
+        for cap in re.captures_iter(input.output) {
+            let key = cap.get(1).map_or("", |m| m.as_str());
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    if !keys.is_empty() {
+        facts.push(("found_api_keys".to_string(), keys.join(" ")));
+    }
+    facts
+}
+
+// --- Users and emails (theHarvester, smtp-user-enum, kerbrute, enum4linux, ldapsearch, ...) ---
+// Facts: found_emails and found_users (usernames plus the local part of each email), both
+// space-separated, deduplicated case-insensitively and capped at MAX_FOUND_USERS. The target
+// chooses these strings, so a username with anything outside [A-Za-z0-9._$-] is dropped.
+const MAX_FOUND_USERS: usize = 200;
+
+fn is_plain_username(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '$' | '-'))
+}
+// Addresses printed by the tools themselves (banners, docs), not by the target
+const NOISE_EMAIL_DOMAINS: &[&str] = &["edge-security.com", "example.com", "example.org"];
+
+fn extract_users(input: &ExtractInput) -> Vec<(String, String)> {
+    let add = |value: &str, list: &mut Vec<String>| {
+        if list.len() < MAX_FOUND_USERS && !list.iter().any(|known| known.eq_ignore_ascii_case(value)) {
+            list.push(value.to_string());
+        }
+    };
+    let mut emails: Vec<String> = Vec::new();
+    let mut users: Vec<String> = Vec::new();
+    for re in USERNAME_RES.iter() {
+        for cap in re.captures_iter(input.output) {
+            if is_plain_username(&cap[1]) {
+                add(&cap[1], &mut users);
+            }
+        }
+    }
+    for email in EMAIL_RE.find_iter(input.output).map(|m| m.as_str()) {
+        let Some((local, domain)) = email.split_once('@') else { continue };
+        if NOISE_EMAIL_DOMAINS.iter().any(|noise| domain.eq_ignore_ascii_case(noise)) {
+            continue;
+        }
+        add(email, &mut emails);
+        if is_plain_username(local) {
+            add(local, &mut users);
+        }
+    }
+
+    let mut facts = Vec::new();
+    if !emails.is_empty() {
+        facts.push(("found_emails".to_string(), emails.join(" ")));
+    }
+    if !users.is_empty() {
+        facts.push(("found_users".to_string(), users.join(" ")));
+    }
+    facts
+}
+
+// --- HTTP responses (curl, wget, httpie) ---
+// Facts from the final response (after redirects): http_status, http_location, http_content_type,
+// http_cookies (cookie names), http_title, and for the first form on the page http_form_action (when set),
+// http_form_method and http_form_fields (input names). http_server comes from the banners extractor.
+// All of it is chosen by the target: http_location and http_form_action are kept only as absolute
+// http(s) URLs resolved against the requested URL, and names only in a plain character set.
+const HTTP_CLIENTS: &[&str] = &["curl", "wget", "http", "https"];
+
+// Cookie and form field names as sites really use them ("__Host-sid", "user[name]")
+fn is_plain_name(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '[' | ']'))
+}
+
+// A Location or form action as an absolute http(s) URL; relative ones resolve against the URL the
+// command requested. None for other schemes or when there is nothing to resolve against.
+fn resolve_http_url(command: &str, reference: &str) -> Option<String> {
+    let requested = command
+        .split_whitespace()
+        .map(|arg| arg.trim_matches(|c| c == '\'' || c == '"'))
+        .find_map(|arg| Url::parse(arg).ok().filter(|url| matches!(url.scheme(), "http" | "https")));
+    let url = match Url::parse(reference) {
+        Ok(url) => url,
+        Err(_) => requested?.join(reference).ok()?,
+    };
+    matches!(url.scheme(), "http" | "https").then(|| url.to_string())
+}
+
+fn extract_http_response(input: &ExtractInput) -> Vec<(String, String)> {
+    if !HTTP_CLIENTS.contains(&tool_name(input.command).as_str()) {
+        return Vec::new();
+    }
+    let mut facts = Vec::new();
+    if let Some(cap) = HTTP_STATUS_RE.captures_iter(input.output).last() {
+        facts.push(("http_status".to_string(), cap[1].to_string()));
+    }
+    let mut cookies: Vec<&str> = Vec::new();
+    let (mut location, mut content_type) = (None, None);
+    for cap in HTTP_RESPONSE_HEADER_RE.captures_iter(input.output) {
+        let value = cap.get(2).map_or("", |m| m.as_str());
+        match cap[1].to_lowercase().as_str() {
+            "location" => location = Some(value),
+            "content-type" => content_type = Some(value.split(';').next().unwrap_or(value).trim()),
+            _ => {
+                let name = value.split('=').next().unwrap_or_default().trim();
+                if is_plain_name(name) && !cookies.contains(&name) {
+                    cookies.push(name);
+                }
+            }
+        }
+    }
+    facts.extend(location.and_then(|v| resolve_http_url(input.command, v)).map(|v| ("http_location".to_string(), v)));
+    let content_type = content_type.filter(|v| v.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '+' | '-')));
+    facts.extend(content_type.map(|v| ("http_content_type".to_string(), v.to_string())));
+    if !cookies.is_empty() {
+        facts.push(("http_cookies".to_string(), cookies.join(" ")));
+    }
+    if let Some(cap) = HTML_TITLE_RE.captures(input.output) {
+        let title = cap[1].split_whitespace().collect::<Vec<_>>().join(" ").replace(|c: char| c.is_control(), "");
+        if !title.is_empty() && title.len() <= 200 {
+            facts.push(("http_title".to_string(), title));
+        }
+    }
+    if let Some(form) = HTML_FORM_RE.captures(input.output) {
+        let attrs = html_attrs(&form[1]);
+        let fields: Vec<String> = HTML_FIELD_RE
+            .captures_iter(&form[2])
+            .filter_map(|field| html_attrs(&field[1]).into_iter().find(|(name, _)| name == "name").map(|(_, value)| value))
+            .filter(|name| is_plain_name(name))
+            .collect();
+        // No action: the form posts back to the page itself
+        if let Some((_, action)) = attrs.iter().find(|(name, value)| name == "action" && !value.is_empty()) {
+            facts.extend(resolve_http_url(input.command, action).map(|url| ("http_form_action".to_string(), url)));
+        }
+        let method = match attrs.iter().find(|(name, _)| name == "method").map(|(_, value)| value.to_uppercase()) {
+            Some(method) if method == "POST" => method,
+            _ => "GET".to_string(),
+        };
+        facts.push(("http_form_method".to_string(), method));
+        if !fields.is_empty() {
+            facts.push(("http_form_fields".to_string(), fields.join(" ")));
+        }
+    }
+    facts
+}
+
+// action/method/name attributes of one HTML tag, names lowercased
+fn html_attrs(tag: &str) -> Vec<(String, String)> {
+    HTML_ATTR_RE
+        .captures_iter(tag)
+        .map(|cap| {
+            let value = cap.get(2).or_else(|| cap.get(3)).or_else(|| cap.get(4)).map_or("", |m| m.as_str());
+            (cap[1].to_lowercase(), value.to_string())
+        })
+        .collect()
+}
+
+// --- nmap ---
+#[derive(Debug, Default)]
+struct NmapPort {
+    port: u16,
+    protocol: String,
+    state: String,
+    service: Option<String>,
+    version: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct NmapHost {
+    address: String,
+    up: bool,
+    ports: Vec<NmapPort>,
+}
+
+fn extract_nmap(input: &ExtractInput) -> Vec<(String, String)> {
+    if tool_name(input.command) != "nmap" {
+        return Vec::new();
+    }
+
+    // Prefer XML (injected report file, or `-oX -` on stdout), fall back to the text output
+    let stdout_xml = Some(input.output).filter(|o| o.contains("<nmaprun"));
+    let hosts = match input.artifact.or(stdout_xml) {
+        Some(xml) => match parse_nmap_xml(xml) {
+            Ok(hosts) => hosts,
+            Err(e) => {
+                println!("WARN: Failed to parse nmap XML ({}), falling back to text output.", e);
+                parse_nmap_text(input.output)
+            }
+        },
+        None => parse_nmap_text(input.output),
+    };
+    nmap_facts(&hosts)
+}
+
+// Flattens hosts into facts like host_192_168_1_5_ports = "22,80"
+fn nmap_facts(hosts: &[NmapHost]) -> Vec<(String, String)> {
+    let mut facts = Vec::new();
+    let live: Vec<&NmapHost> = hosts.iter().filter(|h| h.up).collect();
+    if !live.is_empty() {
+        facts.push((
+            "live_hosts".to_string(),
+            live.iter().map(|h| h.address.as_str()).collect::<Vec<_>>().join(" "),
+        ));
+    }
+
+    for host in &live {
+        let key = host.address.replace(['.', ':'], "_");
+        let open: Vec<&NmapPort> = host.ports.iter().filter(|p| p.state == "open").collect();
+        if open.is_empty() {
+            continue;
+        }
+        let port_list = open.iter().map(|p| p.port.to_string()).collect::<Vec<_>>().join(",");
+        facts.push((format!("host_{}_ports", key), port_list.clone()));
+        if live.len() == 1 {
+            facts.push(("open_ports".to_string(), port_list));
+        }
+        for port in open {
+            if let Some(service) = &port.service {
+                facts.push((format!("host_{}_port_{}_service", key, port.port), service.clone()));
+            }
+            if let Some(version) = &port.version {
+                facts.push((format!("host_{}_port_{}_version", key, port.port), version.clone()));
+            }
+            if port.protocol == "udp" {
+                facts.push((format!("host_{}_port_{}_protocol", key, port.port), "udp".to_string()));
+            }
+        }
+    }
+    facts
+}
+
+fn attr(tag: &BytesStart, name: &str) -> Option<String> {
+    tag.attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == name.as_bytes())
+        .and_then(|a| a.unescape_value().ok().map(|v| v.into_owned()))
+}
+
+fn parse_nmap_xml(xml: &str) -> Result<Vec<NmapHost>, quick_xml::Error> {
+    let mut reader = Reader::from_str(xml);
+    let mut hosts = Vec::new();
+    let mut host: Option<NmapHost> = None;
+    let mut port: Option<NmapPort> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(tag) | Event::Empty(tag) => match tag.name().as_ref() {
+                b"host" => host = Some(NmapHost::default()),
+                b"status" => {
+                    if let Some(h) = host.as_mut() {
+                        h.up = attr(&tag, "state").as_deref() == Some("up");
+                    }
+                }
+                b"address" => {
+                    // Skip MAC addresses; keep the first IP address
+                    let addrtype = attr(&tag, "addrtype").unwrap_or_default();
+                    if let (Some(h), true) = (host.as_mut(), addrtype.starts_with("ipv")) {
+                        if h.address.is_empty() {
+                            h.address = attr(&tag, "addr").unwrap_or_default();
+                        }
+                    }
+                }
+                b"port" => {
+                    port = Some(NmapPort {
+                        port: attr(&tag, "portid").and_then(|p| p.parse().ok()).unwrap_or(0),
+                        protocol: attr(&tag, "protocol").unwrap_or_else(|| "tcp".to_string()),
+                        ..Default::default()
+                    });
+                }
+                b"state" => {
+                    if let Some(p) = port.as_mut() {
+                        p.state = attr(&tag, "state").unwrap_or_default();
+                    }
+                }
+                b"service" => {
+                    if let Some(p) = port.as_mut() {
+                        p.service = attr(&tag, "name");
+                        let version = [attr(&tag, "product"), attr(&tag, "version"), attr(&tag, "extrainfo")]
+                            .into_iter()
+                            .flatten()
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        p.version = Some(version).filter(|v| !v.is_empty());
+                    }
+                }
+                _ => {}
+            },
+            Event::End(tag) => match tag.name().as_ref() {
+                b"port" => {
+                    if let (Some(h), Some(p)) = (host.as_mut(), port.take()) {
+                        h.ports.push(p);
+                    }
+                }
+                b"host" => hosts.extend(host.take()),
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(hosts)
+}
+
+// Text fallback: "Nmap scan report for ..." headers followed by "22/tcp open ssh OpenSSH 8.9" rows
+fn parse_nmap_text(output: &str) -> Vec<NmapHost> {
+    let mut hosts: Vec<NmapHost> = Vec::new();
+
+    for line in output.lines().map(str::trim) {
+        if let Some(cap) = NMAP_REPORT_RE.captures(line) {
+            let address = cap.get(1).or_else(|| cap.get(2)).map(|m| m.as_str().to_string()).unwrap_or_default();
+            // A report header is only printed for hosts nmap considers up
+            hosts.push(NmapHost { address, up: true, ports: Vec::new() });
+        } else if let (Some(cap), Some(host)) = (NMAP_PORT_RE.captures(line), hosts.last_mut()) {
+            host.ports.push(NmapPort {
+                port: cap[1].parse().unwrap_or(0),
+                protocol: cap[2].to_string(),
+                state: cap[3].to_string(),
+                service: Some(cap[4].to_string()),
+                version: cap.get(5).map(|m| m.as_str().trim().to_string()),
+            });
+        }
+    }
+    hosts
+}
@@ -0,0 +1,149 @@
+// src/interactive.rs
+use anyhow::Result;
+use hacker_rs::AppCore;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::{DefaultHistory, SearchDirection};
+use rustyline::validate::Validator;
+use rustyline::{Config, Context, Editor, Helper};
+use std::path::Path;
+
+const HISTORY_FILENAME: &str = "history";
+const MAX_HISTORY: usize = 500;
+
+// Meta-commands and their help text
+const META_COMMANDS: &[(&str, &str)] = &[
+    ("/help", "Show this help"),
+    ("/history", "Show commands run this session"),
+    ("/facts", "Show values discovered by the last task"),
+    ("/clear", "Forget session history and model context"),
+    ("/unload", "Free the model's memory now (reloads on next task)"),
+    ("/exit", "Leave interactive mode"),
+    ("/quit", "Leave interactive mode"),
+];
+
+// --- Line editor helper: completes /meta-commands and previous queries ---
+struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let prefix = &line[..pos];
+        let candidates: Vec<String> = if prefix.starts_with('/') {
+            META_COMMANDS
+                .iter()
+                .filter(|(name, _)| name.starts_with(prefix))
+                .map(|(name, _)| name.to_string())
+                .collect()
+        } else if prefix.is_empty() {
+            Vec::new()
+        } else {
+            // Most recent matching history entries first, without repeats
+            let history = ctx.history();
+            let mut seen = Vec::new();
+            for index in (0..history.len()).rev() {
+                if let Ok(Some(entry)) = history.get(index, SearchDirection::Reverse) {
+                    let entry = entry.entry.to_string();
+                    if entry.starts_with(prefix) && !seen.contains(&entry) {
+                        seen.push(entry);
+                    }
+                }
+            }
+            seen
+        };
+        Ok((0, candidates.into_iter().map(|c| Pair { display: c.clone(), replacement: c }).collect()))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+impl Highlighter for ReplHelper {}
+impl Validator for ReplHelper {}
+impl Helper for ReplHelper {}
+
+// --- run_interactive function ---
+pub async fn run_interactive(app: &mut AppCore, config_dir: &Path) -> Result<()> {
+    let config = Config::builder()
+        .max_history_size(MAX_HISTORY)?
+        .history_ignore_dups(true)?
+        .auto_add_history(false)
+        .build();
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::with_config(config)?;
+    editor.set_helper(Some(ReplHelper));
+
+    let history_path = config_dir.join(HISTORY_FILENAME);
+    if history_path.exists() {
+        if let Err(e) = editor.load_history(&history_path) {
+            eprintln!("WARN: Could not load history from {}: {}", history_path.display(), e);
+        }
+    }
+
+    println!("hacker-rs interactive mode. Type a task, or /help for commands.");
+    loop {
+        let line = match editor.readline("hacker-rs> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue, // Ctrl-C clears the line
+            Err(ReadlineError::Eof) => break,            // Ctrl-D leaves
+            Err(e) => return Err(e.into()),
+        };
+        let input = line.trim();
+        if input.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(input);
+
+        if input.starts_with('/') {
+            match input.split_whitespace().next().unwrap_or_default() {
+                "/exit" | "/quit" => break,
+                "/help" => {
+                    for (name, help) in META_COMMANDS {
+                        println!("  {:<10} {}", name, help);
+                    }
+                }
+                "/history" => {
+                    let history = &app.context().command_history;
+                    if history.is_empty() {
+                        println!("No commands run yet.");
+                    }
+                    for entry in history {
+                        println!("{}\n---", entry);
+                    }
+                }
+                "/facts" => {
+                    let mut facts: Vec<_> = app.discovered_values().iter().collect();
+                    if facts.is_empty() {
+                        println!("No values discovered yet.");
+                    }
+                    facts.sort();
+                    for (key, value) in facts {
+                        println!("  {} = {}", key, value);
+                    }
+                }
+                "/clear" => {
+                    app.reset_context();
+                    println!("Session history and model context cleared.");
+                }
+                "/unload" => match app.unload_model().await {
+                    Ok(()) => println!("Model unloaded."),
+                    Err(e) => eprintln!("Error: {:#}", e),
+                },
+                other => println!("Unknown command '{}'. Type /help for a list.", other),
+            }
+            continue;
+        }
+
+        match app.process_query(input).await {
+            Ok(response) => println!("{}\n\n{}", response, app.run_stats().to_text()),
+            Err(e) => eprintln!("Error: {:#}", e),
+        }
+    }
+
+    if let Err(e) = editor.save_history(&history_path) {
+        eprintln!("WARN: Could not save history to {}: {}", history_path.display(), e);
+    }
+    Ok(())
+}
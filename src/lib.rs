@@ -0,0 +1,37 @@
+// src/lib.rs
+//! Planning and execution engine behind the `hacker-rs` CLI.
+//!
+//! Load an [`AppConfig`], build an [`AppCore`] with [`AppCore::from_config`],
+//! then call [`AppCore::process_query`] with a natural-language task.
+pub mod actions;
+pub mod audit;
+pub mod command_executor;
+pub mod config;
+pub mod core;
+pub mod diff;
+pub mod engagement;
+pub mod extractors;
+pub mod network;
+pub mod ollama_client;
+pub mod profile;
+pub mod prompt;
+pub mod remote;
+pub mod report;
+pub mod scope;
+pub mod session;
+pub mod setup;
+
+pub use crate::actions::{ActionHandler, ActionOutput, ActionRegistry};
+pub use crate::audit::AuditLog;
+pub use crate::command_executor::ExecutionError;
+pub use crate::config::AppConfig;
+pub use crate::core::{AppCore, CommandStep, CoreError, CoreOptions, ExecutionContext, HistoryEntry, StepStatus};
+pub use crate::diff::{ReportDiff, RunSnapshot};
+pub use crate::engagement::Engagement;
+pub use crate::ollama_client::OllamaClient;
+pub use crate::profile::Profile;
+pub use crate::remote::RemoteTarget;
+pub use crate::report::{value_changes, Anonymizer, RunReport, RunStats, StepReport, StepResult};
+pub use crate::scope::ScopeRules;
+pub use crate::session::{Session, SessionState};
+pub use crate::setup::{Platform, SystemSetup};
@@ -1,122 +1,766 @@
 // src/main.rs
 mod cli;
-mod config;
-mod ollama_client;
-mod command_executor;
-mod core;
-mod setup;
-mod network;
-
-use anyhow::{Context, Result};
+mod doctor;
+mod interactive;
+
+use anyhow::{anyhow, Context, Result};
 use clap::Parser;
-use crate::cli::{Cli, Commands};
-use crate::core::AppCore;
-use crate::ollama_client::OllamaClient;
-use crate::setup::SystemSetup;
-use std::path::PathBuf; // Import PathBuf
+use crate::cli::{Cli, Commands, ConfigAction, OutputFormat};
+use hacker_rs::{config, prompt, setup};
+use hacker_rs::{value_changes, AppCore, AuditLog, CoreError, CoreOptions, Engagement, OllamaClient, Profile, RemoteTarget, ReportDiff, RunReport, RunSnapshot, ScopeRules, Session, SystemSetup};
+use std::path::{Path, PathBuf};
+use std::io::{IsTerminal, Write};
 use std::process::exit;
+use std::time::Duration;
+
+const DEFAULT_LEGAL_WARNING: &str = "\
+WARNING: hacker-rs runs offensive security tooling against real systems.
+Only use it against systems you own or have explicit written authorization to test.
+Unauthorized scanning or exploitation may be illegal in your jurisdiction.";
+
+// User config file name inside a config directory (--config-dir or the fallback)
+const CONFIG_FILENAME: &str = "config.toml";
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let setup = setup::SystemSetup::new();
+    let mut cli = Cli::parse();
+    let command = cli.take_command();
+    // The schema is fixed: no config, setup or audit is involved, and stdout must stay pure JSON
+    if let Commands::Schema = command {
+        println!("{}", hacker_rs::core::plan_schema());
+        return Ok(());
+    }
+    // Everything printed before the report (config, progress, tool output) goes to stderr instead
+    let quiet_json = matches!(command, Commands::Run { quiet_json: true, .. });
+    if quiet_json {
+        if let Err(e) = stdout_to_stderr::redirect() {
+            println!("{}", serde_json::json!({ "error": format!("Failed to redirect stdout for --quiet-json: {}", e) }));
+            exit(1);
+        }
+    }
+    let result = run_app(cli, command).await;
+    // Whatever failed, stdout gets back its handle and a JSON answer instead of staying silent
+    if stdout_to_stderr::restore() {
+        if let Err(e) = &result {
+            println!("{}", serde_json::json!({ "error": format!("{:#}", e) }));
+        }
+    }
+    result
+}
+
+async fn run_app(cli: Cli, mut command: Commands) -> Result<()> {
+    let mut setup = setup::SystemSetup::new();
 
     // --- Config path handling (get directory) ---
     let config_file_path_str: String;
     let config_dir: PathBuf;
 
-    let (is_default, config_path_obj) = if let Some(custom_path) = cli.config.as_ref() {
+    // --config-dir, else the most specific --config, decides the config directory (system prompt, audit log)
+    let mut user_config = match &cli.config_dir {
+        Some(dir) => dir.join(CONFIG_FILENAME),
+        None => config::AppConfig::default_path(),
+    };
+    let (is_default, mut config_path_obj) = if let Some(custom_path) = cli.config.last() {
         (false, custom_path.clone()) // Clone custom path
     } else {
-        (true, config::AppConfig::default_path()) // Get default path obj
+        (true, user_config.clone())
+    };
+
+    let mut candidate_dir = match &cli.config_dir {
+        Some(dir) => dir.clone(),
+        None => config_path_obj
+            .parent()
+            .context("Could not determine config directory from path")?
+            .to_path_buf(), // Get the parent directory
     };
 
+    // Ensure config directory exists (needed before loading/generating files). The default location
+    // may be unwritable (read-only home, containers); then fall back to a private temp dir for this run.
+    if let Err(e) = std::fs::create_dir_all(&candidate_dir) {
+        let explicit = cli.config_dir.is_some() || !is_default;
+        eprintln!("{}: {}", if explicit { "ERROR" } else { "WARN" }, describe_config_dir_error(&candidate_dir, &e));
+        if explicit {
+            anyhow::bail!("Config directory {} is not usable", candidate_dir.display());
+        }
+        let fallback = private_fallback_dir().map_err(|fallback_error| {
+            anyhow!("{}\nThe private fallback directory isn't usable either: {}", describe_config_dir_error(&candidate_dir, &e), fallback_error)
+        })?;
+        eprintln!("INFO: Using {} for this run instead (settings there may not survive a reboot).", fallback.display());
+        user_config = fallback.join(CONFIG_FILENAME);
+        config_path_obj = user_config.clone();
+        candidate_dir = fallback;
+    }
+    config_dir = candidate_dir;
+
     config_file_path_str = config_path_obj
         .to_str()
         .context("Config path contains invalid UTF-8")?
         .to_string();
 
-    config_dir = config_path_obj
-        .parent()
-        .context("Could not determine config directory from path")?
-        .to_path_buf(); // Get the parent directory
-
-    // Ensure config directory exists (needed before loading/generating files)
-    std::fs::create_dir_all(&config_dir)
-        .context(format!("Failed to create config directory: {}", config_dir.display()))?;
-
     // Generate default config if needed
     if is_default && !config_path_obj.exists() {
-        config::AppConfig::generate_default_config()
-            .context("Failed to generate default config file")?;
+        config::AppConfig::generate_default_config_at(&config_path_obj)
+            .context(format!("Failed to generate default config file at {}", config_path_obj.display()))?;
         println!("Created default config at: {}", config_file_path_str);
         // You might also want to generate the default system_prompt.txt here
         // e.g., fs::write(config_dir.join(SYSTEM_PROMPT_FILENAME), DEFAULT_SYSTEM_PROMPT_CONTENT)?;
     }
 
-    // Load config using the string path
-    let config = config::AppConfig::from_file(&config_file_path_str)?;
+    // --- Profile resolution ---
+    // A profile's config overlays the implicit layers; explicit --config files still win
+    let profile = cli.profile.as_deref().map(|name| Profile::load(&config_dir, name)).transpose()?;
+    let mut layers = config::AppConfig::layer_paths_with_user(&user_config, &cli.config);
+    if let Some(profile_config) = profile.as_ref().and_then(Profile::config_path) {
+        layers.insert(layers.len() - cli.config.len(), profile_config);
+    }
+    if let Some(profile) = &profile {
+        println!("Using profile '{}' ({})", profile.name, profile.dir.display());
+    }
+
+    // Load and merge all config layers (see AppConfig::layer_paths for precedence)
+    // stderr, like the other start-up notes below: stdout is kept for the command's own output
+    eprintln!("Config layers: {}", layers.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" -> "));
+    let config = config::AppConfig::from_layers(&layers)?;
     // --- End config path handling ---
 
+    // Pure introspection of the resolution above; nothing else needs to be set up
+    if let Commands::Config { action: ConfigAction::Effective } = &command {
+        let mut effective = config::EffectiveConfig::resolve(&layers)?;
+        apply_flag_sources(&mut effective, &cli);
+        println!("{}", effective.to_text());
+        return Ok(());
+    }
+
+    // Pinned installer digests (only used if Ollama needs installing)
+    if let Some(setup_config) = &config.setup {
+        setup.set_installer_checksums(setup::InstallerChecksums {
+            script_sha256: setup_config.ollama_script_sha256.clone(),
+            installer_sha256: setup_config.ollama_installer_sha256.clone(),
+        });
+    }
+
+    // --- Engagement directory --- the audit log, reports, plans, pcaps and session of one job
+    let engagement = cli.engagement.as_deref().map(Engagement::open).transpose()?;
+    if let Some(engagement) = &engagement {
+        println!("Engagement '{}': {}", engagement.name, engagement.dir.display());
+        route_to_engagement(&mut command, engagement);
+    }
+    let artifact_dir = engagement.as_ref().map_or(config_dir.as_path(), |e| e.dir.as_path());
+
+    // Safe by default: plans are previewed unless execution is armed
+    let armed = cli.execute || config.safety.as_ref().and_then(|s| s.auto_execute).unwrap_or(false);
+    if command.executes_commands() && !armed {
+        println!("INFO: Preview only, nothing will be executed. Pass --execute (-x) to run the plan, or set safety.auto_execute = true.");
+    }
+
+    // Every prompt from here on gives up (safe choice) after --input-timeout
+    prompt::set_input_timeout(cli.input_timeout);
+
+    // --- Legal/authorization gate (before anything gets executed) ---
+    if command.executes_commands() && armed {
+        authorization_gate(&config, cli.i_am_authorized, &AuditLog::in_dir(artifact_dir)).await?;
+    }
+
 
     // --- Ollama setup check (no changes) ---
-    if let Err(e) = setup.ensure_ollama().await {
-        eprintln!("Ollama setup failed: {}", e);
-        if cfg!(windows) {
-            eprintln!("On Windows, please install Ollama manually from https://ollama.com");
+    // Skipped with --skip-setup, and for subcommands that never talk to the model
+    let check_model = !cli.skip_setup && command.needs_model();
+    let mut just_installed = false;
+    if !check_model {
+        eprintln!("Skipping Ollama setup and model validation.");
+    } else {
+        match setup.ensure_ollama().await {
+            Ok(installed) => just_installed = installed,
+            Err(e) => {
+                eprintln!("Ollama setup failed: {}", e);
+                if cfg!(windows) {
+                    eprintln!("On Windows, please install Ollama manually from https://ollama.com");
+                }
+                exit(1);
+            }
         }
-        exit(1);
     }
     // --- End Ollama setup check ---
 
+    // Remote execution: from here on platform/tool checks describe the pivot host.
+    // (Done after ensure_ollama, which must still look at the local machine.)
+    let setup = match cli.remote.as_deref() {
+        Some(destination) => setup
+            .with_remote(RemoteTarget::new(destination, cli.ssh_key.clone(), cli.ssh_port))
+            .context("Failed to set up remote execution")?,
+        None => setup,
+    };
+
 
     // Ollama client setup (UPDATED)
-    let ollama_host = config.ollama_host.as_deref().unwrap_or("http://localhost:11434");
     // Pass the config directory path to the constructor
-    let client = ollama_client::OllamaClient::new(
-        ollama_host,
-        &config.model.name,
-        config_dir.clone(), // Pass the determined config directory path
-    );
+    let mut client = OllamaClient::from_config(&config, config_dir.clone());
+    client.set_debug_prompt(cli.debug_prompt);
+    client.set_prompt_additions(cli.prompt_prepend.clone(), cli.prompt_append.clone());
+    // An explicit prompt in the config outranks the profile's file
+    if config.system_prompt.is_none() && config.system_prompt_path.is_none() {
+        if let Some(prompt_path) = profile.as_ref().and_then(Profile::system_prompt_path) {
+            client.set_system_prompt_path(prompt_path);
+        }
+    }
+
 
+    // doctor diagnoses the setup, so it runs before (and instead of) anything that needs it working
+    if let Commands::Doctor = command {
+        let healthy = doctor::run_doctor(&client, &setup, &layers).await;
+        exit(if healthy { 0 } else { 1 });
+    }
+
+    // A fresh install's API may still be starting; don't let the first request race it
+    if just_installed {
+        client.wait_until_ready(hacker_rs::ollama_client::READY_TIMEOUT).await?;
+    }
 
     // --- validate_model function definition ---
     // Needs access to setup, passed as arg
     async fn validate_model(client: &OllamaClient, setup_ref: &SystemSetup) -> Result<()> {
-        let test_prompt = "<|im_start|>system\nTest<|im_end|>\n<|im_start|>user\nTest<|im_end|>\n<|im_start|>assistant\n";
+        // Same layout and formatting (qwen_formatting) as real queries, with a trivial task
+        let os_info = setup_ref.platform.to_string();
+        let test_prompt = client.format_prompt(&hacker_rs::core::task_message(
+            &os_info,
+            "Validation check: reply with a short explanation and an empty steps array.",
+            None,
+            "",
+        ));
         // Pass setup_ref to generate
-        let (response, _) = client.generate(test_prompt, None, setup_ref).await?;
+        let (response, _) = client.generate(&test_prompt, None, setup_ref).await?;
 
         if response.is_empty() {
             anyhow::bail!("Model validation failed. Check:\n1. Model exists (ollama list)\n2. API reachable\n3. Port 11434 accessible");
         }
+        // A too-small context window truncates the prompt silently; worth a warning, not a failure
+        match client.context_window().await {
+            Ok(window) if !window.is_sufficient() => {
+                println!("WARN: Model '{}' context may be too small: {}. Run `hacker-rs doctor` for details.", client.model(), window.describe());
+            }
+            // Not every model reports its context length
+            _ => {}
+        }
         Ok(())
     }
     // --- End validate_model function definition ---
 
 
     // Call validate_model
-    validate_model(&client, &setup).await.context("Model validation failed")?;
+    // On a terminal a missing model or unreachable host gets a guided fix instead of a hard error
+    if check_model {
+        let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+        let (configured_model, configured_host) = (client.model().to_string(), client.host().to_string());
+        loop {
+            match validate_model(&client, &setup).await {
+                Ok(()) => break,
+                Err(e) if interactive => {
+                    eprintln!("ERROR: Model validation failed: {:#}", e);
+                    pick_model_or_host(&mut client).await.context("Model validation failed")?;
+                }
+                Err(e) => return Err(e.context("Model validation failed")),
+            }
+        }
+        let model = Some(client.model()).filter(|m| *m != configured_model);
+        let host = Some(client.host()).filter(|h| *h != configured_host);
+        if model.is_some() || host.is_some() {
+            offer_to_save_model_settings(&config_path_obj, model, host).await?;
+        }
+    }
 
     // Application core initialization (client now holds config_dir path if needed later)
     // Note: AppCore::new signature might need update if it now takes the updated client type
-    let mut app = AppCore::new(client, setup);
+    // Config-driven options first, then CLI flags on top
+    let mut options = CoreOptions::from_config(&config);
+    if cli.no_preparse {
+        options.preparse_query = false;
+    }
+    options.elevate = cli.elevate;
+    options.auto_sudo = cli.auto_sudo;
+    if cli.target.is_some() {
+        options.default_target = cli.target.clone();
+    }
+    options.show_substitutions = cli.show_substitutions || !armed;
+    options.explain_plan = cli.explain_plan;
+    options.edit_plan = cli.edit_plan;
+    options.review_plan = cli.review;
+    options.confirm_plan = cli.confirm_plan;
+    options.artifact_dir = engagement.as_ref().map(|e| e.dir.clone());
+    options.expand_vague_steps |= cli.expand_vague;
+    options.keep_history &= !cli.no_history;
+    options.skip_unresolved = cli.skip_unresolved;
+    options.only_tags = cli.only_tags.clone();
+    options.skip_tags = cli.skip_tags.clone();
+    options.strict_steps = cli.strict_steps;
+    options.max_display_lines = cli.max_display_lines;
+    options.stream_output |= cli.stream;
+    options.include_stderr |= cli.include_stderr;
+    options.attach_stdin = cli.attach_stdin;
+    options.preset_values = cli.set_values.iter().cloned().collect();
+    // A target list becomes {targets} unless --set/--pin already gave one
+    if let Some(path) = &cli.targets_file {
+        let targets = hacker_rs::network::load_targets_file(path, config.max_cidr_hosts())?;
+        println!("Loaded {} target(s) from {}", targets.len(), path.display());
+        options.preset_values.entry("targets".to_string()).or_insert_with(|| targets.join(","));
+    }
+    options.pinned_values = cli.pin_values.iter().cloned().collect();
+    // Config (and profile) exclusions plus any given on the command line
+    let scope_config = config.scope.as_ref();
+    let mut excluded_hosts = scope_config.and_then(|s| s.exclude.clone()).unwrap_or_default();
+    excluded_hosts.extend(cli.exclude.iter().cloned());
+    let mut excluded_ports = scope_config.and_then(|s| s.exclude_ports.clone()).unwrap_or_default();
+    excluded_ports.extend(cli.exclude_ports.iter().cloned());
+    options.scope = ScopeRules::from_lists(&excluded_hosts, &excluded_ports)
+        .context("Invalid scope exclusion (check [scope] and --exclude/--exclude-ports)")?;
+    let mut app = AppCore::new(client, setup, options);
+
+    // Only commands that talk to the model read or write the session; an engagement has one of its own
+    let session_name = cli.session.clone().or_else(|| engagement.as_ref().map(|e| e.name.clone()));
+    let session = match (&session_name, command.needs_model()) {
+        (Some(name), true) => Some(Session::open(artifact_dir, name)?),
+        _ => None,
+    };
+    if let Some(session) = &session {
+        match session.load()? {
+            Some(state) => {
+                println!("Resuming session '{}' ({})", session.name, session.path.display());
+                app.restore_session(state);
+            }
+            None => println!("Starting session '{}' ({})", session.name, session.path.display()),
+        }
+    }
+    // Facts only: the model reasons from scratch, placeholders resolve from what was found before
+    if let (Some(name), true) = (&cli.since, command.executes_commands()) {
+        let earlier = Session::open(artifact_dir, name)?;
+        let state = earlier
+            .load()?
+            .ok_or_else(|| anyhow!("No session '{}' to start from ({} does not exist)", name, earlier.path.display()))?;
+        let restored = app.restore_facts(state.discovered_values);
+        println!("Loaded {} discovered value(s) from session '{}'", restored, earlier.name);
+    }
 
 
+    // kill or a closed terminal would otherwise leave captures and scans running without us
+    tokio::spawn(async {
+        hacker_rs::command_executor::termination_signal().await;
+        hacker_rs::command_executor::kill_all_spawned();
+        if stdout_to_stderr::restore() {
+            println!("{}", serde_json::json!({ "error": "terminated by signal" }));
+        }
+        exit(143);
+    });
+
     // --- Command handling (no changes) ---
-    match cli.command {
-        Commands::Run { query, output } => {
-            let response = app.process_query(&query).await?;
+    let result = run_command(command, &mut app, &config, &config_dir).await;
+    hacker_rs::command_executor::kill_all_spawned();
+    // Saved even when the query failed: the model conversation has still moved on
+    if let Some(session) = &session {
+        session.save(&app.export_session())?;
+    }
+    if let Some(engagement) = &engagement {
+        // stderr: after --quiet-json, stdout holds only the report
+        eprintln!("Engagement files: {}", engagement.dir.display());
+    }
+    result
+}
+
+async fn run_command(command: Commands, app: &mut AppCore, config: &config::AppConfig, config_dir: &Path) -> Result<()> {
+    match command {
+        Commands::Run { query, output, context_file, format, save_plan, anonymize, omit_legend, watch, quiet_json } => {
+            app.set_save_plan(save_plan);
+            if let Some(path) = context_file {
+                app.load_background_notes(&path)?;
+            }
+            let format = if quiet_json { OutputFormat::Json } else { format };
+            let render = RenderOptions { format, anonymize, omit_legend };
+            match watch {
+                None => {
+                    let response = app.process_query(&query).await?;
+                    let (response, _) = render_result(app, &query, &response, &render)?;
+                    if quiet_json {
+                        stdout_to_stderr::restore();
+                    }
+                    println!("{}", response);
+                    if let Some(path) = output {
+                        app.save_output(&response, &path)?;
+                    }
+                }
+                Some(interval) => watch_query(app, &query, interval, &render, output.as_ref()).await?,
+            }
+        }
+        Commands::Replay { plan, query, output } => {
+            let plan_json = std::fs::read_to_string(&plan).context(format!("Failed to read plan file: {}", plan.display()))?;
+            let response = app.replay_plan(&plan_json, query.as_deref()).await?;
+            let response = format!("{}\n\n{}", response, app.run_stats().to_text());
             println!("{}", response);
             if let Some(path) = output {
                 app.save_output(&response, &path)?;
             }
         }
         Commands::Interactive => {
-            todo!("Interactive mode coming soon");
+            interactive::run_interactive(app, config_dir).await?;
+        }
+        Commands::Unload => {
+            app.unload_model().await?;
+            println!("Model '{}' unloaded.", config.model.name);
+        }
+        Commands::Schema => unreachable!("schema is handled before the config is loaded"),
+        Commands::Diff { old, new, format } => {
+            let diff = ReportDiff::between(&RunSnapshot::load(&old)?, &RunSnapshot::load(&new)?);
+            match format {
+                OutputFormat::Text => println!("{}", diff.to_text()),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diff).context("Failed to serialize diff")?),
+            }
+        }
+        Commands::Doctor => unreachable!("doctor is handled before the app is built"),
+        Commands::Config { .. } => unreachable!("config is handled before the app is built"),
+        Commands::Profiles => {
+            let profiles = Profile::list(config_dir);
+            if profiles.is_empty() {
+                println!("No profiles in {}", hacker_rs::profile::profiles_dir(config_dir).display());
+            }
+            for profile in profiles {
+                let mut parts = Vec::new();
+                if profile.config_path().is_some() {
+                    parts.push("config");
+                }
+                if profile.system_prompt_path().is_some() {
+                    parts.push("system prompt");
+                }
+                let contents = if parts.is_empty() { "empty".to_string() } else { parts.join(", ") };
+                println!("  {:<20} {}", profile.name, contents);
+            }
         }
     }
     // --- End Command handling ---
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// --- --quiet-json ---
+// Points the process's stdout at stderr (file descriptor / std handle level, so child processes and
+// every println! follow) until the final report is printed on the real stdout.
+mod stdout_to_stderr {
+    use std::io::Write;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    // The original stdout (fd or HANDLE), -1 while not redirected. restore() is safe to call any
+    // number of times and says whether stdout had been redirected.
+    static SAVED: AtomicI64 = AtomicI64::new(-1);
+
+    #[cfg(unix)]
+    pub fn redirect() -> std::io::Result<()> {
+        std::io::stdout().flush()?;
+        unsafe {
+            let saved = libc::dup(libc::STDOUT_FILENO);
+            if saved < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            SAVED.store(saved as i64, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn restore() -> bool {
+        let _ = std::io::stdout().flush();
+        let saved = SAVED.swap(-1, Ordering::SeqCst);
+        if saved >= 0 {
+            unsafe {
+                libc::dup2(saved as i32, libc::STDOUT_FILENO);
+                libc::close(saved as i32);
+            }
+        }
+        saved >= 0
+    }
+
+    // Rust's stdout looks the handle up on every write, so swapping the std handle is enough
+    #[cfg(windows)]
+    pub fn redirect() -> std::io::Result<()> {
+        use winapi::um::processenv::{GetStdHandle, SetStdHandle};
+        use winapi::um::winbase::{STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+        std::io::stdout().flush()?;
+        unsafe {
+            let original = GetStdHandle(STD_OUTPUT_HANDLE);
+            if SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            SAVED.store(original as i64, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    pub fn restore() -> bool {
+        use winapi::um::processenv::SetStdHandle;
+        use winapi::um::winbase::STD_OUTPUT_HANDLE;
+        let _ = std::io::stdout().flush();
+        let saved = SAVED.swap(-1, Ordering::SeqCst);
+        if saved >= 0 {
+            unsafe {
+                SetStdHandle(STD_OUTPUT_HANDLE, saved as winapi::um::winnt::HANDLE);
+            }
+        }
+        saved >= 0
+    }
+}
+
+// --- Engagement routing ---
+// Relative --output/--save-plan paths move into the engagement directory, and run/replay results
+// are saved there even without --output
+fn route_to_engagement(command: &mut Commands, engagement: &Engagement) {
+    match command {
+        Commands::Run { output, save_plan, format, quiet_json, .. } => {
+            let extension = if *quiet_json || *format == OutputFormat::Json { "json" } else { "txt" };
+            *output = Some(output.as_deref().map_or_else(|| engagement.report_path("run", extension), |path| engagement.path_for(path)));
+            *save_plan = save_plan.as_deref().map(|path| engagement.path_for(path));
+        }
+        Commands::Replay { output, .. } => {
+            *output = Some(output.as_deref().map_or_else(|| engagement.report_path("replay", "txt"), |path| engagement.path_for(path)));
+        }
+        _ => {}
+    }
+}
+
+// --- Run output rendering ---
+struct RenderOptions {
+    format: OutputFormat,
+    anonymize: bool,
+    omit_legend: bool,
+}
+
+// Formats one run's result (text or JSON, optionally anonymized); also returns the report
+fn render_result(app: &AppCore, query: &str, response: &str, render: &RenderOptions) -> Result<(String, RunReport)> {
+    let mut report = app.run_report(query, response);
+    let mut legend = None;
+    if render.anonymize {
+        let anonymizer = app.anonymizer(&report);
+        report = anonymizer.apply_report(&report, !render.omit_legend);
+        legend = (!render.omit_legend).then(|| anonymizer.legend_text());
+    }
+    let text = match render.format {
+        OutputFormat::Text => match legend {
+            Some(legend) => format!("{}\n\n{}\n\n{}", report.summary, legend, report.stats.to_text()),
+            None => format!("{}\n\n{}", report.summary, report.stats.to_text()),
+        },
+        OutputFormat::Json => serde_json::to_string_pretty(&report).context("Failed to serialize run report")?,
+    };
+    Ok((text, report))
+}
+
+// --- Watch mode ---
+// Re-runs the query with a fresh context every `interval`, printing what changed in the discovered
+// values since the previous cycle. A failed cycle is reported and retried next time; Ctrl-C between
+// cycles (or aborting a run) stops watching. --output always holds the latest cycle.
+async fn watch_query(app: &mut AppCore, query: &str, interval: Duration, render: &RenderOptions, output: Option<&PathBuf>) -> Result<()> {
+    let mut previous: Option<RunReport> = None;
+    for cycle in 1.. {
+        println!("\n===== Watch cycle {} (every {}s, Ctrl-C to stop) =====", cycle, interval.as_secs());
+        app.reset_context();
+        match app.process_query(query).await {
+            Ok(response) => {
+                let (text, report) = render_result(app, query, &response, render)?;
+                println!("{}", text);
+                if let Some(path) = output {
+                    app.save_output(&text, path)?;
+                }
+                if let Some(previous) = &previous {
+                    let changes = value_changes(&previous.discovered_values, &report.discovered_values);
+                    if changes.is_empty() {
+                        println!("----- Cycle {}: no changes since cycle {} -----", cycle, cycle - 1);
+                    } else {
+                        println!("----- Cycle {}: {} change(s) since cycle {} -----", cycle, changes.len(), cycle - 1);
+                        for change in changes {
+                            println!("  {}", change);
+                        }
+                    }
+                }
+                previous = Some(report);
+            }
+            Err(CoreError::Aborted) => {
+                println!("Watch stopped.");
+                return Ok(());
+            }
+            Err(e) => eprintln!("ERROR: Watch cycle {} failed: {}", cycle, e),
+        }
+        println!("===== End of cycle {} =====", cycle);
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => {
+                println!("Watch stopped.");
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+// --- Flag sources (`config effective`) ---
+// Records the global flags that override a config setting for this run (see the CoreOptions set-up)
+fn apply_flag_sources(effective: &mut config::EffectiveConfig, cli: &Cli) {
+    if let Some(target) = &cli.target {
+        effective.set("default_target", toml::Value::String(target.clone()), "flag (--target)");
+    }
+    let switches = [
+        (cli.no_preparse, "advanced.query_preparse", false, "--no-preparse"),
+        (cli.stream, "advanced.stream_output", true, "--stream"),
+        (cli.include_stderr, "advanced.include_stderr", true, "--include-stderr"),
+        (cli.expand_vague, "advanced.expand_vague_steps", true, "--expand-vague"),
+        (cli.no_history, "advanced.keep_history", false, "--no-history"),
+        (cli.execute, "safety.auto_execute", true, "--execute"),
+    ];
+    for (given, key, value, flag) in switches {
+        if given {
+            effective.set(key, toml::Value::Boolean(value), format!("flag ({})", flag));
+        }
+    }
+    // Exclusions on the command line add to the configured ones
+    for (extra, key, flag) in [(&cli.exclude, "scope.exclude", "--exclude"), (&cli.exclude_ports, "scope.exclude_ports", "--exclude-ports")] {
+        if extra.is_empty() {
+            continue;
+        }
+        let (mut items, source) = match effective.entries.get(key) {
+            Some((toml::Value::Array(items), source)) => (items.clone(), format!("{} + flag ({})", source, flag)),
+            _ => (Vec::new(), format!("flag ({})", flag)),
+        };
+        items.extend(extra.iter().cloned().map(toml::Value::String));
+        effective.set(key, toml::Value::Array(items), source);
+    }
+}
+
+// --- Config directory errors ---
+// Names the actual problem and how to point hacker-rs somewhere writable
+fn describe_config_dir_error(dir: &Path, error: &std::io::Error) -> String {
+    let problem = match error.kind() {
+        std::io::ErrorKind::PermissionDenied => "permission denied (the directory or a parent isn't writable by this user)".to_string(),
+        _ => error.to_string(),
+    };
+    format!(
+        "Cannot create config directory {}: {}.\nPass --config-dir DIR to use another directory, or --config FILE for the config file.",
+        dir.display(),
+        problem
+    )
+}
+
+// The per-user temp dir used when the config dir can't be created. The shared temp dir is open to
+// every local user, so on Unix it is named after our uid, created 0700 and refused unless we own it
+// and nobody else can reach into it.
+#[cfg(unix)]
+fn private_fallback_dir() -> std::io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    let uid = unsafe { libc::geteuid() };
+    let dir = std::env::temp_dir().join(format!("hacker-rs-{}", uid));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    // symlink_metadata: a symlink planted under our name must not redirect us
+    let meta = std::fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != uid || meta.permissions().mode() & 0o077 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{} exists but is not a private directory owned by this user", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
+
+// %TEMP% is already inside the user's profile on Windows
+#[cfg(not(unix))]
+fn private_fallback_dir() -> std::io::Result<PathBuf> {
+    let dir = std::env::temp_dir().join("hacker-rs");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// Lets the user pick an installed model, or enter a new host when Ollama can't be reached.
+// An empty answer aborts.
+async fn pick_model_or_host(client: &mut OllamaClient) -> Result<()> {
+    match client.list_models().await {
+        Ok(models) if models.is_empty() => {
+            anyhow::bail!("No models are installed on {}. Run `ollama pull <model>` first.", client.host())
+        }
+        Ok(models) => {
+            println!("Models available on {}:", client.host());
+            for (i, name) in models.iter().enumerate() {
+                println!("  {}) {}", i + 1, name);
+            }
+            let answer = prompt::ask(&format!("Use which model? [1-{}, Enter to abort] ", models.len())).await?;
+            if answer.is_empty() {
+                anyhow::bail!("No model selected.");
+            }
+            let chosen = answer
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| models.get(i))
+                .or_else(|| models.iter().find(|m| **m == answer))
+                .with_context(|| format!("'{}' is not one of the listed models", answer))?;
+            client.set_model(chosen);
+        }
+        Err(e) => {
+            eprintln!("ERROR: Cannot list models on {}: {:#}", client.host(), e);
+            let answer = prompt::ask("Ollama host to use instead (e.g. http://10.0.0.2:11434, Enter to abort): ").await?;
+            if answer.is_empty() {
+                anyhow::bail!("No Ollama host given.");
+            }
+            // A bad URL is reported and asked for again on the next round
+            if let Err(e) = client.set_host(&answer) {
+                eprintln!("ERROR: {:#}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Offers to persist a model/host picked at startup into the config file that was in use
+async fn offer_to_save_model_settings(config_path: &Path, model: Option<&str>, host: Option<&str>) -> Result<()> {
+    let is_toml = config_path.extension().is_none_or(|ext| ext == "toml");
+    let mut changes = Vec::new();
+    if let Some(model) = model {
+        changes.push(format!("model.name = \"{}\"", model));
+    }
+    if let Some(host) = host {
+        changes.push(format!("ollama_host = \"{}\"", host));
+    }
+    if !is_toml || !config_path.exists() {
+        println!("INFO: To keep this choice, set {} in your config.", changes.join(" and "));
+        return Ok(());
+    }
+    if prompt::confirm(&format!("Save {} to {}? [y/N] ", changes.join(" and "), config_path.display())).await? {
+        match config::AppConfig::save_model_settings(config_path, model, host) {
+            Ok(()) => println!("Saved to {}", config_path.display()),
+            Err(e) => eprintln!("WARN: Could not save the choice: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+// --- Authorization gate ---
+// Prints the (configurable) legal warning and requires acknowledgment via flag or y/N prompt.
+async fn authorization_gate(config: &config::AppConfig, acknowledged_by_flag: bool, audit: &AuditLog) -> Result<()> {
+    let safety = config.safety.as_ref();
+    if !safety.and_then(|s| s.require_authorization).unwrap_or(true) {
+        return Ok(());
+    }
+    let warning = safety.and_then(|s| s.legal_warning.as_deref()).unwrap_or(DEFAULT_LEGAL_WARNING);
+    eprintln!("{}\n", warning);
+
+    let method = if acknowledged_by_flag {
+        "--i-am-authorized flag"
+    } else if std::io::stdin().is_terminal() {
+        eprint!("Do you confirm you are authorized to test the targets of this session? [y/N] ");
+        std::io::stderr().flush()?;
+        if !prompt::read_answer().await?.is_some_and(|answer| prompt::is_yes(&answer)) {
+            anyhow::bail!("Authorization not confirmed; aborting.");
+        }
+        "interactive prompt"
+    } else {
+        anyhow::bail!("Authorization required: pass --i-am-authorized or set safety.require_authorization = false for automation.");
+    };
+
+    if let Err(e) = audit.record(&format!("authorization acknowledged via {}", method)) {
+        eprintln!("WARN: Could not record acknowledgment in {}: {}", audit.path().display(), e);
+    }
+    Ok(())
+}
@@ -1,11 +1,186 @@
 // src/network.rs
 use anyhow::{anyhow, Context, Result};
+use ipnet::Ipv4Net;
 use std::process::Command;
+use once_cell::sync::Lazy;
 use regex::Regex; // Add regex crate to Cargo.toml
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+
+// Fixed patterns, compiled once on first use (also used to parse gateway steps in core)
+// ipconfig: "Default Gateway . . . : 192.168.1.1"
+pub(crate) static WINDOWS_GATEWAY_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"Default Gateway.*: ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)").expect("Invalid gateway regex"));
+// ip route: "default via 192.168.1.1 dev eth0"
+pub(crate) static LINUX_GATEWAY_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"default via ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)").expect("Invalid gateway regex"));
+// route print: "          0.0.0.0          0.0.0.0      192.168.1.1    192.168.1.23     25"
+pub(crate) static WINDOWS_ROUTE_PRINT_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?m)^\s*0\.0\.0\.0\s+0\.0\.0\.0\s+([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)\s").expect("Invalid route print regex")
+});
+// route -n get default: "gateway: 192.168.1.1"
+pub(crate) static MACOS_GATEWAY_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"gateway: ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)").expect("Invalid gateway regex"));
+
+// IPv6 default routes; link-local gateways get their zone from the interface (dev / Idx)
+// ip -6 route: "default via fe80::1 dev eth0 proto ra metric 100"
+static LINUX_GATEWAY6_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"default via ([0-9A-Fa-f:]+(?:%\S+)?)(?:\s+dev\s+(\S+))?").expect("Invalid IPv6 gateway regex"));
+// route -n get -inet6 default: "gateway: fe80::1%en0"
+static MACOS_GATEWAY6_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"gateway: ([0-9A-Fa-f:]+(?:%\S+)?)").expect("Invalid IPv6 gateway regex"));
+// netsh interface ipv6 show route: "No  Manual  256  ::/0  12  fe80::1"
+static NETSH_GATEWAY6_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?m)\s::/0\s+(\d+)\s+([0-9A-Fa-f:]+(?:%\S+)?)\s*$").expect("Invalid IPv6 gateway regex"));
+
+// IPv4 address / CIDR shapes in free text (validated separately)
+pub(crate) static CIDR_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b((?:[0-9]{1,3}\.){3}[0-9]{1,3}/\d{1,2})\b").expect("Invalid CIDR regex"));
+pub(crate) static IPV4_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b((?:[0-9]{1,3}\.){3}[0-9]{1,3})\b").expect("Invalid IP regex"));
+
+// --- IPv4 validation helpers ---
+// The regexes only check the dotted shape, so octets like 999 still need rejecting
+pub fn is_valid_ipv4(candidate: &str) -> bool {
+    Ipv4Addr::from_str(candidate).is_ok()
+}
+
+// Validates "a.b.c.d/nn" with a well-formed address and a prefix of 0-32 (plain digits, no
+// leading zero, like the address octets)
+pub fn is_valid_cidr(candidate: &str) -> bool {
+    match candidate.split_once('/') {
+        Some((addr, prefix)) => {
+            let plain = !prefix.is_empty() && prefix.bytes().all(|b| b.is_ascii_digit()) && (prefix == "0" || !prefix.starts_with('0'));
+            is_valid_ipv4(addr) && plain && prefix.parse::<u8>().map(|p| p <= 32).unwrap_or(false)
+        }
+        None => false,
+    }
+}
+
+// --- CIDR size guard ---
+// Every feature that expands or scans a CIDR checks it here against scope.max_cidr_hosts
+// (None = no limit), so an accidental /8 is refused in one place with one message.
+pub fn check_cidr_size(cidr: &str, max_hosts: Option<u64>) -> Result<(), String> {
+    let (Some(max), Ok(net)) = (max_hosts, cidr.parse::<Ipv4Net>()) else {
+        return Ok(());
+    };
+    let count = cidr_host_count(&net);
+    if count > max {
+        return Err(format!(
+            "CIDR {} expands to {} hosts, exceeding max of {}; narrow the range or raise max_cidr_hosts",
+            cidr, count, max
+        ));
+    }
+    Ok(())
+}
+
+// The first CIDR in free text (e.g. a command line) that is over the limit
+pub fn oversized_cidr(text: &str, max_hosts: Option<u64>) -> Option<String> {
+    max_hosts?;
+    CIDR_RE.find_iter(text).find_map(|m| check_cidr_size(m.as_str(), max_hosts).err())
+}
+
+// Usable hosts in a network, matching Ipv4Net::hosts() (no network/broadcast below /31)
+pub fn cidr_host_count(net: &Ipv4Net) -> u64 {
+    let size = 1u64 << (32 - net.prefix_len());
+    if net.prefix_len() < 31 { size - 2 } else { size }
+}
 
 // Existing http client function (if any) can remain
 // pub fn create_http_client() -> reqwest::Client { ... }
 
+// Gateway from Windows ipconfig output, else from the 0.0.0.0 default route in `route print`
+// output (ipconfig's "Default Gateway" line is blank on some adapters/configurations)
+pub(crate) fn parse_windows_gateway(output: &str) -> Option<String> {
+    let usable = |ip: &str| ip != "0.0.0.0" && is_valid_ipv4(ip);
+    output
+        .lines()
+        .filter_map(|line| WINDOWS_GATEWAY_RE.captures(line).and_then(|cap| cap.get(1)))
+        .map(|m| m.as_str())
+        .find(|ip| usable(ip))
+        .or_else(|| {
+            WINDOWS_ROUTE_PRINT_RE
+                .captures_iter(output)
+                .filter_map(|cap| cap.get(1))
+                .map(|m| m.as_str())
+                .find(|ip| usable(ip))
+        })
+        .map(str::to_string)
+}
+
+// --- IPv6 gateway helpers ---
+// "fe80::1" + zone "eth0" -> "fe80::1%eth0"; only link-local addresses need (and get) a zone.
+// Returns None unless the address part is a real, routable-as-gateway IPv6 address.
+fn ipv6_gateway(address: &str, zone: Option<&str>) -> Option<String> {
+    let (addr, existing_zone) = match address.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone)),
+        None => (address, None),
+    };
+    let ip = addr.parse::<Ipv6Addr>().ok().filter(|ip| !ip.is_unspecified())?;
+    let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+    match existing_zone.or(zone) {
+        Some(zone) if link_local && !zone.is_empty() => Some(format!("{}%{}", ip, zone)),
+        _ => Some(ip.to_string()),
+    }
+}
+
+// IPv6 default gateway from `ip -6 route`, `route -n get -inet6 default`, `netsh interface ipv6
+// show route` or ipconfig output (whose Default Gateway entry may span several lines)
+pub(crate) fn parse_gateway6(output: &str) -> Option<String> {
+    if let Some(gateway) = LINUX_GATEWAY6_RE
+        .captures_iter(output)
+        .find_map(|cap| ipv6_gateway(cap.get(1)?.as_str(), cap.get(2).map(|m| m.as_str())))
+    {
+        return Some(gateway);
+    }
+    if let Some(gateway) = NETSH_GATEWAY6_RE
+        .captures_iter(output)
+        .find_map(|cap| ipv6_gateway(cap.get(2)?.as_str(), cap.get(1).map(|m| m.as_str())))
+    {
+        return Some(gateway);
+    }
+    if let Some(gateway) = MACOS_GATEWAY6_RE.captures_iter(output).find_map(|cap| ipv6_gateway(cap.get(1)?.as_str(), None)) {
+        return Some(gateway);
+    }
+    // ipconfig: the value after "Default Gateway ... :", then indented continuation lines
+    let mut lines = output.lines();
+    while let Some(line) = lines.by_ref().find(|line| line.contains("Default Gateway")) {
+        let first = line.split_once(": ").map(|(_, value)| value.trim()).unwrap_or_default();
+        let continuation = lines.clone().take_while(|l| !l.trim().is_empty() && !l.contains(" : ")).map(str::trim);
+        if let Some(gateway) = std::iter::once(first).chain(continuation).find_map(|value| ipv6_gateway(value, None)) {
+            return Some(gateway);
+        }
+    }
+    None
+}
+
+// Like get_default_gateway, for the IPv6 default route
+pub fn get_default_gateway_v6() -> Result<Option<String>> {
+    println!("Attempting to find IPv6 default gateway...");
+    let (program, args): (&str, &[&str]) = if cfg!(windows) {
+        ("netsh", &["interface", "ipv6", "show", "route"])
+    } else if cfg!(target_os = "macos") {
+        ("route", &["-n", "get", "-inet6", "default"])
+    } else {
+        ("ip", &["-6", "route", "show", "default"])
+    };
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .context(format!("Failed to execute '{} {}'", program, args.join(" ")))?;
+    if !output.status.success() {
+        println!("'{} {}' failed or no IPv6 default route found.", program, args.join(" "));
+        return Ok(None);
+    }
+    let gateway = parse_gateway6(&String::from_utf8_lossy(&output.stdout));
+    match &gateway {
+        Some(ip) => println!("Found IPv6 default gateway: {}", ip),
+        None => println!("IPv6 default gateway not found in '{}' output.", program),
+    }
+    Ok(gateway)
+}
+
 // NEW function to find default gateway
 // Returns Ok(Some(ip_string)) or Ok(None) if not found, or Err on execution/parse failure
 pub fn get_default_gateway() -> Result<Option<String>> {
@@ -23,23 +198,24 @@ pub fn get_default_gateway() -> Result<Option<String>> {
         }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
-        // Regex to find IPv4 Default Gateway line and capture the IP
-        // Looks for "Default Gateway", then optional whitespace/dots, then ":", then IP
-        let re = Regex::new(r"Default Gateway.*: ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)")
-                       .expect("Invalid regex"); // Expect should be safe for this pattern
-
         // Find the first match which is likely the primary gateway
-        if let Some(cap) = re.captures(&stdout) {
-            if let Some(ip_match) = cap.get(1) {
-                let ip = ip_match.as_str().to_string();
-                // Basic validation it's not 0.0.0.0 if that appears sometimes
-                 if ip != "0.0.0.0" {
-                    println!("Found default gateway (Windows): {}", ip);
-                    return Ok(Some(ip));
-                 }
+        if let Some(ip) = parse_windows_gateway(&stdout) {
+            println!("Found default gateway (Windows): {}", ip);
+            return Ok(Some(ip));
+        }
+        println!("Default gateway not found in ipconfig output, trying 'route print'.");
+
+        let output = Command::new("route")
+            .args(["print", "-4"])
+            .output()
+            .context("Failed to execute 'route print'")?;
+        if output.status.success() {
+            if let Some(ip) = parse_windows_gateway(&String::from_utf8_lossy(&output.stdout)) {
+                println!("Found default gateway (Windows, route print): {}", ip);
+                return Ok(Some(ip));
             }
         }
-        println!("Default gateway not found in ipconfig output.");
+        println!("Default gateway not found in route print output.");
         Ok(None)
     }
     #[cfg(unix)] // Primarily targeting Linux here
@@ -57,15 +233,13 @@ pub fn get_default_gateway() -> Result<Option<String>> {
          }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
-         // Regex to find the line starting with "default via" and capture the IP
-         let re = Regex::new(r"default via ([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)")
-                        .expect("Invalid regex");
-
-        if let Some(cap) = re.captures(&stdout) {
+        if let Some(cap) = LINUX_GATEWAY_RE.captures(&stdout) {
             if let Some(ip_match) = cap.get(1) {
                 let ip = ip_match.as_str().to_string();
-                println!("Found default gateway (Linux): {}", ip);
-                return Ok(Some(ip));
+                if is_valid_ipv4(&ip) {
+                    println!("Found default gateway (Linux): {}", ip);
+                    return Ok(Some(ip));
+                }
             }
         }
         println!("Default gateway not found in 'ip route' output.");
@@ -76,4 +250,106 @@ pub fn get_default_gateway() -> Result<Option<String>> {
          println!("Default gateway discovery not supported on this platform.");
          Ok(None)
      }
-}
\ No newline at end of file
+}
+// --- Target lists (--targets-file) ---
+// One IPv4 address, CIDR or hostname per line; blank lines and `#` comments are ignored.
+// Hostnames must resolve (they are kept as names, tools resolve them again); bad or unresolvable
+// entries are reported and left out. Duplicates are dropped, order is kept.
+pub fn load_targets_file(path: &Path, max_cidr_hosts: Option<u64>) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path).context(format!("Failed to read targets file: {}", path.display()))?;
+    let mut targets: Vec<String> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let valid = if entry.contains('/') {
+            if is_valid_cidr(entry) {
+                check_cidr_size(entry, max_cidr_hosts).map_err(|e| anyhow!("{} line {}: {}", path.display(), number + 1, e))?;
+            }
+            is_valid_cidr(entry)
+        } else if is_valid_ipv4(entry) || entry.parse::<IpAddr>().is_ok() {
+            true
+        } else if entry.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-') {
+            if !(entry, 0).to_socket_addrs().map(|mut addrs| addrs.next().is_some()).unwrap_or(false) {
+                println!("WARN: {} line {}: hostname '{}' does not resolve, skipping.", path.display(), number + 1, entry);
+                continue;
+            }
+            true
+        } else {
+            false
+        };
+        if !valid {
+            println!("WARN: {} line {}: '{}' is not an IP, CIDR or hostname, skipping.", path.display(), number + 1, entry);
+            continue;
+        }
+        if !targets.iter().any(|t| t == entry) {
+            targets.push(entry.to_string());
+        }
+    }
+    if targets.is_empty() {
+        return Err(anyhow!("Targets file {} has no usable targets", path.display()));
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_addresses() {
+        for valid in ["0.0.0.0", "10.0.0.1", "192.168.1.254", "255.255.255.255"] {
+            assert!(is_valid_ipv4(valid), "{} should be valid", valid);
+        }
+        for invalid in ["256.1.1.1", "10.0.0.256", "10.0.0", "10.0.0.1.2", "010.0.0.1", "10.00.0.1", "10.0.0.-1", "", "a.b.c.d"] {
+            assert!(!is_valid_ipv4(invalid), "{} should be invalid", invalid);
+        }
+    }
+
+    // ipconfig with an empty "Default Gateway", followed by `route print`
+    const IPCONFIG_AND_ROUTE_PRINT: &str = "\
+Ethernet adapter Ethernet0:
+
+   IPv4 Address. . . . . . . . . . . : 192.168.56.10
+   Subnet Mask . . . . . . . . . . . : 255.255.255.0
+   Default Gateway . . . . . . . . . :
+
+===========================================================================
+IPv4 Route Table
+===========================================================================
+Active Routes:
+Network Destination        Netmask          Gateway       Interface  Metric
+          0.0.0.0          0.0.0.0         On-link        10.8.0.6     25
+          0.0.0.0          0.0.0.0     192.168.56.1    192.168.56.10     25
+        127.0.0.0        255.0.0.0         On-link         127.0.0.1    331
+     192.168.56.0    255.255.255.0         On-link     192.168.56.10    281
+===========================================================================
+Persistent Routes:
+  None
+";
+
+    #[test]
+    fn windows_gateway_from_route_print() {
+        let gateways: Vec<&str> = WINDOWS_ROUTE_PRINT_RE.captures_iter(IPCONFIG_AND_ROUTE_PRINT).map(|cap| cap.get(1).unwrap().as_str()).collect();
+        assert_eq!(gateways, vec!["192.168.56.1"]);
+        assert_eq!(parse_windows_gateway(IPCONFIG_AND_ROUTE_PRINT).as_deref(), Some("192.168.56.1"));
+    }
+
+    #[test]
+    fn windows_gateway_prefers_ipconfig() {
+        let output = format!("   Default Gateway . . . . . . . . . : 10.0.0.1\n{}", IPCONFIG_AND_ROUTE_PRINT);
+        assert_eq!(parse_windows_gateway(&output).as_deref(), Some("10.0.0.1"));
+        assert_eq!(parse_windows_gateway("Persistent Routes:\n  None\n"), None);
+    }
+
+    #[test]
+    fn cidr_prefixes() {
+        for valid in ["0.0.0.0/0", "10.0.0.0/8", "192.168.1.0/24", "10.0.0.5/32"] {
+            assert!(is_valid_cidr(valid), "{} should be valid", valid);
+        }
+        for invalid in ["10.0.0.0/33", "10.0.0.0/", "10.0.0.0", "256.0.0.0/8", "010.0.0.0/8", "10.0.0.0/08", "10.0.0.0/+8", "10.0.0.0/-1", "10.0.0.0/24/8"] {
+            assert!(!is_valid_cidr(invalid), "{} should be invalid", invalid);
+        }
+    }
+}
@@ -7,21 +7,88 @@ use ollama_rs::{
     },
     Ollama,
 };
+use crate::config::AppConfig;
+use ollama_rs::error::OllamaError;
 use crate::setup::SystemSetup; // Keep for OS info
 // Add imports for file reading and paths
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_stream::StreamExt;
 
 // Define the prompt filename as a constant
 const SYSTEM_PROMPT_FILENAME: &str = "system_prompt.txt";
+// Plans degrade once the system prompt + history no longer fit; warn below this context length
+const MIN_CONTEXT_LENGTH: u32 = 4096;
+// Room left for the query, history and the JSON plan on top of the system prompt
+const CONTEXT_HEADROOM_TOKENS: u32 = 2048;
+// What Ollama uses when neither the Modelfile nor the request sets num_ctx
+const OLLAMA_DEFAULT_NUM_CTX: u32 = 2048;
+
+// Plans are streamed so an answer in prose can be cut off early: the first non-whitespace
+// character of a JSON plan is one of these (`{`, `[`, or the ``` of a fenced block). Leading
+// whitespace is skipped, so a model that is slow to start its JSON isn't cut off.
+const PLAN_START_CHARS: &[char] = &['{', '[', '`'];
+// Added to the system prompt when asking again after an off-format answer was cancelled
+const JSON_ONLY_REMINDER: &str =
+    "\n\nIMPORTANT: Answer with only the JSON object described above. No prose, explanations or markdown outside it.";
+
+// Readiness polling after an install: first retry delay, doubled up to this cap
+const READY_POLL_START: Duration = Duration::from_millis(500);
+const READY_POLL_MAX: Duration = Duration::from_secs(5);
+// How long a fresh install gets to start answering before we give up
+pub const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// ChatML markers around the stock system prompt; --prompt-prepend/--prompt-append go inside them
+const CHATML_SYSTEM_START: &str = "<|im_start|>system\n";
+const CHATML_END: &str = "<|im_end|>";
+
+// Go template that passes the prompt through untouched (raw_prompt mode)
+const RAW_TEMPLATE: &str = "{{ .Prompt }}";
+
+// --- ContextWindow ---
+#[derive(Clone, Debug)]
+pub struct ContextWindow {
+    pub num_ctx: u32,
+    // false when num_ctx is Ollama's built-in default rather than a model parameter
+    pub from_model: bool,
+    // Estimated tokens the system prompt plus a typical query/plan need
+    pub needed: u32,
+    // Modelfile PARAMETER lines (name, value), e.g. ("temperature", "0.6")
+    pub parameters: Vec<(String, String)>,
+}
+
+impl ContextWindow {
+    pub fn is_sufficient(&self) -> bool {
+        self.num_ctx >= self.needed
+    }
+
+    pub fn describe(&self) -> String {
+        let source = if self.from_model { "model parameter" } else { "Ollama default" };
+        format!("num_ctx {} ({}), about {} needed", self.num_ctx, source, self.needed)
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct OllamaClient {
     client: Ollama,
     model: String,
     host: String,
-    // Add field to store the path to the config directory
-    config_dir: PathBuf,
+    // System prompt template; defaults to <config_dir>/system_prompt.txt
+    system_prompt_path: PathBuf,
+    // Template given directly in the config; used instead of the file when set
+    inline_system_prompt: Option<String>,
+    // Print the fully assembled system/user prompts to stderr before each request
+    debug_prompt: bool,
+    // Wrap user prompts in ChatML (<|im_start|>...) tags; from advanced.qwen_formatting
+    chatml: bool,
+    // Send the system prompt + user prompt verbatim, bypassing the model's Modelfile template
+    raw_prompt: bool,
+    // Per-run text around the system prompt template (--prompt-prepend / --prompt-append)
+    prompt_prepend: Option<String>,
+    prompt_append: Option<String>,
+    // Longest a single generation request may take (timeouts.gen_timeout_secs); None waits forever
+    gen_timeout: Option<Duration>,
 }
 
 impl OllamaClient {
@@ -32,7 +99,72 @@ impl OllamaClient {
             client: ollama_client,
             model: model.to_string(),
             host: host.to_string(),
-            config_dir, // Store the config directory path
+            system_prompt_path: config_dir.join(SYSTEM_PROMPT_FILENAME),
+            inline_system_prompt: None,
+            debug_prompt: false,
+            chatml: true,
+            raw_prompt: false,
+            prompt_prepend: None,
+            prompt_append: None,
+            gen_timeout: Some(Duration::from_secs(crate::config::DEFAULT_GEN_TIMEOUT_SECS)),
+        }
+    }
+
+    // Switch models (e.g. after picking one from list_models)
+    pub fn set_model(&mut self, model: &str) {
+        self.model = model.to_string();
+    }
+
+    // Point the client at a different Ollama host, e.g. typed in at the model picker. The scheme
+    // is required and Ollama's default port 11434 applies only when the URL names none.
+    pub fn set_host(&mut self, host: &str) -> Result<()> {
+        let mut url = reqwest::Url::parse(host.trim()).context(format!("'{}' is not a URL like http://10.0.0.2:11434", host.trim()))?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err(anyhow!("'{}' is not an http(s) URL with a host", host.trim()));
+        }
+        if url.port().is_none() {
+            url.set_port(Some(11434)).map_err(|_| anyhow!("Cannot set a port on '{}'", host.trim()))?;
+        }
+        self.client = Ollama::from_url(url);
+        self.host = host.trim().to_string();
+        Ok(())
+    }
+
+    pub fn set_debug_prompt(&mut self, enabled: bool) {
+        self.debug_prompt = enabled;
+    }
+
+    // Text added before/after whichever system prompt template is in use; {OS} applies to it too
+    pub fn set_prompt_additions(&mut self, prepend: Option<String>, append: Option<String>) {
+        self.prompt_prepend = prepend.filter(|p| !p.trim().is_empty());
+        self.prompt_append = append.filter(|p| !p.trim().is_empty());
+    }
+
+    // Use a different system prompt file (e.g. from a --profile)
+    pub fn set_system_prompt_path(&mut self, path: PathBuf) {
+        self.system_prompt_path = path;
+    }
+
+    // Convenience constructor using the configured host (or the local default) and model
+    pub fn from_config(config: &AppConfig, config_dir: PathBuf) -> Self {
+        let host = config.ollama_host.as_deref().unwrap_or("http://localhost:11434");
+        let mut client = OllamaClient::new(host, &config.model.name, config_dir);
+        client.chatml = config.advanced.as_ref().and_then(|a| a.qwen_formatting).unwrap_or(true);
+        client.raw_prompt = config.advanced.as_ref().and_then(|a| a.raw_prompt).unwrap_or(false);
+        client.inline_system_prompt = config.system_prompt.clone().filter(|p| !p.trim().is_empty());
+        client.gen_timeout = config.gen_timeout();
+        if let Some(path) = &config.system_prompt_path {
+            client.system_prompt_path = PathBuf::from(shellexpand::tilde(path).as_ref());
+        }
+        client
+    }
+
+    // Wraps a user message in the configured prompt format (ChatML when qwen_formatting is on)
+    pub fn format_prompt(&self, user_message: &str) -> String {
+        if self.chatml {
+            format!("<|im_start|>user\n{}\n<|im_end|>\n<|im_start|>assistant\n", user_message)
+        } else {
+            user_message.to_string()
         }
     }
 
@@ -42,43 +174,286 @@ impl OllamaClient {
         context: Option<GenerationContext>,
         system_setup: &SystemSetup, // Still needed for OS info
     ) -> Result<(String, Option<GenerationContext>)> {
-        // --- Load System Prompt from File ---
-        let system_prompt_path = self.config_dir.join(SYSTEM_PROMPT_FILENAME);
-        let system_prompt_template = fs::read_to_string(&system_prompt_path).context(format!(
-            "Failed to read system prompt file at: {}",
-            system_prompt_path.display()
-        ))?;
-        // --- End Load System Prompt ---
+        let system_prompt_template = self.system_prompt_template()?;
 
         // Inject OS into the loaded prompt template
         let os_string = system_setup.platform.to_string();
         let system_prompt = system_prompt_template.replace("{OS}", &os_string);
 
+        if self.debug_prompt {
+            eprintln!("===== DEBUG PROMPT: system =====\n{}\n===== DEBUG PROMPT: user =====\n{}\n===== END DEBUG PROMPT =====", system_prompt, prompt);
+        }
+
         // Build the request using the loaded system prompt
-        let mut request = GenerationRequest::new(self.model.clone(), prompt.to_string())
-            .system(system_prompt) // Use loaded and formatted prompt
-            .keep_alive(KeepAlive::Until {
-                time: 5,
-                unit: TimeUnit::Minutes,
-            })
-            .format(FormatType::Json);
+        let build_request = |system_prompt: String| {
+            let request = if self.raw_prompt {
+                // ollama-rs has no `raw` flag; a bare "{{ .Prompt }}" template has the same effect
+                // (no server-side templating), so the system prompt is prepended by hand
+                GenerationRequest::new(self.model.clone(), format!("{}\n\n{}", system_prompt, prompt))
+                    .template(RAW_TEMPLATE)
+            } else {
+                GenerationRequest::new(self.model.clone(), prompt.to_string())
+                    .system(system_prompt) // Use loaded and formatted prompt
+            };
+            let mut request = request
+                .keep_alive(KeepAlive::Until {
+                    time: 5,
+                    unit: TimeUnit::Minutes,
+                })
+                .format(FormatType::Json);
+            if let Some(ctx) = context.clone() {
+                request = request.context(ctx);
+            }
+            request
+        };
+
+        // One repair attempt when the answer starts out as prose; that one runs to the end so
+        // whatever it produces reaches the plan parser (and its error messages). gen_timeout covers
+        // both attempts together.
+        let deadline = self.gen_timeout.map(|limit| tokio::time::Instant::now() + limit);
+        let (response, new_context) = match self.stream_plan(build_request(system_prompt.clone()), true, deadline).await? {
+            Some(streamed) => streamed,
+            None => {
+                println!(
+                    "WARN: Model '{}' started answering in prose instead of a JSON plan; cancelled it and asking again for JSON only.",
+                    self.model
+                );
+                let reminded = format!("{}{}", system_prompt, JSON_ONLY_REMINDER);
+                self.stream_plan(build_request(reminded), false, deadline).await?.unwrap_or_default()
+            }
+        };
+
+        Ok((response.trim().to_string(), new_context))
+    }
 
-        if let Some(ctx) = context {
-            request = request.context(ctx);
+    // --- Streamed plan generation ---
+    // Collects a streamed answer and its context, failing once `deadline` passes. With
+    // `check_format`, returns None as soon as the answer is clearly not JSON (see PLAN_START_CHARS);
+    // dropping the stream closes the connection, which stops Ollama generating the rest.
+    async fn stream_plan(
+        &self,
+        request: GenerationRequest<'_>,
+        check_format: bool,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<Option<(String, Option<GenerationContext>)>> {
+        let collect = async {
+            let mut stream = self.client.generate_stream(request).await.map_err(|e| self.describe_error(&e))?;
+            let mut text = String::new();
+            let mut checked = !check_format;
+            while let Some(chunk) = stream.next().await {
+                for response in chunk.map_err(|e| self.describe_error(&e))? {
+                    text.push_str(&response.response);
+                    if response.done {
+                        return Ok(Some((text, response.context)));
+                    }
+                }
+                if let Some(first) = text.chars().find(|c| !c.is_whitespace()).filter(|_| !checked) {
+                    checked = true;
+                    if !PLAN_START_CHARS.contains(&first) {
+                        return Ok(None);
+                    }
+                }
+            }
+            Ok(Some((text, None)))
+        };
+        match (deadline, self.gen_timeout) {
+            (Some(deadline), Some(limit)) => tokio::time::timeout_at(deadline, collect).await.map_err(|_| self.timeout_error(limit))?,
+            _ => collect.await,
         }
+    }
 
-        let response: GenerationResponse = self.client.generate(request).await.map_err(|e| {
+    // --- Free-text generation ---
+    // A plain prose answer: no plan system prompt, no JSON format and no conversation context
+    pub async fn generate_text(&self, prompt: &str) -> Result<String> {
+        if self.debug_prompt {
+            eprintln!("===== DEBUG PROMPT: free text =====\n{}\n===== END DEBUG PROMPT =====", prompt);
+        }
+        let request = GenerationRequest::new(self.model.clone(), self.format_prompt(prompt)).keep_alive(KeepAlive::Until {
+            time: 5,
+            unit: TimeUnit::Minutes,
+        });
+        let response = self.send(request).await?;
+        Ok(response.response.trim().to_string())
+    }
+
+    // Sends a generation request, giving up after gen_timeout with an error that says so
+    async fn send(&self, request: GenerationRequest<'_>) -> Result<GenerationResponse> {
+        let generation = self.client.generate(request);
+        let result = match self.gen_timeout {
+            Some(limit) => tokio::time::timeout(limit, generation).await.map_err(|_| self.timeout_error(limit))?,
+            None => generation.await,
+        };
+        result.map_err(|e| self.describe_error(&e))
+    }
+
+    fn timeout_error(&self, limit: Duration) -> anyhow::Error {
+        anyhow!(
+            "Model '{}' did not respond within {}s. Raise timeouts.gen_timeout_secs (0 = no limit) or use a smaller/faster model.",
+            self.model,
+            limit.as_secs()
+        )
+    }
+
+    // --- Unload ---
+    // An empty generation with keep_alive 0 makes Ollama drop the model from (V)RAM right away
+    pub async fn unload(&self) -> Result<()> {
+        let request = GenerationRequest::new(self.model.clone(), String::new())
+            .keep_alive(KeepAlive::UnloadOnCompletion);
+        self.client
+            .generate(request)
+            .await
+            .map_err(|e| self.describe_error(&e))?;
+        Ok(())
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn system_prompt_path(&self) -> &Path {
+        &self.system_prompt_path
+    }
+
+    // --- System prompt source ---
+    // Precedence: inline `system_prompt` > config `system_prompt_path` > --profile file > default file
+    // (with any --prompt-prepend/--prompt-append text added)
+    pub fn system_prompt_template(&self) -> Result<String> {
+        let template = match &self.inline_system_prompt {
+            Some(inline) => inline.clone(),
+            None => fs::read_to_string(&self.system_prompt_path).context(format!(
+                "Failed to read system prompt file at: {}",
+                self.system_prompt_path.display()
+            ))?,
+        };
+        Ok(self.with_prompt_additions(template))
+    }
+
+    // Keeps the additions inside a ChatML system block if the template has one
+    fn with_prompt_additions(&self, template: String) -> String {
+        let mut prompt = template;
+        if let Some(prepend) = &self.prompt_prepend {
+            prompt = match prompt.strip_prefix(CHATML_SYSTEM_START) {
+                Some(body) => format!("{}{}\n\n{}", CHATML_SYSTEM_START, prepend.trim(), body.trim_start()),
+                None => format!("{}\n\n{}", prepend.trim(), prompt),
+            };
+        }
+        if let Some(append) = &self.prompt_append {
+            let body = prompt.trim_end();
+            prompt = match body.strip_suffix(CHATML_END) {
+                Some(body) => format!("{}\n\n{}\n{}", body.trim_end(), append.trim(), CHATML_END),
+                None => format!("{}\n\n{}", body, append.trim()),
+            };
+        }
+        prompt
+    }
+
+    // Where the system prompt comes from, for diagnostics
+    pub fn system_prompt_origin(&self) -> String {
+        match &self.inline_system_prompt {
+            Some(_) => "inline system_prompt in config".to_string(),
+            None => self.system_prompt_path.display().to_string(),
+        }
+    }
+
+    // --- Readiness ---
+    // A freshly installed service can take a while to accept requests. Polls the tags endpoint
+    // (list_models) with exponential backoff until it answers or `limit` has passed.
+    pub async fn wait_until_ready(&self, limit: Duration) -> Result<()> {
+        let started = std::time::Instant::now();
+        let mut delay = READY_POLL_START;
+        loop {
+            let error = match self.list_models().await {
+                Ok(_) => return Ok(()),
+                Err(e) => e,
+            };
+            let elapsed = started.elapsed();
+            if elapsed >= limit {
+                return Err(anyhow!(
+                    "Ollama at {} did not become ready within {}s after installation ({:#}). Check that the service is running (`systemctl status ollama`, or start `ollama serve`) and try again.",
+                    self.host,
+                    limit.as_secs(),
+                    error
+                ));
+            }
+            println!("INFO: Waiting for Ollama at {} to come up...", self.host);
+            tokio::time::sleep(delay.min(limit - elapsed)).await;
+            delay = (delay * 2).min(READY_POLL_MAX);
+        }
+    }
+
+    // Names (name:tag) of the models installed on the Ollama host
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        let models = self.client.list_local_models().await.map_err(|e| self.describe_error(&e))?;
+        Ok(models.into_iter().map(|m| m.name).collect())
+    }
+
+    // --- Context window check ---
+    // Effective num_ctx (the model's Modelfile parameters, else Ollama's default) against what the
+    // system prompt needs, estimated at ~4 bytes per token
+    pub async fn context_window(&self) -> Result<ContextWindow> {
+        let info = self
+            .client
+            .show_model_info(self.model.clone())
+            .await
+            .map_err(|e| self.describe_error(&e))?;
+        let parameters: Vec<(String, String)> = info
+            .parameters
+            .lines()
+            .filter_map(|line| {
+                let (key, value) = line.trim().split_once(char::is_whitespace)?;
+                Some((key.to_string(), value.trim().trim_matches('"').to_string()))
+            })
+            .collect();
+        let model_num_ctx = parameters.iter().find(|(key, _)| key == "num_ctx").and_then(|(_, v)| v.parse().ok());
+        let prompt_tokens = self.system_prompt_template().map(|p| (p.len() / 4) as u32).unwrap_or(0);
+        Ok(ContextWindow {
+            num_ctx: model_num_ctx.unwrap_or(OLLAMA_DEFAULT_NUM_CTX),
+            from_model: model_num_ctx.is_some(),
+            needed: MIN_CONTEXT_LENGTH.max(prompt_tokens + CONTEXT_HEADROOM_TOKENS),
+            parameters,
+        })
+    }
+
+    // --- Error mapping ---
+    // Turns Ollama's raw error bodies into actionable messages, especially model load failures
+    fn describe_error(&self, e: &OllamaError) -> anyhow::Error {
+        // OllamaError::Other's Display is just "Error in Ollama"; the useful text is inside
+        let detail = match e {
+            OllamaError::Other(body) => serde_json::from_str::<serde_json::Value>(body)
+                .ok()
+                .and_then(|v| v.get("error").and_then(|m| m.as_str()).map(str::to_string))
+                .unwrap_or_else(|| body.clone()),
+            OllamaError::InternalError(inner) => inner.message.clone(),
+            OllamaError::ReqwestError(inner) => format!("{}: {}", e, inner),
+            other => other.to_string(),
+        };
+        let lower = detail.to_lowercase();
+
+        if lower.contains("requires more system memory") {
+            anyhow!(
+                "Model '{}' failed to load: not enough memory ({}). Try a smaller or more quantized model, close other models, or add RAM/VRAM.",
+                self.model, detail
+            )
+        } else if lower.contains("out of memory") || lower.contains("cuda error") || lower.contains("failed to load model")
+            || lower.contains("llama runner process has terminated") || lower.contains("unable to allocate")
+        {
+            anyhow!(
+                "Model '{}' failed to load ({}) - likely out of memory; try a smaller model or increase VRAM.",
+                self.model, detail
+            )
+        } else if lower.contains("not found") && lower.contains("model") {
+            anyhow!("Model '{}' is not installed on {}. Run `ollama pull {}` first.", self.model, self.host, self.model)
+        } else {
             anyhow!(
                 "Ollama API error: {}. Verify model '{}' exists and API at {} is reachable",
-                e,
+                detail,
                 self.model,
                 self.host
             )
-        })?;
-
-        let cleaned_response = response.response.trim().to_string();
-        let new_context = response.context;
-
-        Ok((cleaned_response, new_context))
+        }
     }
-}
\ No newline at end of file
+}
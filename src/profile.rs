@@ -0,0 +1,65 @@
+// src/profile.rs
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+const PROFILES_DIRNAME: &str = "profiles";
+const PROFILE_CONFIG_FILENAME: &str = "config.toml";
+const PROFILE_PROMPT_FILENAME: &str = "system_prompt.txt";
+
+// --- Profile ---
+// A named posture under <config_dir>/profiles/<name>/:
+//   config.toml        overlay (model, [safety] allowlist, [scope] exclusions, ...)
+//   system_prompt.txt  replaces the default system prompt
+// Both files are optional; the directory itself must exist.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl Profile {
+    pub fn load(config_dir: &Path, name: &str) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(anyhow!("Invalid profile name '{}'", name));
+        }
+        let dir = profiles_dir(config_dir).join(name);
+        if !dir.is_dir() {
+            return Err(anyhow!(
+                "Profile '{}' not found (expected directory {}). Run `hacker-rs profiles` to list them.",
+                name,
+                dir.display()
+            ));
+        }
+        Ok(Profile { name: name.to_string(), dir })
+    }
+
+    pub fn config_path(&self) -> Option<PathBuf> {
+        Some(self.dir.join(PROFILE_CONFIG_FILENAME)).filter(|p| p.exists())
+    }
+
+    pub fn system_prompt_path(&self) -> Option<PathBuf> {
+        Some(self.dir.join(PROFILE_PROMPT_FILENAME)).filter(|p| p.exists())
+    }
+
+    // Names of all profile directories, sorted
+    pub fn list(config_dir: &Path) -> Vec<Profile> {
+        let mut profiles: Vec<Profile> = std::fs::read_dir(profiles_dir(config_dir))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().is_dir())
+                    .filter_map(|entry| {
+                        let name = entry.file_name().to_str()?.to_string();
+                        Some(Profile { name, dir: entry.path() })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        profiles
+    }
+}
+
+pub fn profiles_dir(config_dir: &Path) -> PathBuf {
+    config_dir.join(PROFILES_DIRNAME)
+}
@@ -0,0 +1,67 @@
+// src/prompt.rs
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+// --- Terminal prompts ---
+// Every interactive gate (authorization, plan confirmation, pause, model picker) reads its answer
+// here so --input-timeout covers all of them. When the time runs out there is no answer, and each
+// gate takes its safe choice (no / abort).
+
+static INPUT_TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+// A read that outlived its prompt's timeout. The next prompt takes its line instead of starting a
+// second reader on the same stdin.
+static PENDING_READ: Mutex<Option<oneshot::Receiver<std::io::Result<String>>>> = Mutex::new(None);
+
+pub fn set_input_timeout(limit: Option<Duration>) {
+    *INPUT_TIMEOUT.lock().unwrap_or_else(|e| e.into_inner()) = limit;
+}
+
+// One trimmed line from stdin (the question is already printed); None when --input-timeout
+// passed first. EOF reads as an empty answer.
+pub async fn read_answer() -> Result<Option<String>> {
+    let pending = PENDING_READ.lock().unwrap_or_else(|e| e.into_inner()).take();
+    // A plain thread, not spawn_blocking: a read nobody answers must not hold up runtime shutdown
+    let mut read = pending.unwrap_or_else(|| {
+        let (sender, receiver) = oneshot::channel();
+        std::thread::spawn(move || {
+            let mut line = String::new();
+            let _ = sender.send(std::io::stdin().read_line(&mut line).map(|_| line));
+        });
+        receiver
+    });
+    let limit = *INPUT_TIMEOUT.lock().unwrap_or_else(|e| e.into_inner());
+    let received = match limit {
+        Some(limit) => match tokio::time::timeout(limit, &mut read).await {
+            Ok(received) => received,
+            Err(_) => {
+                *PENDING_READ.lock().unwrap_or_else(|e| e.into_inner()) = Some(read);
+                eprintln!("\nINFO: No answer within {}s (--input-timeout); taking the safe choice.", limit.as_secs());
+                return Ok(None);
+            }
+        },
+        None => read.await,
+    };
+    let line = received.context("Prompt reader stopped")?.context("Failed to read from stdin")?;
+    Ok(Some(line.trim().to_string()))
+}
+
+// Prints the question on stdout and reads the answer; empty when it timed out
+pub async fn ask(question: &str) -> Result<String> {
+    print!("{}", question);
+    std::io::stdout().flush().context("Failed to flush stdout")?;
+    Ok(read_answer().await?.unwrap_or_default())
+}
+
+// A [y/N] question: only y/yes says yes, so a timeout is a no
+pub async fn confirm(question: &str) -> Result<bool> {
+    let answer = ask(question).await?;
+    Ok(is_yes(&answer))
+}
+
+pub fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
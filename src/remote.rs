@@ -0,0 +1,63 @@
+// src/remote.rs
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use tokio::process::Command as TokioCommand;
+
+// --- RemoteTarget ---
+// A pivot host that plan steps run on, reached through the system `ssh` client
+// (so ~/.ssh/config, agents and ProxyJump all keep working).
+#[derive(Debug, Clone)]
+pub struct RemoteTarget {
+    pub destination: String, // user@host or an ssh_config alias
+    pub identity_file: Option<PathBuf>,
+    pub port: Option<u16>,
+}
+
+impl RemoteTarget {
+    pub fn new(destination: &str, identity_file: Option<PathBuf>, port: Option<u16>) -> Self {
+        RemoteTarget { destination: destination.to_string(), identity_file, port }
+    }
+
+    // Common ssh arguments; BatchMode stops ssh from hanging on a password prompt
+    fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+        if let Some(key) = &self.identity_file {
+            args.push("-i".to_string());
+            args.push(key.display().to_string());
+        }
+        if let Some(port) = self.port {
+            args.push("-p".to_string());
+            args.push(port.to_string());
+        }
+        args.push(self.destination.clone());
+        args.push("--".to_string());
+        args
+    }
+
+    // Async command that runs `remote_command` through the remote user's shell
+    pub fn command(&self, remote_command: &str) -> TokioCommand {
+        let mut cmd = TokioCommand::new("ssh");
+        cmd.args(self.ssh_args()).arg(remote_command);
+        cmd
+    }
+
+    // Blocking helper for short probes (setup/tool checks); returns stdout on success
+    pub fn probe(&self, remote_command: &str) -> Result<String> {
+        let output = Command::new("ssh")
+            .args(self.ssh_args())
+            .arg(remote_command)
+            .stdin(Stdio::null())
+            .output()
+            .context("Failed to run ssh - is the OpenSSH client installed?")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "ssh {} failed ({}): {}",
+                self.destination,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
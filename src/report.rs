@@ -0,0 +1,218 @@
+// src/report.rs
+use crate::core::StepStatus;
+use crate::network::{CIDR_RE, IPV4_RE};
+use ipnet::Ipv4Net;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+// Dotted names that can stand for a target host (checked before pseudonymizing)
+static HOSTNAME_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?(?:\.[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?)+$").expect("Invalid hostname regex")
+});
+static UNDERSCORED_IP_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[0-9]{1,3}_[0-9]{1,3}_[0-9]{1,3}_[0-9]{1,3}").expect("Invalid underscored IP regex"));
+
+// --- RunReport ---
+// Machine-readable result of one query (`run --format json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub query: String,
+    // Same text the human output prints (explanation plus step outputs, or the error)
+    pub summary: String,
+    // Facts found by pre-parsing, extractors and step hints; sorted for stable output
+    pub discovered_values: BTreeMap<String, String>,
+    pub steps: Vec<StepReport>,
+    #[serde(default)]
+    pub stats: RunStats,
+    // Pseudonym -> real identity, only in --anonymize reports that keep the legend
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub anonymization_legend: BTreeMap<String, String>,
+}
+
+// --- StepReport ---
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepReport {
+    pub step: u32,
+    // What was performed (the final command for command steps)
+    pub command: String,
+    pub status: StepStatus,
+    pub output: String,
+    // Per-target results when the step ran once for each of several RHOST targets
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub targets: BTreeMap<String, StepResult>,
+}
+
+// --- RunStats ---
+// Step outcome counts and timing of one query. `executed` counts steps whose command actually ran
+// (failed and aborted ones included); `skipped` is every planned step that neither ran nor was blocked.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunStats {
+    pub total_steps: usize,
+    pub executed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub blocked: usize,
+    pub wall_time_secs: f64,
+    pub model_time_secs: f64,
+}
+
+impl RunStats {
+    // One-line footer for the text output
+    pub fn to_text(&self) -> String {
+        format!(
+            "Stats: {} steps ({} executed, {} skipped, {} failed, {} blocked) | {:.1}s total, {:.1}s in the model",
+            self.total_steps, self.executed, self.skipped, self.failed, self.blocked, self.wall_time_secs, self.model_time_secs
+        )
+    }
+}
+
+// --- StepResult ---
+// Outcome of one target of a per-target step; a failed target doesn't stop the others
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepResult {
+    pub success: bool,
+    pub output: String,
+}
+
+// --- Value diff ---
+// Human-readable changes between two runs' discovered values (used by run --watch)
+pub fn value_changes(previous: &BTreeMap<String, String>, current: &BTreeMap<String, String>) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (key, value) in current {
+        match previous.get(key) {
+            None => changes.push(format!("NEW     {}: {}", key, value)),
+            Some(old) if old != value => changes.push(format!("CHANGED {}: {} -> {}", key, old, value)),
+            Some(_) => {}
+        }
+    }
+    for (key, old) in previous {
+        if !current.contains_key(key) {
+            changes.push(format!("GONE    {}: {}", key, old));
+        }
+    }
+    changes
+}
+
+// --- Anonymizer ---
+// Replaces target identities (IPs, CIDRs, hostnames) with stable pseudonyms (HOST_1, NET_1) so a
+// report can be shared. Every IPv4/CIDR in the report is covered, plus hostnames known from the
+// discovered values and scope; the same address always maps to the same pseudonym.
+pub struct Anonymizer {
+    // real -> pseudonym
+    pseudonyms: BTreeMap<String, String>,
+    hostname_re: Option<Regex>,
+}
+
+impl Anonymizer {
+    pub fn for_report(report: &RunReport, known_hosts: &[String]) -> Self {
+        let mut texts: Vec<&str> = vec![&report.query, &report.summary];
+        texts.extend(report.discovered_values.values().map(String::as_str));
+        texts.extend(known_hosts.iter().map(String::as_str));
+        for step in &report.steps {
+            texts.push(&step.command);
+            texts.push(&step.output);
+            texts.extend(step.targets.iter().flat_map(|(target, result)| [target.as_str(), result.output.as_str()]));
+        }
+
+        let mut nets: Vec<Ipv4Net> = Vec::new();
+        let mut ips: Vec<Ipv4Addr> = Vec::new();
+        for text in &texts {
+            nets.extend(CIDR_RE.find_iter(text).filter_map(|m| m.as_str().parse::<Ipv4Net>().ok()));
+            // The address part of a CIDR is not a host of its own
+            ips.extend(
+                IPV4_RE
+                    .find_iter(text)
+                    .filter(|m| !text[m.end()..].starts_with('/'))
+                    .filter_map(|m| m.as_str().parse::<Ipv4Addr>().ok()),
+            );
+        }
+        // Hostnames only come from places that name a target, not from arbitrary output text
+        let mut hostnames: Vec<String> = report
+            .discovered_values
+            .iter()
+            .filter(|(key, _)| ["host", "domain", "target"].iter().any(|k| key.contains(k)))
+            .flat_map(|(_, value)| value.split(|c: char| c == ',' || c.is_whitespace()))
+            .chain(known_hosts.iter().map(String::as_str))
+            .filter(|token| HOSTNAME_RE.is_match(token) && token.parse::<Ipv4Addr>().is_err())
+            .map(|token| token.to_lowercase())
+            .collect();
+        nets.sort();
+        nets.dedup();
+        ips.sort();
+        ips.dedup();
+        hostnames.sort();
+        hostnames.dedup();
+
+        let mut pseudonyms = BTreeMap::new();
+        for (i, net) in nets.iter().enumerate() {
+            pseudonyms.insert(net.to_string(), format!("NET_{}", i + 1));
+        }
+        for (i, host) in ips.iter().map(|ip| ip.to_string()).chain(hostnames.iter().cloned()).enumerate() {
+            pseudonyms.insert(host, format!("HOST_{}", i + 1));
+        }
+
+        // Longest first so "db.corp.example" wins over "corp.example"
+        hostnames.sort_by_key(|h| std::cmp::Reverse(h.len()));
+        let hostname_re = (!hostnames.is_empty()).then(|| {
+            let alternatives = hostnames.iter().map(|h| regex::escape(h)).collect::<Vec<_>>().join("|");
+            Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives)).expect("Escaped hostnames form a valid regex")
+        });
+        Anonymizer { pseudonyms, hostname_re }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let lookup = |real: &str| self.pseudonyms.get(real).cloned();
+        let text = CIDR_RE.replace_all(text, |c: &regex::Captures| lookup(&c[0]).unwrap_or_else(|| c[0].to_string()));
+        let text = IPV4_RE.replace_all(&text, |c: &regex::Captures| lookup(&c[0]).unwrap_or_else(|| c[0].to_string()));
+        // Extractor keys spell addresses with underscores, e.g. host_10_0_0_5_ports
+        let text = UNDERSCORED_IP_RE.replace_all(&text, |c: &regex::Captures| {
+            lookup(&c[0].replace('_', ".")).unwrap_or_else(|| c[0].to_string())
+        });
+        match &self.hostname_re {
+            Some(re) => re
+                .replace_all(&text, |c: &regex::Captures| lookup(&c[0].to_lowercase()).unwrap_or_else(|| c[0].to_string()))
+                .into_owned(),
+            None => text.into_owned(),
+        }
+    }
+
+    // pseudonym -> real, for the private legend
+    pub fn legend(&self) -> BTreeMap<String, String> {
+        self.pseudonyms.iter().map(|(real, pseudonym)| (pseudonym.clone(), real.clone())).collect()
+    }
+
+    pub fn legend_text(&self) -> String {
+        let lines: Vec<String> = self.legend().iter().map(|(p, real)| format!("  {} = {}", p, real)).collect();
+        format!("Anonymization legend (do not share):\n{}", lines.join("\n"))
+    }
+
+    pub fn apply_report(&self, report: &RunReport, include_legend: bool) -> RunReport {
+        RunReport {
+            query: self.apply(&report.query),
+            summary: self.apply(&report.summary),
+            discovered_values: report.discovered_values.iter().map(|(k, v)| (self.apply(k), self.apply(v))).collect(),
+            steps: report
+                .steps
+                .iter()
+                .map(|step| StepReport {
+                    step: step.step,
+                    command: self.apply(&step.command),
+                    status: step.status,
+                    output: self.apply(&step.output),
+                    targets: step
+                        .targets
+                        .iter()
+                        .map(|(target, result)| {
+                            (self.apply(target), StepResult { success: result.success, output: self.apply(&result.output) })
+                        })
+                        .collect(),
+                })
+                .collect(),
+            stats: report.stats.clone(),
+            anonymization_legend: if include_legend { self.legend() } else { BTreeMap::new() },
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// src/scope.rs
+use anyhow::{anyhow, Result};
+use ipnet::Ipv4Net;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+// IPv4 addresses and CIDRs anywhere in a command
+static HOST_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b((?:[0-9]{1,3}\.){3}[0-9]{1,3}(?:/\d{1,2})?)\b").expect("Invalid host regex"));
+// nmap/ssh style -p lists, --port(s) values, and host:port forms
+static PORT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?:(?:^|\s)-p\s*|--ports?[ =]|:)(-|\d[\d,\-]*)").expect("Invalid port regex"));
+// DNS names (dotted, last label not numeric) anywhere in a command
+static NAME_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b((?:[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?\.)+[A-Za-z][A-Za-z0-9-]*[A-Za-z0-9])\b").expect("Invalid name regex"));
+
+// --- ScopeRules ---
+// Rules-of-engagement exclusions checked before every command runs
+#[derive(Debug, Clone, Default)]
+pub struct ScopeRules {
+    pub excluded_hosts: Vec<Ipv4Net>,
+    pub excluded_ports: Vec<u16>,
+}
+
+impl ScopeRules {
+    // Builds rules from the raw --exclude / --exclude-ports values
+    pub fn from_lists(hosts: &[String], ports: &[String]) -> Result<Self> {
+        let excluded_hosts = hosts
+            .iter()
+            .map(|h| parse_net(h.trim()).ok_or_else(|| anyhow!("Invalid excluded host/CIDR: '{}'", h)))
+            .collect::<Result<Vec<_>>>()?;
+        let excluded_ports = ports
+            .iter()
+            .map(|p| p.trim().parse::<u16>().map_err(|_| anyhow!("Invalid excluded port: '{}'", p)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ScopeRules { excluded_hosts, excluded_ports })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.excluded_hosts.is_empty() && self.excluded_ports.is_empty()
+    }
+
+    // Returns the block reason if the command touches an excluded host or port
+    pub fn check_command(&self, command: &str) -> Option<&'static str> {
+        if self.is_empty() {
+            return None;
+        }
+
+        for m in HOST_RE.find_iter(command) {
+            let Some(target) = parse_net(m.as_str()) else { continue };
+            // A scanned range that merely contains an excluded host is still off-limits
+            if self.excluded_hosts.iter().any(|ex| ex.contains(&target) || target.contains(ex)) {
+                return Some("excluded host");
+            }
+        }
+
+        for cap in PORT_RE.captures_iter(command) {
+            let spec = &cap[1];
+            if self.excluded_ports.is_empty() {
+                break;
+            }
+            // "-p-" means every port
+            if spec == "-" || self.excluded_ports.iter().any(|p| port_spec_contains(spec, *p)) {
+                return Some("excluded port");
+            }
+        }
+        None
+    }
+
+    // Resolves host names and checks their IPv4 addresses, so an excluded host can't be reached by
+    // name. Names that don't resolve pass; the literal check above still sees any IP in the command.
+    pub fn check_names<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Option<&'static str> {
+        if self.excluded_hosts.is_empty() {
+            return None;
+        }
+        let excluded = |addr: &SocketAddr| match addr {
+            SocketAddr::V4(v4) => self.excluded_hosts.iter().any(|ex| ex.contains(v4.ip())),
+            SocketAddr::V6(_) => false,
+        };
+        names
+            .into_iter()
+            .any(|name| (name, 0).to_socket_addrs().is_ok_and(|mut addrs| addrs.any(|addr| excluded(&addr))))
+            .then_some("excluded host")
+    }
+
+    // nmap's own `--exclude-ports` value, so a scan without -p skips the excluded ports too
+    pub fn nmap_exclude_ports(&self) -> Option<String> {
+        (!self.excluded_ports.is_empty())
+            .then(|| self.excluded_ports.iter().map(u16::to_string).collect::<Vec<_>>().join(","))
+    }
+}
+
+// DNS names in a command, in order of appearance
+pub fn host_names(command: &str) -> impl Iterator<Item = &str> {
+    NAME_RE.find_iter(command).map(|m| m.as_str())
+}
+
+// Parses "a.b.c.d" or "a.b.c.d/nn" into a network (single hosts become /32)
+fn parse_net(value: &str) -> Option<Ipv4Net> {
+    if value.contains('/') {
+        Ipv4Net::from_str(value).ok().map(|n| n.trunc())
+    } else {
+        Ipv4Addr::from_str(value).ok().map(Ipv4Net::from)
+    }
+}
+
+// Checks a comma-separated list of ports/ranges ("22,80-90") for a port
+fn port_spec_contains(spec: &str, port: u16) -> bool {
+    spec.split(',').any(|part| match part.split_once('-') {
+        Some((lo, hi)) => {
+            let lo = lo.parse::<u16>().unwrap_or(0);
+            let hi = hi.parse::<u16>().unwrap_or(u16::MAX);
+            (lo..=hi).contains(&port)
+        }
+        None => part.parse::<u16>().map(|p| p == port).unwrap_or(false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(hosts: &[&str], ports: &[&str]) -> ScopeRules {
+        let owned = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        ScopeRules::from_lists(&owned(hosts), &owned(ports)).unwrap()
+    }
+
+    #[test]
+    fn host_names_skips_addresses() {
+        let names: Vec<&str> = host_names("curl http://intranet.corp/login -o page.html && ping 10.0.0.5").collect();
+        assert_eq!(names, vec!["intranet.corp", "page.html"]);
+    }
+
+    #[test]
+    fn check_names_resolves_to_excluded_hosts() {
+        let scope = rules(&["127.0.0.0/8"], &[]);
+        assert_eq!(scope.check_names(["localhost"]), Some("excluded host"));
+        assert_eq!(rules(&["10.9.9.9"], &[]).check_names(["localhost"]), None);
+    }
+
+    #[test]
+    fn nmap_exclude_ports_lists_every_port() {
+        assert_eq!(rules(&[], &["3389", "22"]).nmap_exclude_ports().as_deref(), Some("3389,22"));
+        assert_eq!(rules(&["10.0.0.1"], &[]).nmap_exclude_ports(), None);
+    }
+}
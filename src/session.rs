@@ -0,0 +1,61 @@
+// src/session.rs
+use crate::core::HistoryEntry;
+use anyhow::{anyhow, Context, Result};
+use ollama_rs::generation::completion::GenerationContext;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const SESSIONS_DIRNAME: &str = "sessions";
+
+// --- SessionState ---
+// What carries over between `--session` runs. The model context is only valid for the model
+// that produced it, so the model name is stored alongside it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionState {
+    pub model: String,
+    #[serde(default)]
+    pub model_context: Option<GenerationContext>,
+    #[serde(default)]
+    pub command_history: Vec<HistoryEntry>,
+    #[serde(default)]
+    pub discovered_values: BTreeMap<String, String>,
+}
+
+// --- Session ---
+// A named state file at <config_dir>/sessions/<name>.json
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Session {
+    pub fn open(config_dir: &Path, name: &str) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(anyhow!("Invalid session name '{}'", name));
+        }
+        let path = config_dir.join(SESSIONS_DIRNAME).join(format!("{}.json", name));
+        Ok(Session { name: name.to_string(), path })
+    }
+
+    // None for a new session
+    pub fn load(&self) -> Result<Option<SessionState>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let raw = std::fs::read_to_string(&self.path)
+            .context(format!("Failed to read session file: {}", self.path.display()))?;
+        let state = serde_json::from_str(&raw)
+            .context(format!("Session file {} is corrupt; delete it to start over", self.path.display()))?;
+        Ok(Some(state))
+    }
+
+    pub fn save(&self, state: &SessionState) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).context(format!("Failed to create sessions directory: {}", dir.display()))?;
+        }
+        let json = serde_json::to_string(state).context("Failed to serialize session")?;
+        std::fs::write(&self.path, json).context(format!("Failed to write session file: {}", self.path.display()))
+    }
+}
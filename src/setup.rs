@@ -1,11 +1,17 @@
 // src/setup.rs
+use crate::remote::RemoteTarget;
 use anyhow::{anyhow, Context, Result};
 use directories_next::UserDirs;
 use os_info::Type;
 use std::fmt; // Import fmt for Display trait
+use sha2::{Digest, Sha256};
 use sysinfo::System;
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::process::Command;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use which::which;
 
 // Derive Clone, Debug, and add Display
@@ -33,6 +39,26 @@ pub struct SystemSetup {
     // Make platform public
     pub platform: Platform, // <-- Changed to pub
     is_admin: bool,         // Keep is_admin private for now
+    remote: Option<RemoteTarget>, // When set, platform/tools describe the remote host
+    installer_checksums: InstallerChecksums,
+    // Tools confirmed present (or installed) this run, so each is only probed once; never persisted
+    verified_tools: RwLock<HashSet<String>>,
+}
+
+// Tool presence checks run at most this many at once during preflight
+const PREFLIGHT_CONCURRENCY: usize = 4;
+
+// Pinned SHA-256 digests (lowercase hex) for the Ollama installers
+#[derive(Clone, Debug, Default)]
+pub struct InstallerChecksums {
+    pub script_sha256: Option<String>,
+    pub installer_sha256: Option<String>,
+}
+
+impl Default for SystemSetup {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SystemSetup {
@@ -41,15 +67,77 @@ impl SystemSetup {
         let platform = detect_platform(&sys);
         let is_admin = is_elevated();
 
-        SystemSetup { platform, is_admin }
+        SystemSetup {
+            platform,
+            is_admin,
+            remote: None,
+            installer_checksums: InstallerChecksums::default(),
+            verified_tools: RwLock::new(HashSet::new()),
+        }
+    }
+
+    // Points setup at a remote host: platform and admin status are probed over ssh
+    pub fn with_remote(mut self, remote: RemoteTarget) -> Result<Self> {
+        println!("Probing remote host {} ...", remote.destination);
+        let platform = match remote.probe("cat /etc/os-release 2>/dev/null || uname -s") {
+            Ok(info) => {
+                let info = info.to_lowercase();
+                if info.contains("id=kali") {
+                    Platform::KaliLinux
+                } else if info.contains("id=") || info.contains("linux") {
+                    Platform::OtherLinux
+                } else {
+                    Platform::Unsupported
+                }
+            }
+            // No POSIX shell answered; an OpenSSH-on-Windows host runs cmd
+            Err(_) => match remote.probe("ver") {
+                Ok(info) if info.contains("Windows") => Platform::Windows,
+                Ok(_) => Platform::Unsupported,
+                Err(e) => return Err(e.context("Remote host is unreachable over ssh")),
+            },
+        };
+        self.is_admin = remote.probe("id -u").map(|uid| uid.trim() == "0").unwrap_or(false);
+        println!("Remote platform: {} (admin: {})", platform, self.is_admin);
+        self.platform = platform;
+        self.remote = Some(remote);
+        Ok(self)
+    }
+
+    pub fn remote(&self) -> Option<&RemoteTarget> {
+        self.remote.as_ref()
+    }
+
+    pub fn set_installer_checksums(&mut self, checksums: InstallerChecksums) {
+        self.installer_checksums = checksums;
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    // Package manager used for automatic tool installs on this platform, if any
+    pub fn package_manager(&self) -> Option<&'static str> {
+        match self.platform {
+            Platform::KaliLinux => Some("apt"),
+            Platform::Windows => Some("winget"),
+            _ => None,
+        }
     }
     // ... rest of SystemSetup impl remains the same ...
 
     async fn install_ollama_linux(&self) -> Result<()> {
-        let install_script = reqwest::get("https://ollama.ai/install.sh")
-            .await?
-            .text()
-            .await?;
+        let script_bytes = download(OLLAMA_INSTALL_SCRIPT_URL, SCRIPT_DOWNLOAD_TIMEOUT_SECS)
+            .await
+            .map_err(manual_install_hint)?;
+        verify_checksum("install.sh", &script_bytes, self.installer_checksums.script_sha256.as_deref())
+            .map_err(manual_install_hint)?;
+        let install_script = String::from_utf8(script_bytes)
+            .map_err(|_| manual_install_hint(anyhow!("install.sh is not valid UTF-8")))?;
+        // A truncated or error-page body must never reach `sh`
+        if !install_script.starts_with("#!") || !install_script.contains("ollama") {
+            return Err(manual_install_hint(anyhow!("Downloaded install.sh does not look like the Ollama installer")));
+        }
 
         let mut cmd = if self.is_admin {
             Command::new("sh")
@@ -69,18 +157,20 @@ impl SystemSetup {
         }
     }
 
-    pub async fn ensure_ollama(&self) -> Result<()> {
+    // Ok(true) when Ollama had to be installed just now (its API may not be up yet)
+    pub async fn ensure_ollama(&self) -> Result<bool> {
         if self.check_ollama_installed().await? {
-            return Ok(());
+            return Ok(false);
         }
 
         match self.platform {
-            Platform::KaliLinux | Platform::OtherLinux => self.install_ollama_linux().await,
-            Platform::Windows => self.install_ollama_windows().await,
-            _ => Err(anyhow!(
+            Platform::KaliLinux | Platform::OtherLinux => self.install_ollama_linux().await?,
+            Platform::Windows => self.install_ollama_windows().await?,
+            _ => return Err(anyhow!(
                 "Unsupported platform for automatic Ollama installation"
             )),
         }
+        Ok(true)
     }
 
     async fn enable_ollama_service(&self) -> Result<()> {
@@ -102,12 +192,12 @@ impl SystemSetup {
     }
 
     async fn check_ollama_installed(&self) -> Result<bool> {
-        let status = Command::new("ollama")
-            .arg("--version")
-            .status()
-            .map_err(|_| anyhow::anyhow!("Ollama not found"))?;
-
-        Ok(status.success())
+        // Not on PATH means "not installed" (so we can install it), not a hard error
+        match Command::new("ollama").arg("--version").status() {
+            Ok(status) => Ok(status.success()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(anyhow!("Failed to run ollama --version: {}", e)),
+        }
     }
 
     async fn install_ollama_windows(&self) -> Result<()> {
@@ -118,15 +208,30 @@ impl SystemSetup {
             .context("Failed to find downloads directory")?
             .join("OllamaSetup.exe");
 
-        let client = reqwest::Client::new();
-        let response = client
-            .get("https://ollama.com/download/OllamaSetup.exe")
-            .send()
-            .await?;
+        let content = download(OLLAMA_WINDOWS_INSTALLER_URL, INSTALLER_DOWNLOAD_TIMEOUT_SECS)
+            .await
+            .map_err(manual_install_hint)?;
+        if content.len() < MIN_INSTALLER_BYTES || !content.starts_with(b"MZ") {
+            return Err(manual_install_hint(anyhow!(
+                "Downloaded OllamaSetup.exe looks incomplete ({} bytes)",
+                content.len()
+            )));
+        }
+
+        verify_checksum("OllamaSetup.exe", &content, self.installer_checksums.installer_sha256.as_deref())
+            .map_err(manual_install_hint)?;
 
         let mut file = std::fs::File::create(&path)?;
-        let content = response.bytes().await?;
-        std::io::copy(&mut content.as_ref(), &mut file)?;
+        std::io::copy(&mut content.as_slice(), &mut file)?;
+        drop(file);
+
+        // Without a pinned digest, require a valid Authenticode signature from Ollama
+        if self.installer_checksums.installer_sha256.is_none() {
+            if let Err(e) = verify_authenticode(&path) {
+                let _ = std::fs::remove_file(&path);
+                return Err(manual_install_hint(e));
+            }
+        }
 
         let status = Command::new("cmd")
             .args(&["/C", "start", "/wait", path.to_str().unwrap()])
@@ -139,8 +244,78 @@ impl SystemSetup {
         }
     }
 
+    fn is_verified(&self, tool: &str) -> bool {
+        self.verified_tools.read().map(|set| set.contains(tool)).unwrap_or(false)
+    }
+
+    fn mark_verified(&self, tool: &str) {
+        if let Ok(mut set) = self.verified_tools.write() {
+            set.insert(tool.to_string());
+        }
+    }
+
+    // --- Plan preflight ---
+    // Checks every distinct tool of a plan up front, several at a time, so the per-step
+    // check_and_install_tool calls become cache hits. Installs stay sequential (apt/winget
+    // hold a global lock); failures are only warned about here and surface again at the step.
+    pub async fn preflight_tools(&self, tools: &[String]) {
+        let mut pending: Vec<String> = tools.iter().filter(|t| !self.is_verified(t)).cloned().collect();
+        pending.sort();
+        pending.dedup();
+        if pending.is_empty() {
+            return;
+        }
+        let semaphore = Arc::new(Semaphore::new(PREFLIGHT_CONCURRENCY));
+        let mut checks = JoinSet::new();
+        for tool in pending {
+            let semaphore = Arc::clone(&semaphore);
+            let remote = self.remote.clone();
+            let platform = self.platform.clone();
+            checks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let present = tokio::task::spawn_blocking({
+                    let tool = tool.clone();
+                    move || tool_present(&tool, remote.as_ref(), &platform)
+                })
+                .await
+                .unwrap_or(false);
+                (tool, present)
+            });
+        }
+
+        let mut missing = Vec::new();
+        while let Some(result) = checks.join_next().await {
+            match result {
+                Ok((tool, true)) => self.mark_verified(&tool),
+                Ok((tool, false)) => missing.push(tool),
+                Err(e) => println!("WARN: Tool preflight task failed: {}", e),
+            }
+        }
+
+        missing.sort();
+        for tool in missing {
+            if let Err(e) = self.check_and_install_tool(&tool).await {
+                println!("WARN: Preflight: {}", e);
+            }
+        }
+    }
+
     pub async fn check_and_install_tool(&self, tool: &str) -> Result<()> {
-        if which(tool).is_ok() {
+        if self.is_verified(tool) {
+            return Ok(());
+        }
+
+        // Remote hosts are only checked, never auto-installed onto
+        if let Some(remote) = &self.remote {
+            if tool_present(tool, Some(remote), &self.platform) {
+                self.mark_verified(tool);
+                return Ok(());
+            }
+            return Err(anyhow!("'{}' is not installed on remote host {}", tool, remote.destination));
+        }
+
+        if tool_present(tool, None, &self.platform) {
+            self.mark_verified(tool);
             return Ok(());
         }
 
@@ -150,7 +325,9 @@ impl SystemSetup {
             _ => Err(anyhow::anyhow!(
                 "Automatic installation not supported for this platform"
             )),
-        }
+        }?;
+        self.mark_verified(tool);
+        Ok(())
     }
 
     async fn apt_install(&self, package: &str) -> Result<()> {
@@ -190,6 +367,103 @@ impl SystemSetup {
 }
 
 
+// --- Tool presence check (blocking: `which` locally, `command -v`/`where` over ssh) ---
+fn tool_present(tool: &str, remote: Option<&RemoteTarget>, platform: &Platform) -> bool {
+    match remote {
+        Some(remote) => {
+            let lookup = match platform {
+                Platform::Windows => format!("where {}", tool),
+                _ => format!("command -v {}", tool),
+            };
+            remote.probe(&lookup).is_ok()
+        }
+        None => which(tool).is_ok(),
+    }
+}
+
+// --- Download helpers ---
+const OLLAMA_INSTALL_SCRIPT_URL: &str = "https://ollama.com/install.sh";
+const OLLAMA_WINDOWS_INSTALLER_URL: &str = "https://ollama.com/download/OllamaSetup.exe";
+const DOWNLOAD_CONNECT_TIMEOUT_SECS: u64 = 10;
+const SCRIPT_DOWNLOAD_TIMEOUT_SECS: u64 = 60;
+const INSTALLER_DOWNLOAD_TIMEOUT_SECS: u64 = 1800;
+const MIN_INSTALLER_BYTES: usize = 1024 * 1024;
+
+// Fetches a URL with connect/overall timeouts, requiring a 2xx status and a non-empty body
+async fn download(url: &str, timeout_secs: u64) -> Result<Vec<u8>> {
+    let client = reqwest::Client::builder()
+        .https_only(true)
+        .connect_timeout(std::time::Duration::from_secs(DOWNLOAD_CONNECT_TIMEOUT_SECS))
+        .timeout(std::time::Duration::from_secs(timeout_secs))
+        .build()
+        .context("Failed to build HTTP client")?;
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .context(format!("Failed to download {} (timed out or unreachable)", url))?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Download of {} failed with HTTP {}", url, response.status()));
+    }
+    let body = response
+        .bytes()
+        .await
+        .context(format!("Download of {} was interrupted", url))?;
+    if body.is_empty() {
+        return Err(anyhow!("Download of {} returned an empty body", url));
+    }
+    Ok(body.to_vec())
+}
+
+// Prints the SHA-256 of a download and enforces it when a pinned digest is configured
+fn verify_checksum(name: &str, content: &[u8], expected: Option<&str>) -> Result<()> {
+    let digest = Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    println!("{} SHA-256: {}", name, digest);
+    match expected {
+        Some(expected) if !expected.trim().eq_ignore_ascii_case(&digest) => Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}. The download may have been tampered with.",
+            name,
+            expected.trim(),
+            digest
+        )),
+        Some(_) => {
+            println!("{} checksum verified against pinned value.", name);
+            Ok(())
+        }
+        None => {
+            println!("WARN: No pinned checksum for {} (set it under [setup] in the config to enforce one).", name);
+            Ok(())
+        }
+    }
+}
+
+// Asks PowerShell whether the file carries a valid signature from Ollama
+fn verify_authenticode(path: &std::path::Path) -> Result<()> {
+    let script = format!(
+        "$s = Get-AuthenticodeSignature -LiteralPath '{}'; \"$($s.Status)|$($s.SignerCertificate.Subject)\"",
+        path.display()
+    );
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()
+        .context("Failed to run PowerShell to check the installer signature")?;
+    let result = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let (status, subject) = result.split_once('|').unwrap_or((result.as_str(), ""));
+    if status == "Valid" && subject.to_lowercase().contains("ollama") {
+        println!("OllamaSetup.exe signature verified ({}).", subject);
+        Ok(())
+    } else {
+        Err(anyhow!("OllamaSetup.exe signature check failed (status: {}, signer: {})", status, subject))
+    }
+}
+
+fn manual_install_hint(e: anyhow::Error) -> anyhow::Error {
+    e.context("Automatic Ollama installation failed; install it manually from https://ollama.com/download")
+}
+
 // --- detect_platform function (no changes) ---
 fn detect_platform(_sys: &System) -> Platform {
     let info = os_info::get();